@@ -0,0 +1,174 @@
+use anyhow::{bail, Context, Result};
+use image::Rgba;
+use std::path::Path;
+
+/// A 3D color lookup table loaded from an Adobe/Resolve `.cube` file.
+pub struct CubeLut {
+    pub title: Option<String>,
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// `size`³ output colors, red varying fastest, then green, then blue.
+    table: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read LUT: {:?}", path))?;
+        Self::parse(&text).with_context(|| format!("Invalid LUT: {:?}", path))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            match keyword {
+                "TITLE" => {
+                    let rest = line["TITLE".len()..].trim();
+                    title = Some(rest.trim_matches('"').to_string());
+                }
+                "LUT_3D_SIZE" => {
+                    let n: usize = words
+                        .next()
+                        .context("LUT_3D_SIZE without a value")?
+                        .parse()
+                        .context("LUT_3D_SIZE is not a number")?;
+                    if !(2..=256).contains(&n) {
+                        bail!("LUT_3D_SIZE {} is out of range", n);
+                    }
+                    size = Some(n);
+                }
+                "LUT_1D_SIZE" => bail!("1D LUTs are not supported"),
+                "DOMAIN_MIN" => domain_min = parse_triple(words, line_no)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(words, line_no)?,
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    table.push(parse_triple(line.split_whitespace(), line_no)?);
+                }
+                // Vendor extensions (e.g. LUT_IN_VIDEO_RANGE) don't affect the data.
+                _ => log::warn!("Ignoring unknown LUT keyword {}", keyword),
+            }
+        }
+
+        let size = size.context("Missing LUT_3D_SIZE")?;
+        if table.len() != size * size * size {
+            bail!(
+                "Expected {} entries for LUT_3D_SIZE {}, found {}",
+                size * size * size,
+                size,
+                table.len()
+            );
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            bail!("DOMAIN_MAX must be greater than DOMAIN_MIN");
+        }
+
+        Ok(Self {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[r + g * self.size + b * self.size * self.size]
+    }
+
+    /// Maps one pixel through the table with trilinear interpolation. Alpha
+    /// passes through unchanged.
+    pub fn apply(&self, pixel: Rgba<u8>) -> Rgba<u8> {
+        let max_index = (self.size - 1) as f32;
+        let mut base = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+        for c in 0..3 {
+            let v = pixel[c] as f32 / 255.0;
+            let t = ((v - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]))
+                .clamp(0.0, 1.0)
+                * max_index;
+            base[c] = (t.floor() as usize).min(self.size - 2);
+            frac[c] = t - base[c] as f32;
+        }
+
+        let mut out = [0.0f32; 3];
+        for corner in 0..8 {
+            let (dr, dg, db) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = [dr, dg, db]
+                .iter()
+                .zip(frac)
+                .map(|(&d, f)| if d == 1 { f } else { 1.0 - f })
+                .product::<f32>();
+            if weight == 0.0 {
+                continue;
+            }
+            let value = self.entry(base[0] + dr, base[1] + dg, base[2] + db);
+            for c in 0..3 {
+                out[c] += value[c] * weight;
+            }
+        }
+
+        Rgba([
+            (out[0] * 255.0).round().clamp(0.0, 255.0) as u8,
+            (out[1] * 255.0).round().clamp(0.0, 255.0) as u8,
+            (out[2] * 255.0).round().clamp(0.0, 255.0) as u8,
+            pixel[3],
+        ])
+    }
+}
+
+/// Shifts brightness and scales contrast around mid-gray. Both amounts run
+/// from -1.0 to 1.0, with 0.0 leaving the pixel unchanged. Alpha is kept.
+pub fn brightness_contrast(pixel: Rgba<u8>, brightness: f32, contrast: f32) -> Rgba<u8> {
+    let brightness = brightness.clamp(-1.0, 1.0) * 255.0;
+    let contrast = contrast.clamp(-1.0, 1.0);
+    // Maps -1..1 onto a 0..∞ gain with 0 -> 1.
+    let gain = if contrast >= 0.0 {
+        1.0 / (1.0 - contrast * 0.99)
+    } else {
+        1.0 + contrast
+    };
+    let mut out = pixel;
+    for c in 0..3 {
+        let v = (pixel[c] as f32 - 127.5) * gain + 127.5 + brightness;
+        out[c] = v.round().clamp(0.0, 255.0) as u8;
+    }
+    out
+}
+
+/// Unsharp mask: pushes `pixel` away from its `blurred` neighborhood by
+/// `amount` (0.0 leaves it unchanged). Alpha is kept.
+pub fn unsharp(pixel: Rgba<u8>, blurred: Rgba<u8>, amount: f32) -> Rgba<u8> {
+    let mut out = pixel;
+    for c in 0..3 {
+        let v = pixel[c] as f32 + (pixel[c] as f32 - blurred[c] as f32) * amount;
+        out[c] = v.round().clamp(0.0, 255.0) as u8;
+    }
+    out
+}
+
+fn parse_triple<'a>(mut words: impl Iterator<Item = &'a str>, line_no: usize) -> Result<[f32; 3]> {
+    let mut out = [0.0; 3];
+    for value in &mut out {
+        *value = words
+            .next()
+            .with_context(|| format!("Line {}: expected three values", line_no + 1))?
+            .parse()
+            .with_context(|| format!("Line {}: not a number", line_no + 1))?;
+    }
+    Ok(out)
+}
@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use std::borrow::Cow;
+
+/// The OS clipboard. Opened on first use and then kept for the life of the
+/// app, since some platforms drop copied data along with the handle.
+#[derive(Default)]
+pub struct Clipboard {
+    inner: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    fn get(&mut self) -> Result<&mut arboard::Clipboard> {
+        if self.inner.is_none() {
+            self.inner = Some(arboard::Clipboard::new().context("Clipboard unavailable")?);
+        }
+        Ok(self.inner.as_mut().unwrap())
+    }
+
+    /// Puts `image` on the clipboard.
+    pub fn copy_image(&mut self, image: &RgbaImage) -> Result<()> {
+        let data = arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: Cow::Borrowed(image.as_raw()),
+        };
+        self.get()?
+            .set_image(data)
+            .context("Failed to copy image to the clipboard")
+    }
+
+    /// The image on the clipboard, or `None` if it doesn't hold one.
+    pub fn paste_image(&mut self) -> Result<Option<RgbaImage>> {
+        match self.get()?.get_image() {
+            Ok(data) => Ok(RgbaImage::from_raw(
+                data.width as u32,
+                data.height as u32,
+                data.bytes.into_owned(),
+            )),
+            Err(arboard::Error::ContentNotAvailable) => Ok(None),
+            Err(e) => Err(e).context("Failed to read the clipboard"),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use image::Rgba;
+
+/// Converts 8-bit RGB to HSV with hue in degrees `[0, 360)` and saturation
+/// and value in `[0, 1]`.
+pub fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+    (h, s, max)
+}
+
+/// Inverse of [`rgb_to_hsv`].
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (u8, u8, u8) {
+    let h = h.rem_euclid(360.0);
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let to_u8 = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
+/// Formats `color` as `#RRGGBB`, or `#RRGGBBAA` when it is not opaque.
+pub fn to_hex(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+    if a == 255 {
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+    }
+}
+
+/// Parses `#RRGGBB` or `#RRGGBBAA`, with or without the `#`. Six digits
+/// give an opaque color.
+pub fn parse_hex(text: &str) -> Option<Rgba<u8>> {
+    let digits = text.trim().trim_start_matches('#');
+    if !matches!(digits.len(), 6 | 8) || !digits.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok();
+    let alpha = if digits.len() == 8 { channel(6)? } else { 255 };
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+/// Gives `target` the hue and saturation of `source` while keeping its own
+/// value, so shading and line detail survive recoloring. Alpha is untouched.
+pub fn with_hue_saturation_of(target: Rgba<u8>, source: Rgba<u8>) -> Rgba<u8> {
+    let (h, s, _) = rgb_to_hsv(source[0], source[1], source[2]);
+    let (_, _, v) = rgb_to_hsv(target[0], target[1], target[2]);
+    let (r, g, b) = hsv_to_rgb(h, s, v);
+    Rgba([r, g, b, target[3]])
+}
+
+/// Gives `target` the value of `source` while keeping its own hue and
+/// saturation. Alpha is untouched.
+pub fn with_value_of(target: Rgba<u8>, source: Rgba<u8>) -> Rgba<u8> {
+    let (h, s, _) = rgb_to_hsv(target[0], target[1], target[2]);
+    let (_, _, v) = rgb_to_hsv(source[0], source[1], source[2]);
+    let (r, g, b) = hsv_to_rgb(h, s, v);
+    Rgba([r, g, b, target[3]])
+}
+
+/// Linear blend of the color channels from `a` to `b` by `t` in `[0, 1]`,
+/// keeping the alpha of `a`.
+pub fn mix_rgb(a: Rgba<u8>, b: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    let lerp = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * t).round() as u8;
+    Rgba([lerp(a[0], b[0]), lerp(a[1], b[1]), lerp(a[2], b[2]), a[3]])
+}
+
+/// Composites `src` over `dst` with straight (unpremultiplied) alpha, so a
+/// translucent stroke glazes the color beneath instead of replacing it.
+pub fn over(dst: Rgba<u8>, src: Rgba<u8>) -> Rgba<u8> {
+    let sa = src[3] as f32 / 255.0;
+    if sa >= 1.0 {
+        return src;
+    }
+    let da = dst[3] as f32 / 255.0;
+    let out_a = sa + da * (1.0 - sa);
+    if out_a <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let mut out = [0u8; 4];
+    for c in 0..3 {
+        let v = (src[c] as f32 * sa + dst[c] as f32 * da * (1.0 - sa)) / out_a;
+        out[c] = v.round().clamp(0.0, 255.0) as u8;
+    }
+    out[3] = (out_a * 255.0).round() as u8;
+    Rgba(out)
+}
+
+/// `color` with its channels multiplied by its alpha, in the same gamma-encoded
+/// space [`over`] blends in.
+pub fn premultiply(color: Rgba<u8>) -> Rgba<u8> {
+    let a = color[3] as u32;
+    let scale = |c: u8| ((c as u32 * a + 127) / 255) as u8;
+    Rgba([scale(color[0]), scale(color[1]), scale(color[2]), color[3]])
+}
+
+/// `color` with its alpha scaled by `coverage` out of 255, for painting
+/// through a partially selected pixel.
+pub fn fade(color: Rgba<u8>, coverage: u8) -> Rgba<u8> {
+    let mut out = color;
+    out[3] = ((color[3] as u32 * coverage as u32 + 127) / 255) as u8;
+    out
+}
@@ -0,0 +1,152 @@
+use crate::color::{hsv_to_rgb, parse_hex, rgb_to_hsv, to_hex};
+use egui::{Color32, Mesh, Pos2, Sense, Shape, Stroke, Ui, Vec2};
+use image::Rgba;
+
+/// Cells per side of the saturation/value square's gradient mesh.
+const SV_STEPS: usize = 16;
+
+/// Hue, saturation and value sliders, a saturation/value square and a hex
+/// field, all editing one color.
+///
+/// The picker keeps its own HSV: gray has no hue and black no saturation,
+/// so deriving them from RGB every frame would snap the sliders back to
+/// zero mid-drag.
+pub struct ColorPicker {
+    /// Hue in degrees `[0, 360]`, saturation and value in `[0, 1]`.
+    hsv: (f32, f32, f32),
+    /// The color `hsv` and `hex` were last synced with.
+    synced: Option<Rgba<u8>>,
+    /// Hex text as typed, applied whenever it parses.
+    hex: String,
+}
+
+impl ColorPicker {
+    pub fn new() -> Self {
+        Self {
+            hsv: (0.0, 0.0, 0.0),
+            synced: None,
+            hex: String::new(),
+        }
+    }
+
+    /// Shows the picker for `color`. Returns whether it changed.
+    pub fn show(&mut self, ui: &mut Ui, color: &mut Rgba<u8>) -> bool {
+        if self.synced != Some(*color) {
+            // Changed elsewhere, e.g. by a swatch or the eyedropper.
+            self.sync_hsv(*color);
+            self.hex = to_hex(*color);
+        }
+
+        let mut changed = self.sv_square(ui);
+        let (h, s, v) = &mut self.hsv;
+        egui::Grid::new("color_picker_sliders").show(ui, |ui| {
+            ui.label("H");
+            changed |= ui
+                .add(egui::Slider::new(h, 0.0..=360.0).suffix("°"))
+                .changed();
+            ui.end_row();
+            ui.label("S");
+            changed |= ui.add(egui::Slider::new(s, 0.0..=1.0)).changed();
+            ui.end_row();
+            ui.label("V");
+            changed |= ui.add(egui::Slider::new(v, 0.0..=1.0)).changed();
+            ui.end_row();
+        });
+        let mut alpha = color[3];
+        let alpha_changed = ui
+            .horizontal(|ui| {
+                ui.label("A");
+                ui.add(egui::Slider::new(&mut alpha, 0..=255)).changed()
+            })
+            .inner;
+
+        if changed || alpha_changed {
+            let (r, g, b) = hsv_to_rgb(self.hsv.0, self.hsv.1, self.hsv.2);
+            *color = Rgba([r, g, b, alpha]);
+            self.hex = to_hex(*color);
+        }
+
+        let mut hex_changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Hex");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.hex).desired_width(90.0));
+            if response.changed() {
+                if let Some(parsed) = parse_hex(&self.hex) {
+                    hex_changed = parsed != *color;
+                    *color = parsed;
+                    self.sync_hsv(parsed);
+                }
+            }
+            if response.lost_focus() {
+                // Tidy up whatever was typed, or drop it if it never parsed.
+                self.hex = to_hex(*color);
+            }
+        });
+
+        self.synced = Some(*color);
+        changed || alpha_changed || hex_changed
+    }
+
+    /// Takes HSV from `color`, keeping the hue and saturation it leaves
+    /// undefined.
+    fn sync_hsv(&mut self, color: Rgba<u8>) {
+        let (h, s, v) = rgb_to_hsv(color[0], color[1], color[2]);
+        let (old_h, old_s, _) = self.hsv;
+        self.hsv = match (s == 0.0, v == 0.0) {
+            (_, true) => (old_h, old_s, v),
+            (true, false) => (old_h, s, v),
+            _ => (h, s, v),
+        };
+    }
+
+    /// Saturation left to right, value bottom to top, at the current hue.
+    /// Returns whether a click or drag moved the marker.
+    fn sv_square(&mut self, ui: &mut Ui) -> bool {
+        let (rect, response) = ui.allocate_exact_size(Vec2::splat(160.0), Sense::click_and_drag());
+        let (h, s, v) = self.hsv;
+
+        let mut mesh = Mesh::default();
+        for row in 0..=SV_STEPS {
+            for col in 0..=SV_STEPS {
+                let (cs, cv) = (
+                    col as f32 / SV_STEPS as f32,
+                    1.0 - row as f32 / SV_STEPS as f32,
+                );
+                let (r, g, b) = hsv_to_rgb(h, cs, cv);
+                let pos = Pos2::new(
+                    rect.left() + cs * rect.width(),
+                    rect.bottom() - cv * rect.height(),
+                );
+                mesh.colored_vertex(pos, Color32::from_rgb(r, g, b));
+            }
+        }
+        let stride = SV_STEPS as u32 + 1;
+        for row in 0..SV_STEPS as u32 {
+            for col in 0..SV_STEPS as u32 {
+                let i = row * stride + col;
+                mesh.add_triangle(i, i + 1, i + stride);
+                mesh.add_triangle(i + 1, i + stride + 1, i + stride);
+            }
+        }
+        let painter = ui.painter_at(rect);
+        painter.add(Shape::mesh(mesh));
+
+        let marker = Pos2::new(
+            rect.left() + s * rect.width(),
+            rect.bottom() - v * rect.height(),
+        );
+        painter.circle_stroke(marker, 5.0, Stroke::new(2.0, Color32::BLACK));
+        painter.circle_stroke(marker, 4.0, Stroke::new(1.5, Color32::WHITE));
+
+        let Some(pointer) = response
+            .interact_pointer_pos()
+            .filter(|_| response.is_pointer_button_down_on())
+        else {
+            return false;
+        };
+        let local = (pointer - rect.min) / rect.size();
+        self.hsv.1 = local.x.clamp(0.0, 1.0);
+        self.hsv.2 = (1.0 - local.y).clamp(0.0, 1.0);
+        true
+    }
+}
@@ -1,63 +1,211 @@
-use crate::image_store::ImageStore;
-use crate::layers::LayerData;
-use image::{GenericImage, RgbaImage};
+use crate::image_store::{DocumentSnapshot, ImageStore};
+use crate::layers::{FlipAxis, Layer, LayerData, LayerProperties, VectorShape};
+use image::{GenericImage, GrayImage, RgbaImage};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub trait Command {
     fn undo(&self, image: &mut ImageStore);
     fn redo(&self, image: &mut ImageStore);
     fn name(&self) -> &str;
+    /// Approximate bytes of image data the command keeps for undo and redo.
+    /// Commands that only record settings or indices hold next to nothing.
+    fn memory_size(&self) -> usize {
+        0
+    }
+    /// Canvas area `(x, y, width, height)` that undo and redo change, so only
+    /// it is recomposited. `None` means anything may have changed.
+    fn dirty_region(&self) -> Option<(u32, u32, u32, u32)> {
+        None
+    }
+}
+
+/// Marks what `command` changed for recompositing.
+fn mark_changed(command: &dyn Command, image: &mut ImageStore) {
+    match command.dirty_region() {
+        Some((x, y, w, h)) => image.mark_dirty_rect(x, y, w, h),
+        None => image.mark_dirty(),
+    }
 }
 
+/// Source of the ids that tell document states apart. Shared by every stack
+/// so a state from one document never matches one from another.
+static NEXT_STATE: AtomicU64 = AtomicU64::new(1);
+
 pub struct CommandStack {
     commands: Vec<Box<dyn Command>>,
+    /// State id reached by applying each command, parallel to `commands`.
+    states: Vec<u64>,
     cursor: usize,
+    /// Most undo steps kept; the oldest are dropped past this.
+    limit: usize,
+    /// State id with nothing applied: the document as opened, or as it was
+    /// after the last evicted step.
+    base_state: u64,
+    /// State id as of the last save.
+    saved_state: u64,
 }
 
 impl CommandStack {
-    pub fn new() -> Self {
+    pub fn new(limit: usize) -> Self {
+        // A fresh document counts as saved until it is edited.
+        let base_state = NEXT_STATE.fetch_add(1, Ordering::Relaxed);
         Self {
             commands: Vec::new(),
+            states: Vec::new(),
             cursor: 0,
+            limit: limit.max(1),
+            base_state,
+            saved_state: base_state,
         }
     }
 
     pub fn push(&mut self, command: Box<dyn Command>) {
         if self.cursor < self.commands.len() {
             self.commands.truncate(self.cursor);
+            self.states.truncate(self.cursor);
         }
         self.commands.push(command);
+        self.states.push(NEXT_STATE.fetch_add(1, Ordering::Relaxed));
         self.cursor += 1;
+        self.evict();
+    }
+
+    /// Identifies the document as the applied steps leave it. Undoing back to
+    /// a state gives its id again.
+    pub fn state(&self) -> u64 {
+        match self.cursor {
+            0 => self.base_state,
+            n => self.states[n - 1],
+        }
+    }
+
+    /// Records `state` as the one last saved. Taken when a save starts, so
+    /// edits made while it runs still count as unsaved.
+    pub fn mark_saved(&mut self, state: u64) {
+        self.saved_state = state;
+    }
+
+    /// Whether the document differs from what was last saved, as far as the
+    /// undo history can tell.
+    pub fn is_modified(&self) -> bool {
+        self.state() != self.saved_state
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+        self.evict();
+    }
+
+    /// Drops the oldest steps beyond `limit`. Redo steps are kept, since
+    /// they are the newest.
+    fn evict(&mut self) {
+        let excess = self.cursor.saturating_sub(self.limit);
+        if excess > 0 {
+            self.base_state = self.states[excess - 1];
+            self.commands.drain(..excess);
+            self.states.drain(..excess);
+            self.cursor -= excess;
+        }
+    }
+
+    /// Names of every recorded step, oldest first, and how many of them are
+    /// applied. Steps from the cursor on are the ones redo would replay.
+    pub fn history(&self) -> (Vec<&str>, usize) {
+        (
+            self.commands.iter().map(|c| c.name()).collect(),
+            self.cursor,
+        )
+    }
+
+    /// Undoes or redoes until exactly `target` steps are applied.
+    pub fn seek(&mut self, target: usize, image: &mut ImageStore) {
+        let target = target.min(self.commands.len());
+        while self.cursor > target {
+            self.undo(image);
+        }
+        while self.cursor < target {
+            self.redo(image);
+        }
+    }
+
+    /// Number of steps that can currently be undone.
+    pub fn undo_count(&self) -> usize {
+        self.cursor
     }
 
     pub fn undo(&mut self, image: &mut ImageStore) {
         if self.cursor > 0 {
             self.cursor -= 1;
             self.commands[self.cursor].undo(image);
-            image.mark_dirty();
+            mark_changed(self.commands[self.cursor].as_ref(), image);
         }
     }
 
     pub fn redo(&mut self, image: &mut ImageStore) {
         if self.cursor < self.commands.len() {
             self.commands[self.cursor].redo(image);
+            mark_changed(self.commands[self.cursor].as_ref(), image);
             self.cursor += 1;
-            image.mark_dirty();
         }
     }
 
-    #[allow(dead_code)]
-    pub fn can_undo(&self) -> bool {
-        self.cursor > 0
+    /// Applies `command` to the image and records it, for operations that
+    /// are built as a command up front rather than applied by a tool first.
+    pub fn execute(&mut self, command: Box<dyn Command>, image: &mut ImageStore) {
+        command.redo(image);
+        mark_changed(command.as_ref(), image);
+        self.push(command);
+    }
+
+    /// Approximate bytes of image data held by every recorded step, undo and
+    /// redo alike.
+    pub fn memory_estimate(&self) -> usize {
+        self.commands.iter().map(|c| c.memory_size()).sum()
+    }
+}
+
+/// Several commands recorded as one undo step, such as an edit that touched
+/// more than one layer. Undone in reverse order.
+pub struct CompoundCommand {
+    pub name: String,
+    pub commands: Vec<Box<dyn Command>>,
+}
+
+impl Command for CompoundCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        for command in self.commands.iter().rev() {
+            command.undo(image);
+        }
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        for command in &self.commands {
+            command.redo(image);
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn can_redo(&self) -> bool {
-        self.cursor < self.commands.len()
+    fn memory_size(&self) -> usize {
+        self.commands.iter().map(|c| c.memory_size()).sum()
+    }
+
+    fn dirty_region(&self) -> Option<(u32, u32, u32, u32)> {
+        // One child that needs a full recomposite makes the whole step need one.
+        let regions: Vec<_> = self
+            .commands
+            .iter()
+            .map(|c| c.dirty_region())
+            .collect::<Option<_>>()?;
+        regions
+            .into_iter()
+            .fold(None, |a, b| Some(crate::image_store::union_rect(a, b)))
     }
 }
 
 pub struct PatchCommand {
-    #[allow(dead_code)]
     pub name: String,
     pub layer_index: usize,
     pub x: u32,
@@ -66,6 +214,31 @@ pub struct PatchCommand {
     pub new_patch: RgbaImage,
 }
 
+impl PatchCommand {
+    /// Boxes a patch command for the undo stack, or returns `None` when the
+    /// patches are identical so edits that changed nothing leave no undo step.
+    pub fn if_changed(
+        name: &str,
+        layer_index: usize,
+        x: u32,
+        y: u32,
+        old_patch: RgbaImage,
+        new_patch: RgbaImage,
+    ) -> Option<Box<dyn Command>> {
+        if old_patch == new_patch {
+            return None;
+        }
+        Some(Box::new(PatchCommand {
+            name: name.to_string(),
+            layer_index,
+            x,
+            y,
+            old_patch,
+            new_patch,
+        }))
+    }
+}
+
 impl Command for PatchCommand {
     fn name(&self) -> &str {
         &self.name
@@ -92,4 +265,345 @@ impl Command for PatchCommand {
             }
         }
     }
+
+    fn memory_size(&self) -> usize {
+        self.old_patch.as_raw().len() + self.new_patch.as_raw().len()
+    }
+
+    fn dirty_region(&self) -> Option<(u32, u32, u32, u32)> {
+        let (w, h) = self.new_patch.dimensions();
+        Some((self.x, self.y, w, h))
+    }
+}
+
+/// Records the whole document before and after an operation that changes the
+/// canvas size or replaces layers wholesale (resize, crop, rotate, ...).
+pub struct DocumentCommand {
+    pub name: String,
+    pub before: DocumentSnapshot,
+    pub after: DocumentSnapshot,
+}
+
+impl DocumentCommand {
+    /// Runs `edit` on `image`, recording the document before and after it.
+    pub fn record(name: &str, image: &mut ImageStore, edit: impl FnOnce(&mut ImageStore)) -> Self {
+        let before = image.snapshot();
+        edit(image);
+        Self {
+            name: name.to_string(),
+            before,
+            after: image.snapshot(),
+        }
+    }
+}
+
+impl Command for DocumentCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        image.restore(&self.before);
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        image.restore(&self.after);
+    }
+
+    fn memory_size(&self) -> usize {
+        self.before.memory_size() + self.after.memory_size()
+    }
+}
+
+/// Records a change to a layer's settings (visibility, locks, opacity, ...).
+pub struct LayerPropertyCommand {
+    pub name: String,
+    pub layer_index: usize,
+    pub before: LayerProperties,
+    pub after: LayerProperties,
+}
+
+impl Command for LayerPropertyCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        if let Some(layer) = image.layers.get_mut(self.layer_index) {
+            layer.set_properties(&self.before);
+        }
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        if let Some(layer) = image.layers.get_mut(self.layer_index) {
+            layer.set_properties(&self.after);
+        }
+    }
+}
+
+/// Records moving a layer from one stack position to another.
+pub struct ReorderLayerCommand {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl Command for ReorderLayerCommand {
+    fn name(&self) -> &str {
+        "Reorder Layer"
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        image.move_layer(self.to, self.from);
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        image.move_layer(self.from, self.to);
+    }
+}
+
+/// Inserts `layer` at `index` and makes it active. Undo removes it and
+/// reactivates the layer beneath.
+pub struct AddLayerCommand {
+    pub name: String,
+    pub index: usize,
+    pub layer: Layer,
+}
+
+impl Command for AddLayerCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        image.remove_layer(self.index);
+        image.set_active(self.index.saturating_sub(1));
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        image.insert_layer(self.index, self.layer.clone());
+    }
+
+    fn memory_size(&self) -> usize {
+        self.layer.memory_size()
+    }
+}
+
+/// Appends `shape` to a vector layer. Undo pops it off again.
+pub struct AddShapeCommand {
+    pub layer_index: usize,
+    pub shape: VectorShape,
+}
+
+impl Command for AddShapeCommand {
+    fn name(&self) -> &str {
+        "Add Shape"
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        if let Some(LayerData::Vector(shapes)) =
+            image.layers.get_mut(self.layer_index).map(|l| &mut l.data)
+        {
+            shapes.pop();
+        }
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        if let Some(LayerData::Vector(shapes)) =
+            image.layers.get_mut(self.layer_index).map(|l| &mut l.data)
+        {
+            shapes.push(self.shape.clone());
+        }
+    }
+}
+
+/// Removes one layer, keeping a copy so undo can put it back in place.
+pub struct RemoveLayerCommand {
+    pub index: usize,
+    pub layer: Layer,
+}
+
+impl Command for RemoveLayerCommand {
+    fn name(&self) -> &str {
+        "Delete Layer"
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        image.insert_layer(self.index, self.layer.clone());
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        image.remove_layer(self.index);
+    }
+
+    fn memory_size(&self) -> usize {
+        self.layer.memory_size()
+    }
+}
+
+/// Adds, replaces or removes a layer's mask as a whole.
+pub struct SetMaskCommand {
+    pub name: String,
+    pub layer_index: usize,
+    pub before: Option<GrayImage>,
+    pub after: Option<GrayImage>,
+}
+
+impl Command for SetMaskCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        if let Some(layer) = image.layers.get_mut(self.layer_index) {
+            layer.mask = self.before.clone();
+        }
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        if let Some(layer) = image.layers.get_mut(self.layer_index) {
+            layer.mask = self.after.clone();
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        [&self.before, &self.after]
+            .iter()
+            .filter_map(|m| m.as_ref())
+            .map(|m| m.as_raw().len())
+            .sum()
+    }
+}
+
+/// The mask counterpart of `PatchCommand`: a painted region of a layer mask.
+pub struct MaskPatchCommand {
+    pub name: String,
+    pub layer_index: usize,
+    pub x: u32,
+    pub y: u32,
+    pub old_patch: GrayImage,
+    pub new_patch: GrayImage,
+}
+
+impl MaskPatchCommand {
+    /// Boxes the command, or returns `None` if the patches are identical.
+    pub fn if_changed(
+        name: &str,
+        layer_index: usize,
+        x: u32,
+        y: u32,
+        old_patch: GrayImage,
+        new_patch: GrayImage,
+    ) -> Option<Box<dyn Command>> {
+        if old_patch == new_patch {
+            return None;
+        }
+        Some(Box::new(MaskPatchCommand {
+            name: name.to_string(),
+            layer_index,
+            x,
+            y,
+            old_patch,
+            new_patch,
+        }))
+    }
+
+    fn apply(&self, image: &mut ImageStore, patch: &GrayImage) {
+        if let Some(mask) = image
+            .layers
+            .get_mut(self.layer_index)
+            .and_then(|l| l.mask.as_mut())
+        {
+            let _ = mask.copy_from(patch, self.x, self.y);
+        }
+    }
+}
+
+impl Command for MaskPatchCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        self.apply(image, &self.old_patch);
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        self.apply(image, &self.new_patch);
+    }
+
+    fn memory_size(&self) -> usize {
+        self.old_patch.as_raw().len() + self.new_patch.as_raw().len()
+    }
+
+    fn dirty_region(&self) -> Option<(u32, u32, u32, u32)> {
+        let (w, h) = self.new_patch.dimensions();
+        Some((self.x, self.y, w, h))
+    }
+}
+
+/// Mirrors a region of some layers, and optionally the selection. A flip is
+/// its own inverse, so undo just flips again.
+pub struct FlipCommand {
+    pub name: String,
+    pub axis: FlipAxis,
+    pub layers: Vec<usize>,
+    pub region: (u32, u32, u32, u32),
+    pub selection: bool,
+}
+
+impl Command for FlipCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undo(&self, image: &mut ImageStore) {
+        self.redo(image);
+    }
+
+    fn redo(&self, image: &mut ImageStore) {
+        image.flip(self.axis, &self.layers, self.region, self.selection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Changes nothing, and reports `region` as what it changed.
+    struct Touch(Option<(u32, u32, u32, u32)>);
+
+    impl Command for Touch {
+        fn undo(&self, _: &mut ImageStore) {}
+        fn redo(&self, _: &mut ImageStore) {}
+        fn name(&self) -> &str {
+            "Touch"
+        }
+        fn dirty_region(&self) -> Option<(u32, u32, u32, u32)> {
+            self.0
+        }
+    }
+
+    fn compound(regions: &[Option<(u32, u32, u32, u32)>]) -> CompoundCommand {
+        CompoundCommand {
+            name: String::new(),
+            commands: regions.iter().map(|&r| Box::new(Touch(r)) as _).collect(),
+        }
+    }
+
+    #[test]
+    fn compound_region_spans_its_children() {
+        let step = compound(&[Some((2, 2, 4, 4)), Some((10, 0, 2, 3))]);
+        assert_eq!(step.dirty_region(), Some((2, 0, 10, 6)));
+    }
+
+    #[test]
+    fn compound_region_is_everything_if_any_child_is() {
+        for regions in [
+            [None, Some((2, 2, 4, 4)), Some((10, 0, 2, 3))],
+            [Some((2, 2, 4, 4)), None, Some((10, 0, 2, 3))],
+            [Some((2, 2, 4, 4)), Some((10, 0, 2, 3)), None],
+        ] {
+            assert_eq!(compound(&regions).dirty_region(), None);
+        }
+    }
 }
@@ -0,0 +1,54 @@
+use crate::export::ExportSettings;
+use crate::state::{Keybindings, Preferences};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Settings kept between sessions in `<config dir>/arspaint/config.json`.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub preferences: Preferences,
+    pub keybindings: Keybindings,
+    pub last_export: Option<ExportSettings>,
+    pub last_export_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// `<config dir>/arspaint`, where everything ArsPaint keeps between
+    /// sessions lives.
+    pub fn dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("arspaint"))
+    }
+
+    fn path() -> Option<PathBuf> {
+        Self::dir().map(|dir| dir.join("config.json"))
+    }
+
+    /// Reads the config file, falling back to defaults if it is missing or
+    /// unreadable.
+    pub fn load() -> Self {
+        let Some(path) = Self::path().filter(|p| p.exists()) else {
+            return Self::default();
+        };
+        let result = std::fs::read_to_string(&path)
+            .context("Failed to read config")
+            .and_then(|text| serde_json::from_str(&text).context("Invalid config"));
+        match result {
+            Ok(config) => config,
+            Err(e) => {
+                log::warn!("Ignoring {:?}: {:#}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().context("No config directory on this system")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, text).with_context(|| format!("Failed to write {:?}", path))
+    }
+}
@@ -0,0 +1,172 @@
+use crate::tools::ViewTransform;
+use anyhow::{Context, Result};
+use egui::Pos2;
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::{GrayImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Png,
+    Jpeg,
+    Bmp,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [ExportFormat::Png, ExportFormat::Jpeg, ExportFormat::Bmp];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ExportFormat::Png => "PNG",
+            ExportFormat::Jpeg => "JPEG",
+            ExportFormat::Bmp => "BMP",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::Bmp => "bmp",
+        }
+    }
+
+    /// JPEG has no alpha channel, so it is always flattened onto the matte.
+    pub fn has_alpha(self) -> bool {
+        self != ExportFormat::Jpeg
+    }
+}
+
+/// How the composite is written out. Remembered between exports.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+    /// JPEG quality, 1-100.
+    pub quality: u8,
+    /// Resolution stored in the file's metadata. BMP ignores it.
+    pub dpi: u16,
+    /// Composite onto `matte` to drop transparency.
+    pub flatten: bool,
+    pub matte: [u8; 3],
+    /// Write color premultiplied by alpha, for tools that expect it.
+    pub premultiply: bool,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Png,
+            quality: 90,
+            dpi: 72,
+            flatten: false,
+            matte: [255, 255, 255],
+            premultiply: false,
+        }
+    }
+}
+
+impl ExportSettings {
+    pub fn flattens(&self) -> bool {
+        self.flatten || !self.format.has_alpha()
+    }
+}
+
+/// Writes `image` to `path` in the format and with the options in `settings`.
+pub fn export(image: &RgbaImage, path: &Path, settings: &ExportSettings) -> Result<()> {
+    let mut pixels = image.clone();
+    if settings.flattens() {
+        let [r, g, b] = settings.matte;
+        for p in pixels.pixels_mut() {
+            *p = crate::color::over(Rgba([r, g, b, 255]), *p);
+        }
+    } else if settings.premultiply {
+        for p in pixels.pixels_mut() {
+            *p = crate::color::premultiply(*p);
+        }
+    }
+
+    let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    match settings.format {
+        ExportFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut writer, pixels.width(), pixels.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            // PNG stores density per meter.
+            let ppm = (settings.dpi as f64 / 0.0254).round() as u32;
+            encoder.set_pixel_dims(Some(png::PixelDimensions {
+                xppu: ppm,
+                yppu: ppm,
+                unit: png::Unit::Meter,
+            }));
+            encoder
+                .write_header()
+                .and_then(|mut w| {
+                    w.write_image_data(pixels.as_raw())?;
+                    w.finish()
+                })
+                .context("Failed to write PNG")?;
+        }
+        ExportFormat::Jpeg => {
+            let rgb = image::DynamicImage::ImageRgba8(pixels).to_rgb8();
+            let mut encoder =
+                JpegEncoder::new_with_quality(&mut writer, settings.quality.clamp(1, 100));
+            encoder.set_pixel_density(PixelDensity::dpi(settings.dpi));
+            encoder.encode_image(&rgb).context("Failed to write JPEG")?;
+        }
+        ExportFormat::Bmp => {
+            pixels
+                .write_to(&mut writer, image::ImageFormat::Bmp)
+                .context("Failed to write BMP")?;
+        }
+    }
+    writer.flush().context("Failed to write file")
+}
+
+/// Renders the composite as it appears on screen: `view` places the image in
+/// a `width`×`height` viewport, with the checkerboard under transparency and
+/// the workspace color around the canvas. With `overlays`, the selection tint
+/// and canvas border are drawn too.
+pub fn render_view(
+    composite: &RgbaImage,
+    selection: Option<&GrayImage>,
+    view: &ViewTransform,
+    width: u32,
+    height: u32,
+    overlays: bool,
+) -> RgbaImage {
+    let workspace = Rgba([26, 27, 38, 255]);
+    let border = Rgba([60, 60, 60, 255]);
+    let (iw, ih) = composite.dimensions();
+    let canvas = view.screen_rect(egui::Rect::from_min_size(
+        Pos2::ZERO,
+        egui::vec2(iw as f32, ih as f32),
+    ));
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let screen = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+        let pos = view.image_pos(screen);
+        if pos.x < 0.0 || pos.y < 0.0 || pos.x >= iw as f32 || pos.y >= ih as f32 {
+            let on_border = overlays && canvas.expand(1.0).contains(screen);
+            return if on_border { border } else { workspace };
+        }
+        let (px, py) = (pos.x as u32, pos.y as u32);
+
+        // Same 16px image-space checkerboard as the canvas.
+        let checker = ((px / 16) + (py / 16)) % 2 == 1;
+        let backdrop = Rgba(if checker {
+            [180, 180, 180, 255]
+        } else {
+            [200, 200, 200, 255]
+        });
+        let mut out = crate::color::over(backdrop, *composite.get_pixel(px, py));
+        if overlays && selection.is_some_and(|m| m.get_pixel(px, py)[0] > 0) {
+            out = crate::color::over(out, Rgba([0, 100, 255, 50]));
+        }
+        out
+    })
+}
@@ -0,0 +1,78 @@
+use crate::tools::ViewTransform;
+use egui::{Color32, Painter, Pos2, Rect, Stroke, Vec2};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GuideKind {
+    /// Lines radiating from a vanishing point.
+    Perspective,
+    /// Circles around a common center.
+    Concentric,
+}
+
+/// On-canvas drawing guides. Only drawn over the canvas, never into pixels.
+pub struct Guides {
+    pub visible: bool,
+    pub kind: GuideKind,
+    /// Vanishing point or circle center, in image coordinates.
+    pub center: Pos2,
+    /// Radiating lines for `Perspective`, rings for `Concentric`.
+    pub count: u32,
+    /// Distance between rings, in image pixels.
+    pub spacing: f32,
+    pub color: Color32,
+    /// Make the Line tool aim at the vanishing point.
+    pub snap_lines: bool,
+    /// The next click on the canvas moves `center` instead of painting.
+    pub placing: bool,
+}
+
+impl Guides {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            kind: GuideKind::Perspective,
+            center: Pos2::ZERO,
+            count: 24,
+            spacing: 50.0,
+            color: Color32::from_rgba_unmultiplied(255, 120, 0, 140),
+            snap_lines: false,
+            placing: false,
+        }
+    }
+
+    /// The point lines are snapped toward, when snapping applies.
+    pub fn snap_target(&self) -> Option<Pos2> {
+        (self.visible && self.snap_lines && self.kind == GuideKind::Perspective)
+            .then_some(self.center)
+    }
+
+    /// Draws the guides over `canvas`, the image's on-screen rect.
+    pub fn draw(&self, painter: &Painter, view: &ViewTransform, canvas: Rect) {
+        if !self.visible {
+            return;
+        }
+        let painter = painter.with_clip_rect(canvas.intersect(painter.clip_rect()));
+        let stroke = Stroke::new(1.0, self.color);
+        let center = view.screen_pos(self.center);
+        let count = self.count.max(1);
+
+        match self.kind {
+            GuideKind::Perspective => {
+                // Long enough to cross the canvas from any center.
+                let reach = canvas.size().length() + center.distance(canvas.center());
+                for i in 0..count {
+                    let angle = i as f32 * std::f32::consts::TAU / count as f32;
+                    let end = center + Vec2::angled(angle) * reach;
+                    painter.line_segment([center, end], stroke);
+                }
+            }
+            GuideKind::Concentric => {
+                let spacing = self.spacing.max(1.0) * view.zoom;
+                for i in 1..=count {
+                    painter.circle_stroke(center, spacing * i as f32, stroke);
+                }
+            }
+        }
+        painter.circle_stroke(center, 5.0, Stroke::new(1.5, self.color.to_opaque()));
+    }
+}
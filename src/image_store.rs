@@ -1,8 +1,62 @@
-use crate::layers::{BlendMode, Layer, LayerData};
+use crate::commands::PatchCommand;
+use crate::layers::{flip_region, BlendMode, FlipAxis, Layer, LayerData};
+use crate::project::SavedTool;
 use anyhow::{Context, Result};
-use image::{ImageBuffer, Pixel, Rgba, RgbaImage};
+use image::imageops::{self, FilterType};
+use image::{GenericImageView, GrayImage, ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
+use rayon::prelude::*;
+use std::borrow::Cow;
 use std::path::Path;
 
+/// The smallest `(x, y, width, height)` rect covering `a`, if any, and `b`.
+pub fn union_rect(
+    a: Option<(u32, u32, u32, u32)>,
+    b: (u32, u32, u32, u32),
+) -> (u32, u32, u32, u32) {
+    let Some((ax, ay, aw, ah)) = a else {
+        return b;
+    };
+    let (bx, by, bw, bh) = b;
+    let (x, y) = (ax.min(bx), ay.min(by));
+    (x, y, (ax + aw).max(bx + bw) - x, (ay + ah).max(by + bh) - y)
+}
+
+/// A layer's pixels as the compositor sees them: `image` covers the canvas
+/// from `origin`, which is the whole canvas for raster layers.
+struct LayerPixels<'a> {
+    image: Cow<'a, RgbaImage>,
+    origin: (u32, u32),
+}
+
+impl LayerPixels<'_> {
+    /// The pixel at canvas position `(x, y)`, if `image` covers it.
+    fn get(&self, x: u32, y: u32) -> Option<&Rgba<u8>> {
+        let (ox, oy) = self.origin;
+        self.image
+            .get_pixel_checked(x.checked_sub(ox)?, y.checked_sub(oy)?)
+    }
+}
+
+/// Everything needed to put a document back exactly as it was, minus the
+/// cached composite. Used by commands that change the canvas size or the
+/// layer stack as a whole.
+#[derive(Clone)]
+pub struct DocumentSnapshot {
+    width: u32,
+    height: u32,
+    layers: Vec<Layer>,
+    active_layer: usize,
+    selection: Option<image::GrayImage>,
+}
+
+impl DocumentSnapshot {
+    /// Approximate bytes held by the snapshot's layers and selection.
+    pub fn memory_size(&self) -> usize {
+        self.layers.iter().map(Layer::memory_size).sum::<usize>()
+            + self.selection.as_ref().map_or(0, |s| s.as_raw().len())
+    }
+}
+
 #[derive(Clone)]
 pub struct ImageStore {
     width: u32,
@@ -10,30 +64,51 @@ pub struct ImageStore {
     pub layers: Vec<Layer>,
     pub active_layer: usize,
     pub selection: Option<image::GrayImage>,
+    /// Solid color composited under every layer. Not a layer, so it can be
+    /// recolored instantly; transparent exports leave it out.
+    pub background: Option<Rgba<u8>>,
     // Cached final render
     composite: RgbaImage,
     composite_dirty: bool,
+    // Area `(x, y, width, height)` changed since the last composite, when
+    // only part of the canvas needs rebuilding. Ignored while
+    // `composite_dirty` asks for everything.
+    dirty_rect: Option<(u32, u32, u32, u32)>,
+    // Area of the composite rebuilt since `take_composite_changes` was last
+    // called, so the UI can upload just that part.
+    changed_rect: Option<(u32, u32, u32, u32)>,
+    // Downscaled composite keyed by the `max_dim` it was built for. Dropped
+    // whenever the composite is rebuilt.
+    thumbnail: Option<(u32, RgbaImage)>,
 }
 
 impl ImageStore {
     pub fn new(width: u32, height: u32) -> Self {
+        Self::with_fill(width, height, Some(Rgba([255, 255, 255, 255])))
+    }
+
+    /// A document of one raster layer filled with `fill`, or left
+    /// transparent without one.
+    pub fn with_fill(width: u32, height: u32, fill: Option<Rgba<u8>>) -> Self {
         // Default to one raster layer
         let layer = Layer::new_raster(width, height, "Layer 1".to_string());
-        // Initialize background to white
         let mut store = Self {
             width,
             height,
             layers: vec![layer],
             active_layer: 0,
             selection: None,
+            background: None,
             composite: ImageBuffer::new(width, height),
             composite_dirty: true,
+            dirty_rect: None,
+            changed_rect: None,
+            thumbnail: None,
         };
 
-        // Fill first layer with white
-        if let LayerData::Raster(ref mut img) = store.layers[0].data {
+        if let (Some(fill), LayerData::Raster(ref mut img)) = (fill, &mut store.layers[0].data) {
             for pixel in img.pixels_mut() {
-                *pixel = Rgba([255, 255, 255, 255]);
+                *pixel = fill;
             }
         }
         store.composite();
@@ -42,32 +117,92 @@ impl ImageStore {
 
     pub fn from_file(path: &Path) -> Result<Self> {
         let img = image::open(path).context("Failed to open image file")?;
-        let buffer = img.to_rgba8();
-        let width = buffer.width();
-        let height = buffer.height();
-
-        let layer = Layer {
-            name: "Background".to_string(),
-            visible: true,
-            locked: false,
-            alpha_locked: false,
-            clipped: false,
-            opacity: 1.0,
-            blend: BlendMode::Normal,
-            data: LayerData::Raster(buffer),
+        Ok(Self::from_frames(vec![img.to_rgba8()]))
+    }
+
+    /// Decodes every frame of an animated GIF or APNG at full canvas size.
+    /// Returns `None` for other formats and for files with a single frame.
+    pub fn decode_frames(path: &Path) -> Result<Option<Vec<RgbaImage>>> {
+        use image::codecs::{gif::GifDecoder, png::PngDecoder};
+        use image::{AnimationDecoder, ImageFormat};
+        use std::io::BufReader;
+
+        let reader = image::ImageReader::open(path)
+            .context("Failed to open image file")?
+            .with_guessed_format()
+            .context("Failed to read image file")?;
+        let file = || -> Result<BufReader<std::fs::File>> {
+            Ok(BufReader::new(
+                std::fs::File::open(path).context("Failed to open image file")?,
+            ))
         };
 
+        let frames = match reader.format() {
+            Some(ImageFormat::Gif) => GifDecoder::new(file()?)?.into_frames().collect_frames()?,
+            Some(ImageFormat::Png) => {
+                let decoder = PngDecoder::new(file()?)?;
+                if !decoder.is_apng()? {
+                    return Ok(None);
+                }
+                decoder.apng()?.into_frames().collect_frames()?
+            }
+            _ => return Ok(None),
+        };
+
+        if frames.len() < 2 {
+            return Ok(None);
+        }
+        Ok(Some(frames.into_iter().map(|f| f.into_buffer()).collect()))
+    }
+
+    /// Builds a document with one layer per frame, the first frame at the
+    /// bottom. A single frame becomes the usual "Background" layer.
+    pub fn from_frames(frames: Vec<RgbaImage>) -> Self {
+        let (width, height) = frames.first().map_or((1, 1), |f| f.dimensions());
+        let single = frames.len() == 1;
+
+        let layers = frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, buffer)| Layer {
+                name: if single {
+                    "Background".to_string()
+                } else {
+                    format!("Frame {}", i + 1)
+                },
+                visible: true,
+                locked: false,
+                alpha_locked: false,
+                channel_lock: [false; 4],
+                clipped: false,
+                opacity: 1.0,
+                blend: BlendMode::Normal,
+                data: LayerData::Raster(buffer),
+                mask: None,
+            })
+            .collect::<Vec<_>>();
+        let active = layers.len().saturating_sub(1);
+        Self::from_layers(width, height, layers, active)
+    }
+
+    /// Builds a document from an existing layer stack. Every layer's buffers
+    /// must already be `width`×`height`.
+    pub fn from_layers(width: u32, height: u32, layers: Vec<Layer>, active: usize) -> Self {
         let mut store = Self {
             width,
             height,
-            layers: vec![layer],
-            active_layer: 0,
+            active_layer: active.min(layers.len().saturating_sub(1)),
+            layers,
             selection: None,
+            background: None,
             composite: ImageBuffer::new(width, height),
             composite_dirty: true,
+            dirty_rect: None,
+            changed_rect: None,
+            thumbnail: None,
         };
         store.composite();
-        Ok(store)
+        store
     }
 
     pub fn width(&self) -> u32 {
@@ -77,6 +212,27 @@ impl ImageStore {
         self.height
     }
 
+    /// Number of layers in the document.
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Index of the layer that tools currently paint into.
+    pub fn active_index(&self) -> usize {
+        self.active_layer
+    }
+
+    /// Makes `idx` the active layer. Returns `false` and leaves the active
+    /// layer untouched if `idx` is out of bounds.
+    pub fn set_active(&mut self, idx: usize) -> bool {
+        if idx < self.layers.len() {
+            self.active_layer = idx;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn active_layer_mut(&mut self) -> Option<&mut Layer> {
         if self.active_layer < self.layers.len() {
             Some(&mut self.layers[self.active_layer])
@@ -89,69 +245,152 @@ impl ImageStore {
         self.layers.get(self.active_layer)
     }
 
-    pub fn add_layer(&mut self, layer: Layer) {
-        self.layers.insert(self.active_layer + 1, layer);
-        self.active_layer += 1;
+    /// Whether the active layer is locked against all edits. Tools check
+    /// this before writing pixels.
+    pub fn active_layer_is_locked(&self) -> bool {
+        self.active_layer().is_some_and(|layer| layer.locked)
+    }
+
+    /// Moves the layer at `from` to `to`, keeping the same layer active.
+    /// Returns false if either index is out of range.
+    pub fn move_layer(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.layers.len() || to >= self.layers.len() {
+            return false;
+        }
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+
+        if self.active_layer == from {
+            self.active_layer = to;
+        } else if from < self.active_layer && self.active_layer <= to {
+            self.active_layer -= 1;
+        } else if to <= self.active_layer && self.active_layer < from {
+            self.active_layer += 1;
+        }
         self.composite_dirty = true;
+        true
     }
 
-    pub fn composite(&mut self) {
-        if !self.composite_dirty {
-            return;
+    /// Takes the layer at `index` out of the stack. The last remaining layer
+    /// can't be removed. The active index is clamped to the shorter stack.
+    pub fn remove_layer(&mut self, index: usize) -> Option<Layer> {
+        if self.layers.len() <= 1 || index >= self.layers.len() {
+            return None;
         }
+        let layer = self.layers.remove(index);
+        if self.active_layer > index {
+            self.active_layer -= 1;
+        }
+        self.active_layer = self.active_layer.min(self.layers.len() - 1);
+        self.composite_dirty = true;
+        Some(layer)
+    }
 
-        // Clear composite
-        self.composite =
-            ImageBuffer::from_pixel(self.width, self.height, Rgba([200, 200, 200, 255])); // Checkerboard fallback?
-                                                                                          // Actually, let's start transparent
-        for p in self.composite.pixels_mut() {
-            *p = Rgba([0, 0, 0, 0]);
-        }
-
-        let mut i = 0;
-        while i < self.layers.len() {
-            // Need to decoupling borrowing of self from layer
-            // We can clone properties needed for blending
-            let (visible, opacity, blend, data) = {
-                let layer = &self.layers[i];
-                (layer.visible, layer.opacity, layer.blend, &layer.data)
-            };
-
-            if visible {
-                match data {
-                    LayerData::Raster(img) => {
-                        // Safe because we are not borrowing self.layers anymore, but we need img reference
-                        // Uh oh, `data` borrows from `self.layers`.
-                        // But `blend_buffer` borrows `self.composite` (mut).
-                        // Rust won't like `self.layers` (immutable) and `self.composite` (part of self, mutable) being borrowed same time.
-                        // We need to split ImageStore or use interior mutability or unsafe.
-                        // OR: clone the buffer? Too slow.
-                        // Better: Iterate over indices, and get references strictly.
-                        // Actually, `composite` field is disjoint from `layers`.
-                        // But the method takes `&mut self`.
-
-                        // Workaround: Temporarily take composite out of self?
-                        // Or pass `&mut composite` and `&layers` to a static function.
-                    }
-                    _ => {}
-                }
-            }
-            i += 1;
+    /// Puts `layer` back at `index` and makes it active.
+    pub fn insert_layer(&mut self, index: usize, layer: Layer) {
+        let index = index.min(self.layers.len());
+        self.layers.insert(index, layer);
+        self.active_layer = index;
+        self.composite_dirty = true;
+    }
+
+    /// Composites the layers that show into one raster layer placed where the
+    /// lowest of them was. Hidden layers, and visible ones clipped to a hidden
+    /// base, are left in the stack untouched. Returns false if fewer than two
+    /// layers show.
+    pub fn merge_visible(&mut self) -> bool {
+        let shown: Vec<bool> = (0..self.layers.len())
+            .map(|i| Self::shows(&self.layers, i))
+            .collect();
+        let Some(insert_at) = shown.iter().position(|&s| s) else {
+            return false;
+        };
+        if shown.iter().filter(|&&s| s).count() < 2 {
+            return false;
         }
 
-        // Let's refactor composite to be a static-like method that takes the dest and source layers
-        // to avoid self-borrow conflicts.
+        let mut merged = ImageBuffer::new(self.width, self.height);
+        Self::composite_layers(&mut merged, &self.layers, None);
+
+        let mut layer = Layer::new_raster(self.width, self.height, "Merged".to_string());
+        layer.data = LayerData::Raster(merged);
+        let mut shown = shown.into_iter();
+        self.layers.retain(|_| !shown.next().unwrap_or(false));
+        self.layers.insert(insert_at, layer);
+        self.active_layer = insert_at;
+        self.composite_dirty = true;
+        true
+    }
 
-        let dest = &mut self.composite;
-        let layers = &self.layers;
+    /// Replaces the whole stack with a single layer of what is currently
+    /// visible. Hidden layers are discarded.
+    pub fn flatten(&mut self) {
+        let mut flat = ImageBuffer::new(self.width, self.height);
+        Self::composite_layers(&mut flat, &self.layers, None);
 
-        Self::composite_layers(dest, layers);
+        let mut layer = Layer::new_raster(self.width, self.height, "Background".to_string());
+        layer.data = LayerData::Raster(flat);
+        self.layers = vec![layer];
+        self.active_layer = 0;
+        self.composite_dirty = true;
+    }
+
+    pub fn add_layer(&mut self, layer: Layer) {
+        self.layers.insert(self.active_layer + 1, layer);
+        self.active_layer += 1;
+        self.composite_dirty = true;
+    }
+
+    pub fn composite(&mut self) {
+        if self.composite_dirty || self.composite.dimensions() != (self.width, self.height) {
+            // `composite_layers` resets every pixel to the background, or to
+            // fully transparent without one, so uncovered areas never keep
+            // stale pixels.
+            if self.composite.dimensions() != (self.width, self.height) {
+                self.composite = ImageBuffer::new(self.width, self.height);
+            }
+            Self::composite_layers(&mut self.composite, &self.layers, self.background);
+            self.changed_rect = Some((0, 0, self.width, self.height));
+        } else if let Some((x, y, w, h)) = self.dirty_rect {
+            // Rebuild just the changed area and patch it into the cache.
+            let mut region = ImageBuffer::new(w, h);
+            Self::composite_layers_at(
+                &mut region,
+                &self.layers,
+                self.background,
+                (x, y),
+                (self.width, self.height),
+            );
+            imageops::replace(&mut self.composite, &region, x as i64, y as i64);
+            self.changed_rect = Some(union_rect(self.changed_rect, (x, y, w, h)));
+        } else {
+            return;
+        }
         self.composite_dirty = false;
+        self.dirty_rect = None;
+        self.thumbnail = None;
+    }
+
+    // An associated function rather than a method: `composite` needs
+    // `self.composite` mutably while reading `self.layers`, which a `&mut
+    // self` method can't borrow apart.
+    fn composite_layers(dest: &mut RgbaImage, layers: &[Layer], background: Option<Rgba<u8>>) {
+        let size = dest.dimensions();
+        Self::composite_layers_at(dest, layers, background, (0, 0), size);
     }
 
-    fn composite_layers(dest: &mut RgbaImage, layers: &[Layer]) {
+    /// Composites the part of a `canvas`-sized document that `dest` covers
+    /// when placed at `origin`.
+    fn composite_layers_at(
+        dest: &mut RgbaImage,
+        layers: &[Layer],
+        background: Option<Rgba<u8>>,
+        origin: (u32, u32),
+        canvas: (u32, u32),
+    ) {
+        let base = background.unwrap_or(Rgba([0, 0, 0, 0]));
         for p in dest.pixels_mut() {
-            *p = Rgba([0, 0, 0, 0]);
+            *p = base;
         }
 
         for i in 0..layers.len() {
@@ -160,117 +399,274 @@ impl ImageStore {
                 continue;
             }
 
-            let mask = if layer.clipped && i > 0 {
-                match &layers[i - 1].data {
-                    LayerData::Raster(img) => Some(img),
-                    LayerData::Tone { buffer, .. } => Some(buffer),
-                    _ => None,
-                }
-            } else {
-                None
-            };
-
-            match &layer.data {
-                LayerData::Raster(img) => {
-                    Self::blend_buffer_static(dest, img, layer.opacity, layer.blend, mask)
-                }
-                LayerData::Tone { buffer, .. } => {
-                    Self::blend_buffer_static(dest, buffer, layer.opacity, layer.blend, mask)
-                }
-                _ => {}
+            if !Self::shows(layers, i) {
+                continue;
             }
+            // Only the area `dest` covers is rasterized or screened.
+            let (w, h) = dest.dimensions();
+            let (x, y) = origin;
+            let area = (x, y, w.min(canvas.0 - x), h.min(canvas.1 - y));
+            let base = Self::clip_base(layers, i);
+            let clip = base.map(|b| {
+                (
+                    Self::layer_pixels(&layers[b], area),
+                    layers[b].mask.as_ref(),
+                )
+            });
+            let clip = clip.as_ref().map(|(pixels, mask)| (pixels, *mask));
+
+            Self::blend_buffer_static(
+                dest,
+                &Self::layer_pixels(layer, area),
+                layer.opacity,
+                layer.blend,
+                clip,
+                layer.mask.as_ref(),
+                origin,
+            );
         }
     }
 
+    /// The layer the one at `index` clips to, if it is clipped. A run of
+    /// clipped layers all clip to the first unclipped layer beneath them, so
+    /// several color layers can share one base fill.
+    fn clip_base(layers: &[Layer], index: usize) -> Option<usize> {
+        if layers[index].clipped {
+            layers[..index].iter().rposition(|l| !l.clipped)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the layer at `index` adds anything to the composite: it is
+    /// visible, and so is its base if it is clipped. Hiding the base hides
+    /// everything clipped to it.
+    fn shows(layers: &[Layer], index: usize) -> bool {
+        layers[index].visible && Self::clip_base(layers, index).is_none_or(|b| layers[b].visible)
+    }
+
+    /// A layer's pixels for compositing the `(x, y, width, height)` area.
+    /// Raster layers are borrowed whole; vector layers are rasterized and
+    /// tone layers screened into dots for that area alone.
+    fn layer_pixels(layer: &Layer, area: (u32, u32, u32, u32)) -> LayerPixels<'_> {
+        let (image, origin) = match &layer.data {
+            LayerData::Raster(img) => (Cow::Borrowed(img), (0, 0)),
+            LayerData::Tone {
+                buffer,
+                frequency,
+                density,
+            } => (
+                Cow::Owned(crate::layers::halftone(buffer, *frequency, *density, area)),
+                (area.0, area.1),
+            ),
+            LayerData::Vector(shapes) => (
+                Cow::Owned(crate::layers::rasterize_shapes(shapes, area)),
+                (area.0, area.1),
+            ),
+        };
+        LayerPixels { image, origin }
+    }
+
+    /// Blends `source` onto `dest`, whose top-left pixel is `origin` in
+    /// canvas coordinates. `clip` is the base layer a clipped layer shows
+    /// through, with that layer's own mask; `mask` is the source's.
     fn blend_buffer_static(
         dest: &mut RgbaImage,
-        source: &RgbaImage,
+        source: &LayerPixels,
         opacity: f32,
         mode: BlendMode,
-        mask: Option<&RgbaImage>,
+        clip: Option<(&LayerPixels, Option<&GrayImage>)>,
+        mask: Option<&GrayImage>,
+        (ox, oy): (u32, u32),
     ) {
-        for (x, y, pixel) in dest.enumerate_pixels_mut() {
-            if x >= source.width() || y >= source.height() {
-                continue;
-            }
-            let src_pixel = source.get_pixel(x, y);
+        // Masks are canvas-sized; anything outside one is hidden.
+        let coverage = |m: &GrayImage, x: u32, y: u32| {
+            m.get_pixel_checked(x, y)
+                .map_or(0.0, |p| p[0] as f32 / 255.0)
+        };
 
-            let mut src_a = (src_pixel[3] as f32 / 255.0) * opacity;
+        // Rows are independent, so they blend in parallel. Each row is
+        // written by exactly one task and the math is unchanged.
+        let row_len = dest.width() as usize * 4;
+        if row_len == 0 {
+            return;
+        }
+        dest.par_chunks_mut(row_len)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let y = y as u32 + oy;
+                for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                    let x = x as u32 + ox;
+                    let pixel = Rgba::from_slice_mut(pixel);
+                    let Some(src_pixel) = source.get(x, y) else {
+                        continue;
+                    };
 
-            if let Some(mask_img) = mask {
-                if x < mask_img.width() && y < mask_img.height() {
-                    let mask_pixel = mask_img.get_pixel(x, y);
-                    src_a *= mask_pixel[3] as f32 / 255.0;
-                } else {
-                    src_a = 0.0;
-                }
-            }
+                    let mut src_a = (src_pixel[3] as f32 / 255.0) * opacity;
 
-            if src_a <= 0.0 {
-                continue;
-            }
+                    if let Some(mask) = mask {
+                        src_a *= coverage(mask, x, y);
+                    }
+
+                    if let Some((clip_img, clip_mask)) = clip {
+                        src_a *= clip_img.get(x, y).map_or(0.0, |p| p[3] as f32 / 255.0);
+                        if let Some(clip_mask) = clip_mask {
+                            src_a *= coverage(clip_mask, x, y);
+                        }
+                    }
 
-            let dst_pixel = *pixel;
-            let dst_a = dst_pixel[3] as f32 / 255.0;
-
-            let (r, g, b) = match mode {
-                BlendMode::Normal => (
-                    src_pixel[0] as f32,
-                    src_pixel[1] as f32,
-                    src_pixel[2] as f32,
-                ),
-                BlendMode::Multiply => (
-                    (dst_pixel[0] as f32 * src_pixel[0] as f32) / 255.0,
-                    (dst_pixel[1] as f32 * src_pixel[1] as f32) / 255.0,
-                    (dst_pixel[2] as f32 * src_pixel[2] as f32) / 255.0,
-                ),
-                BlendMode::Add => (
-                    (dst_pixel[0] as f32 + src_pixel[0] as f32).min(255.0),
-                    (dst_pixel[1] as f32 + src_pixel[1] as f32).min(255.0),
-                    (dst_pixel[2] as f32 + src_pixel[2] as f32).min(255.0),
-                ),
-                BlendMode::Screen => {
-                    let inv_src_r = 1.0 - (src_pixel[0] as f32 / 255.0);
-                    let inv_dst_r = 1.0 - (dst_pixel[0] as f32 / 255.0);
-                    let inv_src_g = 1.0 - (src_pixel[1] as f32 / 255.0);
-                    let inv_dst_g = 1.0 - (dst_pixel[1] as f32 / 255.0);
-                    let inv_src_b = 1.0 - (src_pixel[2] as f32 / 255.0);
-                    let inv_dst_b = 1.0 - (dst_pixel[2] as f32 / 255.0);
-
-                    (
-                        (1.0 - (inv_src_r * inv_dst_r)) * 255.0,
-                        (1.0 - (inv_src_g * inv_dst_g)) * 255.0,
-                        (1.0 - (inv_src_b * inv_dst_b)) * 255.0,
-                    )
+                    if src_a <= 0.0 {
+                        continue;
+                    }
+
+                    let dst_pixel = *pixel;
+                    let dst_a = dst_pixel[3] as f32 / 255.0;
+
+                    // Where the layer below is transparent there is nothing to blend
+                    // with, so the source shows through as if in Normal mode.
+                    let blended = |c: usize| {
+                        let (d, s) = (dst_pixel[c] as f32 / 255.0, src_pixel[c] as f32 / 255.0);
+                        (s * (1.0 - dst_a) + mode.channel(d, s) * dst_a) * 255.0
+                    };
+                    let (r, g, b) = (blended(0), blended(1), blended(2));
+
+                    let out_a = src_a + dst_a * (1.0 - src_a);
+                    let out_r = (r * src_a + dst_pixel[0] as f32 * dst_a * (1.0 - src_a)) / out_a;
+                    let out_g = (g * src_a + dst_pixel[1] as f32 * dst_a * (1.0 - src_a)) / out_a;
+                    let out_b = (b * src_a + dst_pixel[2] as f32 * dst_a * (1.0 - src_a)) / out_a;
+
+                    // Round like `color::over`, so a stroke composites to the
+                    // same value it was committed with.
+                    let to_u8 = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+                    *pixel = Rgba([
+                        to_u8(out_r),
+                        to_u8(out_g),
+                        to_u8(out_b),
+                        to_u8(out_a * 255.0),
+                    ]);
                 }
-            };
+            });
+    }
 
-            let out_a = src_a + dst_a * (1.0 - src_a);
-            let out_r = (r * src_a + dst_pixel[0] as f32 * dst_a * (1.0 - src_a)) / out_a;
-            let out_g = (g * src_a + dst_pixel[1] as f32 * dst_a * (1.0 - src_a)) / out_a;
-            let out_b = (b * src_a + dst_pixel[2] as f32 * dst_a * (1.0 - src_a)) / out_a;
+    pub fn get_composite(&mut self) -> &RgbaImage {
+        self.composite();
+        &self.composite
+    }
+
+    /// Returns an owned copy of the composite, recompositing first if needed.
+    /// Use `get_composite` on hot paths to avoid the copy.
+    pub fn render_to_rgba(&mut self) -> RgbaImage {
+        self.get_composite().clone()
+    }
 
-            *pixel = Rgba([
-                out_r.clamp(0.0, 255.0) as u8,
-                out_g.clamp(0.0, 255.0) as u8,
-                out_b.clamp(0.0, 255.0) as u8,
-                (out_a * 255.0).clamp(0.0, 255.0) as u8,
-            ]);
+    /// Composites the layers alone, leaving out `background`, for exports
+    /// that keep transparency.
+    pub fn render_transparent(&self) -> RgbaImage {
+        let mut out = ImageBuffer::new(self.width, self.height);
+        Self::composite_layers(&mut out, &self.layers, None);
+        out
+    }
+
+    /// Returns the composite scaled down so its longer side is at most
+    /// `max_dim` pixels, for previews such as a navigator or layer and tab
+    /// thumbnails. The result is cached until the composite changes.
+    // Shared infrastructure for those previews; none is wired up yet.
+    #[allow(dead_code)]
+    pub fn thumbnail(&mut self, max_dim: u32) -> RgbaImage {
+        self.composite();
+        let max_dim = max_dim.max(1);
+        match &self.thumbnail {
+            Some((dim, thumb)) if *dim == max_dim => thumb.clone(),
+            _ => {
+                let thumb = Self::box_downscale(&self.composite, max_dim);
+                self.thumbnail = Some((max_dim, thumb.clone()));
+                thumb
+            }
         }
     }
 
-    pub fn get_composite(&mut self) -> &RgbaImage {
-        if self.composite_dirty {
-            self.composite();
+    /// Box filter: each output pixel averages the source block it covers,
+    /// weighting color by alpha so transparent pixels don't darken edges.
+    fn box_downscale(src: &RgbaImage, max_dim: u32) -> RgbaImage {
+        let (w, h) = src.dimensions();
+        let longest = w.max(h);
+        if longest <= max_dim {
+            return src.clone();
         }
-        &self.composite
+        let out_w = ((w as u64 * max_dim as u64) / longest as u64).max(1) as u32;
+        let out_h = ((h as u64 * max_dim as u64) / longest as u64).max(1) as u32;
+
+        ImageBuffer::from_fn(out_w, out_h, |ox, oy| {
+            let x0 = (ox as u64 * w as u64 / out_w as u64) as u32;
+            let x1 = (((ox + 1) as u64 * w as u64 / out_w as u64) as u32).max(x0 + 1);
+            let y0 = (oy as u64 * h as u64 / out_h as u64) as u32;
+            let y1 = (((oy + 1) as u64 * h as u64 / out_h as u64) as u32).max(y0 + 1);
+
+            let mut sum = [0u64; 4];
+            for y in y0..y1.min(h) {
+                for x in x0..x1.min(w) {
+                    let p = src.get_pixel(x, y);
+                    let a = p[3] as u64;
+                    sum[0] += p[0] as u64 * a;
+                    sum[1] += p[1] as u64 * a;
+                    sum[2] += p[2] as u64 * a;
+                    sum[3] += a;
+                }
+            }
+            let count = ((x1.min(w) - x0) * (y1.min(h) - y0)) as u64;
+            if sum[3] == 0 {
+                return Rgba([0, 0, 0, 0]);
+            }
+            Rgba([
+                (sum[0] / sum[3]) as u8,
+                (sum[1] / sum[3]) as u8,
+                (sum[2] / sum[3]) as u8,
+                (sum[3] / count) as u8,
+            ])
+        })
     }
 
+    /// Saves the flattened image over the document's background color;
+    /// layers are lost. See `save_project`.
     pub fn save(&self, path: &Path) -> Result<()> {
-        // Save composite for now
-        // Ideally save .ars project file with layers
-        self.composite.save(path).context("Failed to save image")?;
-        Ok(())
+        self.save_flattened(path, self.background)
+    }
+
+    /// Saves the layers flattened onto `background`, or with their
+    /// transparency when it is `None`. Formats without alpha, such as JPEG,
+    /// are flattened onto white when no background is given.
+    pub fn save_flattened(&self, path: &Path, background: Option<Rgba<u8>>) -> Result<()> {
+        let format = image::ImageFormat::from_path(path).context("Unknown image format")?;
+        let background = match background {
+            None if format == image::ImageFormat::Jpeg => Some(Rgba([255, 255, 255, 255])),
+            other => other,
+        };
+        let mut flat = ImageBuffer::new(self.width, self.height);
+        Self::composite_layers(&mut flat, &self.layers, background);
+
+        // An opaque background leaves nothing for an alpha channel to hold.
+        if background.is_some_and(|c| c[3] == 255) {
+            image::DynamicImage::ImageRgba8(flat).to_rgb8().save(path)
+        } else {
+            flat.save(path)
+        }
+        .context("Failed to save image")
+    }
+
+    /// Saves the full layer stack as an `.ars` project, with the active tool.
+    pub fn save_project(&self, tool: &SavedTool, path: &Path) -> Result<()> {
+        crate::project::save(self, tool, path)
+    }
+
+    /// Opens an `.ars` project saved by `save_project`, with its tool.
+    pub fn load_project(path: &Path) -> Result<(Self, Option<SavedTool>)> {
+        crate::project::load(path)
+    }
+
+    /// Whether `path` names an `.ars` project rather than a flat image.
+    pub fn is_project_path(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ars"))
     }
 
     // API for tools to get raw buffer of active layer
@@ -287,58 +683,398 @@ impl ImageStore {
         }
     }
 
+    /// Selects the whole canvas.
+    pub fn select_all(&mut self) {
+        self.selection = Some(GrayImage::from_pixel(self.width, self.height, Luma([255])));
+    }
+
+    /// Expands the selection by `radius` pixels. Does nothing without one.
+    pub fn grow_selection(&mut self, radius: u32) {
+        if let Some(mask) = &mut self.selection {
+            *mask = crate::mask_ops::grow(mask, radius);
+        }
+    }
+
+    /// Contracts the selection by `radius` pixels. Edges on the canvas
+    /// border stay put.
+    pub fn shrink_selection(&mut self, radius: u32) {
+        if let Some(mask) = &mut self.selection {
+            *mask = crate::mask_ops::shrink(mask, radius);
+        }
+    }
+
+    /// Softens the selection edge with a blur of `radius` pixels, so painting
+    /// fades out across it. Does nothing without a selection.
+    pub fn feather_selection(&mut self, radius: f32) {
+        if let Some(mask) = &mut self.selection {
+            *mask = crate::mask_ops::feather(mask, radius);
+        }
+    }
+
+    /// Swaps selected and unselected areas; partial selection becomes its
+    /// complement. With no selection, everything becomes selected.
+    pub fn invert_selection(&mut self) {
+        match &mut self.selection {
+            Some(mask) => {
+                for p in mask.pixels_mut() {
+                    p[0] = 255 - p[0];
+                }
+            }
+            None => self.select_all(),
+        }
+    }
+
+    /// The visible image within the selection's bounding box, with pixels
+    /// outside the mask cleared and feathered edges faded by the mask value.
+    /// Without a selection this is the whole composite. Returns `None` if the
+    /// selection is empty.
+    pub fn copy_selection(&mut self) -> Option<RgbaImage> {
+        let (x0, y0, x1, y1) = match self.selection {
+            Some(_) => self.selection_bounds()?,
+            None => (0, 0, self.width - 1, self.height - 1),
+        };
+        let mut out = self
+            .get_composite()
+            .view(x0, y0, x1 - x0 + 1, y1 - y0 + 1)
+            .to_image();
+        if let Some(mask) = &self.selection {
+            for (x, y, p) in out.enumerate_pixels_mut() {
+                let m = mask.get_pixel(x0 + x, y0 + y)[0] as u32;
+                p[3] = ((p[3] as u32 * m + 127) / 255) as u8;
+            }
+        }
+        Some(out)
+    }
+
+    /// Inclusive pixel bounds `(min_x, min_y, max_x, max_y)` of the selected
+    /// area, or `None` if there is no selection or the mask is empty.
+    pub fn selection_bounds(&self) -> Option<(u32, u32, u32, u32)> {
+        let mask = self.selection.as_ref()?;
+        let mut min_x = mask.width();
+        let mut max_x = 0;
+        let mut min_y = mask.height();
+        let mut max_y = 0;
+        let mut found = false;
+
+        for (x, y, p) in mask.enumerate_pixels() {
+            if p[0] > 0 {
+                min_x = min_x.min(x);
+                max_x = max_x.max(x);
+                min_y = min_y.min(y);
+                max_y = max_y.max(y);
+                found = true;
+            }
+        }
+
+        found.then_some((min_x, min_y, max_x, max_y))
+    }
+
+    /// Swaps every pixel of the active layer that exactly equals `from` for
+    /// `to`, limited to the selection if there is one. Returns the undo patch
+    /// covering the changed pixels, or `None` if nothing matched.
+    pub fn replace_color(&mut self, from: Rgba<u8>, to: Rgba<u8>) -> Option<PatchCommand> {
+        if from == to {
+            return None;
+        }
+        let layer_index = self.active_layer;
+        let selection = &self.selection;
+        let target = match &mut self.layers.get_mut(layer_index)?.data {
+            LayerData::Raster(img) => img,
+            LayerData::Tone { buffer, .. } => buffer,
+            _ => return None,
+        };
+
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for (x, y, p) in target.enumerate_pixels() {
+            let selected = selection.as_ref().is_none_or(|m| m.get_pixel(x, y)[0] > 0);
+            if *p == from && selected {
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+        }
+        let (x0, y0, x1, y1) = bounds?;
+        let (w, h) = (x1 - x0 + 1, y1 - y0 + 1);
+
+        let old_patch = target.view(x0, y0, w, h).to_image();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let selected = selection.as_ref().is_none_or(|m| m.get_pixel(x, y)[0] > 0);
+                if selected && *target.get_pixel(x, y) == from {
+                    target.put_pixel(x, y, to);
+                }
+            }
+        }
+        let new_patch = target.view(x0, y0, w, h).to_image();
+        self.mark_dirty();
+
+        Some(PatchCommand {
+            name: "Replace Color".to_string(),
+            layer_index,
+            x: x0,
+            y: y0,
+            old_patch,
+            new_patch,
+        })
+    }
+
+    /// Runs every pixel of the active layer through `adjust`, honoring the
+    /// layer's channel locks. With a selection, each result is blended with
+    /// the original by the mask value so feathered edges fade out smoothly.
+    /// Returns the undo patch, or `None` if no pixel changed.
+    pub fn adjust_active(
+        &mut self,
+        name: &str,
+        adjust: impl Fn(Rgba<u8>) -> Rgba<u8>,
+    ) -> Option<PatchCommand> {
+        self.adjust_active_at(name, |_, _, p| adjust(p))
+    }
+
+    /// Like `adjust_active`, but `adjust` also gets each pixel's position.
+    pub fn adjust_active_at(
+        &mut self,
+        name: &str,
+        adjust: impl Fn(u32, u32, Rgba<u8>) -> Rgba<u8>,
+    ) -> Option<PatchCommand> {
+        if self.active_layer_is_locked() {
+            return None;
+        }
+        let (x0, y0, x1, y1) = match self.selection {
+            Some(_) => self.selection_bounds()?,
+            None => (
+                0,
+                0,
+                self.width.checked_sub(1)?,
+                self.height.checked_sub(1)?,
+            ),
+        };
+        let layer_index = self.active_layer;
+        let selection = &self.selection;
+        let layer = self.layers.get_mut(layer_index)?;
+        let channel_lock = layer.channel_lock;
+        let target = match &mut layer.data {
+            LayerData::Raster(img) => img,
+            LayerData::Tone { buffer, .. } => buffer,
+            _ => return None,
+        };
+        let (w, h) = (x1 - x0 + 1, y1 - y0 + 1);
+
+        let old_patch = target.view(x0, y0, w, h).to_image();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let weight = selection.as_ref().map_or(255, |m| m.get_pixel(x, y)[0]);
+                if weight == 0 {
+                    continue;
+                }
+                let existing = *target.get_pixel(x, y);
+                let mut adjusted = adjust(x, y, existing);
+                if weight < 255 {
+                    let t = weight as f32 / 255.0;
+                    for c in 0..4 {
+                        adjusted[c] = (existing[c] as f32
+                            + (adjusted[c] as f32 - existing[c] as f32) * t)
+                            .round() as u8;
+                    }
+                }
+                target.put_pixel(
+                    x,
+                    y,
+                    crate::layers::lock_channels(channel_lock, existing, adjusted),
+                );
+            }
+        }
+        let new_patch = target.view(x0, y0, w, h).to_image();
+        if new_patch == old_patch {
+            return None;
+        }
+        self.mark_dirty_rect(x0, y0, w, h);
+
+        Some(PatchCommand {
+            name: name.to_string(),
+            layer_index,
+            x: x0,
+            y: y0,
+            old_patch,
+            new_patch,
+        })
+    }
+
+    /// Sharpens the active layer with an unsharp mask: a gaussian blur of
+    /// `radius` pixels is subtracted, scaled by `amount`. Honors the
+    /// selection like `adjust_active`.
+    pub fn sharpen_active(&mut self, radius: f32, amount: f32) -> Option<PatchCommand> {
+        let blurred = match &self.active_layer()?.data {
+            LayerData::Raster(img) | LayerData::Tone { buffer: img, .. } => {
+                imageops::blur(img, radius.max(0.1))
+            }
+            LayerData::Vector(_) => return None,
+        };
+        self.adjust_active_at("Sharpen", |x, y, p| {
+            crate::adjustments::unsharp(p, *blurred.get_pixel(x, y), amount)
+        })
+    }
+
+    /// Mirrors the `(x, y, width, height)` region of each layer in `layers`,
+    /// and of the selection too if `selection` is set. Flipping twice
+    /// restores the original, which is how `FlipCommand` undoes it.
+    pub fn flip(
+        &mut self,
+        axis: FlipAxis,
+        layers: &[usize],
+        region: (u32, u32, u32, u32),
+        selection: bool,
+    ) {
+        let canvas = (self.width, self.height);
+        for &index in layers {
+            if let Some(layer) = self.layers.get_mut(index) {
+                layer.flip(axis, region, canvas);
+            }
+        }
+        if let Some(mask) = self.selection.as_mut().filter(|_| selection) {
+            flip_region(mask, axis, region);
+        }
+        self.mark_dirty();
+    }
+
+    pub fn snapshot(&self) -> DocumentSnapshot {
+        DocumentSnapshot {
+            width: self.width,
+            height: self.height,
+            layers: self.layers.clone(),
+            active_layer: self.active_layer,
+            selection: self.selection.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &DocumentSnapshot) {
+        self.width = snapshot.width;
+        self.height = snapshot.height;
+        self.layers = snapshot.layers.clone();
+        self.active_layer = snapshot.active_layer;
+        self.selection = snapshot.selection.clone();
+        self.composite = ImageBuffer::new(self.width, self.height);
+        self.mark_dirty();
+    }
+
+    pub fn set_background(&mut self, background: Option<Rgba<u8>>) {
+        if self.background != background {
+            self.background = background;
+            self.composite_dirty = true;
+        }
+    }
+
     pub fn mark_dirty(&mut self) {
         self.composite_dirty = true;
     }
 
-    pub fn resize(&mut self, new_width: u32, new_height: u32) {
+    /// Marks only the `width`×`height` area at (`x`, `y`) for recompositing,
+    /// merged with any area already pending.
+    pub fn mark_dirty_rect(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let x1 = x.saturating_add(width).min(self.width);
+        let y1 = y.saturating_add(height).min(self.height);
+        if x >= x1 || y >= y1 {
+            return;
+        }
+        self.dirty_rect = Some(union_rect(self.dirty_rect, (x, y, x1 - x, y1 - y)));
+    }
+
+    /// The composite area rebuilt since the last call, if any, clearing it.
+    /// The whole canvas after a full recomposite.
+    pub fn take_composite_changes(&mut self) -> Option<(u32, u32, u32, u32)> {
+        self.changed_rect.take()
+    }
+
+    /// Changes the canvas size without scaling anything. `anchor` is the
+    /// edge or corner the existing pixels stay against: content past the new
+    /// edges is cropped, and new area is transparent on every layer, so the
+    /// document background shows through it. Masks are padded revealed.
+    pub fn resize_canvas(&mut self, new_width: u32, new_height: u32, anchor: egui::Align2) {
         if new_width == self.width && new_height == self.height {
             return;
         }
 
-        for (idx, layer) in self.layers.iter_mut().enumerate() {
+        let offset = |old: u32, new: u32, align: egui::Align| {
+            let extra = new as i64 - old as i64;
+            match align {
+                egui::Align::Min => 0,
+                egui::Align::Center => extra / 2,
+                egui::Align::Max => extra,
+            }
+        };
+        let dx = offset(self.width, new_width, anchor.x());
+        let dy = offset(self.height, new_height, anchor.y());
+
+        fn place<P: image::Pixel>(
+            old: &ImageBuffer<P, Vec<P::Subpixel>>,
+            fill: P,
+            (width, height): (u32, u32),
+            (dx, dy): (i64, i64),
+        ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+            let mut new = ImageBuffer::from_pixel(width, height, fill);
+            image::imageops::replace(&mut new, old, dx, dy);
+            new
+        }
+        let size = (new_width, new_height);
+
+        for layer in &mut self.layers {
             match &mut layer.data {
-                LayerData::Raster(ref mut img) => {
-                    let mut new_img = ImageBuffer::new(new_width, new_height);
-                    if idx == 0 {
-                        for p in new_img.pixels_mut() {
-                            *p = Rgba([255, 255, 255, 255]);
-                        }
-                    }
-                    let copy_w = self.width.min(new_width);
-                    let copy_h = self.height.min(new_height);
-                    for y in 0..copy_h {
-                        for x in 0..copy_w {
-                            new_img.put_pixel(x, y, *img.get_pixel(x, y));
-                        }
-                    }
-                    *img = new_img;
+                // New canvas area is transparent; the document background
+                // shows through it.
+                LayerData::Raster(img) | LayerData::Tone { buffer: img, .. } => {
+                    *img = place(img, Rgba([0, 0, 0, 0]), size, (dx, dy));
                 }
-                LayerData::Tone { ref mut buffer, .. } => {
-                    let mut new_img = ImageBuffer::new(new_width, new_height);
-                    let copy_w = self.width.min(new_width);
-                    let copy_h = self.height.min(new_height);
-                    for y in 0..copy_h {
-                        for x in 0..copy_w {
-                            new_img.put_pixel(x, y, *buffer.get_pixel(x, y));
-                        }
+                LayerData::Vector(shapes) => {
+                    let shift = egui::vec2(dx as f32, dy as f32);
+                    for shape in shapes {
+                        shape.translate(shift);
                     }
-                    *buffer = new_img;
                 }
-                _ => {}
+            }
+            // New canvas area starts revealed.
+            if let Some(mask) = &mut layer.mask {
+                *mask = place(mask, Luma([255]), size, (dx, dy));
             }
         }
 
         if let Some(mask) = &mut self.selection {
-            let mut new_mask = ImageBuffer::new(new_width, new_height);
-            let copy_w = self.width.min(new_width);
-            let copy_h = self.height.min(new_height);
-            for y in 0..copy_h {
-                for x in 0..copy_w {
-                    new_mask.put_pixel(x, y, *mask.get_pixel(x, y));
+            *mask = place(mask, Luma([0]), size, (dx, dy));
+        }
+
+        self.width = new_width;
+        self.height = new_height;
+        self.composite = ImageBuffer::new(new_width, new_height);
+        self.mark_dirty();
+    }
+
+    /// Resamples every layer, mask and the selection to
+    /// `new_width`×`new_height` with `filter`. Vector shapes are scaled to
+    /// match.
+    pub fn scale_image(&mut self, new_width: u32, new_height: u32, filter: FilterType) {
+        if new_width == self.width && new_height == self.height {
+            return;
+        }
+        let sx = new_width as f32 / self.width as f32;
+        let sy = new_height as f32 / self.height as f32;
+
+        for layer in &mut self.layers {
+            match &mut layer.data {
+                LayerData::Raster(img) | LayerData::Tone { buffer: img, .. } => {
+                    *img = imageops::resize(img, new_width, new_height, filter);
+                }
+                LayerData::Vector(shapes) => {
+                    for shape in shapes {
+                        shape.scale(sx, sy);
+                    }
                 }
             }
-            *mask = new_mask;
+            if let Some(mask) = &mut layer.mask {
+                *mask = imageops::resize(mask, new_width, new_height, filter);
+            }
+        }
+
+        if let Some(mask) = &mut self.selection {
+            *mask = imageops::resize(mask, new_width, new_height, filter);
         }
 
         self.width = new_width;
@@ -346,4 +1082,326 @@ impl ImageStore {
         self.composite = ImageBuffer::new(new_width, new_height);
         self.mark_dirty();
     }
+
+    /// Rotates every layer, mask and the selection clockwise by
+    /// `quarter_turns` × 90°, swapping width and height for odd turns.
+    pub fn rotate(&mut self, quarter_turns: u32) {
+        let turns = quarter_turns % 4;
+        if turns == 0 {
+            return;
+        }
+        fn turn<P: image::Pixel + 'static>(
+            buffer: &ImageBuffer<P, Vec<P::Subpixel>>,
+            turns: u32,
+        ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+            match turns {
+                1 => imageops::rotate90(buffer),
+                2 => imageops::rotate180(buffer),
+                _ => imageops::rotate270(buffer),
+            }
+        }
+
+        for layer in &mut self.layers {
+            match &mut layer.data {
+                LayerData::Raster(img) | LayerData::Tone { buffer: img, .. } => {
+                    *img = turn(img, turns);
+                }
+                LayerData::Vector(shapes) => {
+                    for shape in shapes {
+                        shape.rotate(turns, self.width, self.height);
+                    }
+                }
+            }
+            if let Some(mask) = &mut layer.mask {
+                *mask = turn(mask, turns);
+            }
+        }
+
+        if let Some(mask) = &mut self.selection {
+            *mask = turn(mask, turns);
+        }
+
+        if turns % 2 == 1 {
+            std::mem::swap(&mut self.width, &mut self.height);
+        }
+        self.composite = ImageBuffer::new(self.width, self.height);
+        self.mark_dirty();
+    }
+
+    /// Cuts every layer and the selection down to the `width`×`height`
+    /// region at (`x`, `y`). The region must lie inside the canvas.
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        if width == 0 || height == 0 || x + width > self.width || y + height > self.height {
+            return;
+        }
+
+        for layer in &mut self.layers {
+            match &mut layer.data {
+                LayerData::Raster(img) | LayerData::Tone { buffer: img, .. } => {
+                    *img = img.view(x, y, width, height).to_image();
+                }
+                LayerData::Vector(shapes) => {
+                    let shift = egui::vec2(-(x as f32), -(y as f32));
+                    for shape in shapes {
+                        shape.translate(shift);
+                    }
+                }
+            }
+            if let Some(mask) = &mut layer.mask {
+                *mask = mask.view(x, y, width, height).to_image();
+            }
+        }
+
+        if let Some(mask) = &mut self.selection {
+            *mask = mask.view(x, y, width, height).to_image();
+        }
+
+        self.width = width;
+        self.height = height;
+        self.composite = ImageBuffer::new(width, height);
+        self.mark_dirty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    /// A 4×1 raster layer with the given pixels, left to right.
+    fn layer(pixels: [Rgba<u8>; 4], blend: BlendMode, clipped: bool) -> Layer {
+        let mut layer = Layer::new_raster(4, 1, String::new());
+        layer.data = LayerData::Raster(ImageBuffer::from_fn(4, 1, |x, _| pixels[x as usize]));
+        layer.blend = blend;
+        layer.clipped = clipped;
+        layer
+    }
+
+    #[test]
+    fn clipped_run_shares_the_base_under_multiply_line_art() {
+        let gray = Rgba([200, 200, 200, 255]);
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let ink = Rgba([128, 128, 128, 255]);
+        let layers = vec![
+            // Base fill covers the left half only.
+            layer([gray, gray, CLEAR, CLEAR], BlendMode::Normal, false),
+            layer([red; 4], BlendMode::Normal, true),
+            // Clips to the base, not to the red layer under it, so it is
+            // hidden at x = 3 even though red is opaque there.
+            layer([blue, CLEAR, CLEAR, blue], BlendMode::Normal, true),
+            layer([ink, CLEAR, ink, CLEAR], BlendMode::Multiply, false),
+        ];
+        let store = ImageStore::from_layers(4, 1, layers, 0);
+
+        let out = store.render_transparent();
+        assert_eq!(*out.get_pixel(0, 0), Rgba([0, 0, 128, 255]));
+        assert_eq!(*out.get_pixel(1, 0), red);
+        // Line art over nothing shows as itself.
+        assert_eq!(*out.get_pixel(2, 0), ink);
+        assert_eq!(*out.get_pixel(3, 0), CLEAR);
+    }
+
+    #[test]
+    fn difference_and_subtract_blend_solid_layers() {
+        let below = Rgba([200, 100, 50, 255]);
+        let above = Rgba([100, 150, 50, 255]);
+        let blend = |mode, opacity| {
+            let mut top = layer([above; 4], mode, false);
+            top.opacity = opacity;
+            let layers = vec![layer([below; 4], BlendMode::Normal, false), top];
+            *ImageStore::from_layers(4, 1, layers, 0)
+                .render_transparent()
+                .get_pixel(0, 0)
+        };
+
+        assert_eq!(blend(BlendMode::Difference, 1.0), Rgba([100, 50, 0, 255]));
+        assert_eq!(blend(BlendMode::Subtract, 1.0), Rgba([100, 0, 0, 255]));
+        // Opacity mixes the blended color back toward the layer below.
+        assert_eq!(blend(BlendMode::Difference, 0.5), Rgba([150, 75, 25, 255]));
+        assert_eq!(blend(BlendMode::Subtract, 0.5), Rgba([150, 50, 25, 255]));
+    }
+
+    #[test]
+    fn resize_canvas_pads_every_layer_with_transparency() {
+        let red = Rgba([255, 0, 0, 255]);
+        let layers = vec![
+            layer([red; 4], BlendMode::Normal, false),
+            layer([red; 4], BlendMode::Normal, false),
+        ];
+        let mut store = ImageStore::from_layers(4, 1, layers, 0);
+
+        store.resize_canvas(6, 2, egui::Align2::LEFT_TOP);
+        let out = store.render_transparent();
+        for (x, y, p) in out.enumerate_pixels() {
+            assert_eq!(
+                *p,
+                if x < 4 && y == 0 { red } else { CLEAR },
+                "({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn parallel_rows_blend_each_pixel_like_over() {
+        // Every pixel differs, so a row landing at the wrong offset shows.
+        let (w, h) = (7, 5);
+        let under =
+            |x: u32, y: u32| Rgba([(x * 30) as u8, (y * 50) as u8, 90, (40 + x * y * 6) as u8]);
+        let top = |x: u32, y: u32| {
+            Rgba([
+                200,
+                (x * 20) as u8,
+                (y * 40) as u8,
+                (20 + (x + y) * 20) as u8,
+            ])
+        };
+        let mut bottom = Layer::new_raster(w, h, String::new());
+        bottom.data = LayerData::Raster(ImageBuffer::from_fn(w, h, under));
+        let mut upper = Layer::new_raster(w, h, String::new());
+        upper.data = LayerData::Raster(ImageBuffer::from_fn(w, h, top));
+        let mut store = ImageStore::from_layers(w, h, vec![bottom, upper], 0);
+
+        let composite = store.get_composite();
+        for (x, y, &pixel) in composite.enumerate_pixels() {
+            assert_eq!(
+                pixel,
+                crate::color::over(under(x, y), top(x, y)),
+                "at ({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn thumbnail_box_filters_and_follows_edits() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        // Left half red, right half blue, with a transparent top-right quarter.
+        let mut base = Layer::new_raster(8, 4, String::new());
+        base.data = LayerData::Raster(ImageBuffer::from_fn(8, 4, |x, y| match (x < 4, y < 2) {
+            (true, _) => red,
+            (false, true) => CLEAR,
+            (false, false) => blue,
+        }));
+        let mut store = ImageStore::from_layers(8, 4, vec![base], 0);
+
+        let thumb = store.thumbnail(2);
+        assert_eq!(thumb.dimensions(), (2, 1));
+        assert_eq!(*thumb.get_pixel(0, 0), red);
+        // Transparent pixels halve the alpha without darkening the color.
+        assert_eq!(*thumb.get_pixel(1, 0), Rgba([0, 0, 255, 127]));
+
+        let LayerData::Raster(pixels) = &mut store.layers[0].data else {
+            unreachable!();
+        };
+        for y in 0..4 {
+            for x in 0..4 {
+                pixels.put_pixel(x, y, blue);
+            }
+        }
+        store.mark_dirty_rect(0, 0, 4, 4);
+        assert_eq!(*store.thumbnail(2).get_pixel(0, 0), blue);
+    }
+
+    #[test]
+    fn partial_composite_matches_a_full_one() {
+        let gradient = |x: u32, y: u32| Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]);
+        let mut base = Layer::new_raster(32, 32, String::new());
+        base.data = LayerData::Raster(ImageBuffer::from_fn(32, 32, gradient));
+        let mut tone = Layer::new_tone(32, 32, String::new());
+        tone.data = LayerData::Tone {
+            buffer: ImageBuffer::from_fn(32, 32, |x, _| Rgba([0, 0, 0, (x * 8) as u8])),
+            frequency: 0.25,
+            density: 1.0,
+        };
+        let mut shapes = Layer::new_vector(String::new());
+        shapes.clipped = true;
+        shapes.blend = BlendMode::Multiply;
+        shapes.data = LayerData::Vector(vec![crate::layers::VectorShape::Ellipse {
+            rect: egui::Rect::from_min_max(egui::pos2(4.0, 4.0), egui::pos2(28.0, 20.0)),
+            color: Rgba([255, 0, 0, 200]),
+            width: 2.0,
+            fill: true,
+        }]);
+        let mut store = ImageStore::from_layers(32, 32, vec![base, tone, shapes], 0);
+        store.get_composite();
+
+        // Repaint a patch of the base, under the tone layer and the shapes
+        // clipped to it.
+        let LayerData::Raster(pixels) = &mut store.layers[0].data else {
+            unreachable!();
+        };
+        for y in 10..18 {
+            for x in 6..26 {
+                pixels.put_pixel(x, y, Rgba([255, 255, 0, 255]));
+            }
+        }
+        store.mark_dirty_rect(6, 10, 20, 8);
+        let partial = store.get_composite().clone();
+
+        store.mark_dirty();
+        assert!(partial == *store.get_composite());
+    }
+
+    #[test]
+    fn crop_moves_vector_shapes_with_the_pixels() {
+        let mut vector = Layer::new_vector("Shapes".to_string());
+        vector.data = LayerData::Vector(vec![crate::layers::VectorShape::Rectangle {
+            rect: egui::Rect::from_min_max(egui::pos2(5.0, 6.0), egui::pos2(9.0, 8.0)),
+            color: Rgba([255, 0, 0, 255]),
+            width: 1.0,
+            fill: true,
+        }]);
+        let mut store = ImageStore::from_layers(16, 16, vec![vector], 0);
+
+        store.crop(4, 2, 8, 8);
+        let LayerData::Vector(shapes) = &store.layers[0].data else {
+            unreachable!();
+        };
+        let crate::layers::VectorShape::Rectangle { rect, .. } = shapes[0] else {
+            unreachable!();
+        };
+        assert_eq!(
+            rect,
+            egui::Rect::from_min_max(egui::pos2(1.0, 4.0), egui::pos2(5.0, 6.0))
+        );
+    }
+
+    #[test]
+    fn merge_visible_keeps_layers_clipped_to_a_hidden_base() {
+        let red = Rgba([255, 0, 0, 255]);
+        let mut hidden = layer([Rgba([200, 200, 200, 255]); 4], BlendMode::Normal, false);
+        hidden.visible = false;
+        let layers = vec![
+            hidden,
+            layer([red; 4], BlendMode::Normal, true),
+            layer([red, CLEAR, CLEAR, CLEAR], BlendMode::Normal, false),
+            layer([CLEAR, red, CLEAR, CLEAR], BlendMode::Normal, false),
+        ];
+        let mut store = ImageStore::from_layers(4, 1, layers, 0);
+        let before = store.render_transparent();
+
+        assert!(store.merge_visible());
+        assert_eq!(store.layers.len(), 3);
+        assert!(!store.layers[0].visible);
+        assert!(store.layers[1].clipped);
+        assert_eq!(store.layers[2].name, "Merged");
+        assert_eq!(store.render_transparent(), before);
+    }
+
+    #[test]
+    fn hiding_the_base_hides_its_clipped_layers() {
+        let mut base = layer([Rgba([200, 200, 200, 255]); 4], BlendMode::Normal, false);
+        base.visible = false;
+        let layers = vec![
+            base,
+            layer([Rgba([255, 0, 0, 255]); 4], BlendMode::Normal, true),
+        ];
+        let store = ImageStore::from_layers(4, 1, layers, 0);
+
+        assert!(store.render_transparent().pixels().all(|p| *p == CLEAR));
+    }
 }
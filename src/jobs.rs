@@ -0,0 +1,37 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// A unit of work running on a background thread so the UI stays responsive.
+/// The UI polls it once per frame and shows a busy overlay until it finishes.
+pub struct Job<T> {
+    label: String,
+    receiver: Receiver<T>,
+}
+
+impl<T: Send + 'static> Job<T> {
+    pub fn spawn(label: impl Into<String>, work: impl FnOnce() -> T + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The receiver may be gone if the app is closing; nothing to do then.
+            let _ = sender.send(work());
+        });
+        Self {
+            label: label.into(),
+            receiver,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the result once the work has finished. A worker that panicked
+    /// is reported as `Err(())` so the caller can drop the job.
+    pub fn poll(&self) -> Option<Result<T, ()>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(Ok(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(())),
+        }
+    }
+}
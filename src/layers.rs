@@ -1,13 +1,54 @@
 use egui::{Pos2, Rect};
-use image::{ImageBuffer, Rgba, RgbaImage};
-// use serde::{Deserialize, Serialize}; // Optional, but good practice
+use image::{GrayImage, ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum BlendMode {
     Normal,
     Multiply,
     Add,
     Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+    Subtract,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 9] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Add,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::Difference,
+        BlendMode::Subtract,
+    ];
+
+    /// Blends one channel of `src` onto `dst`, both in 0..=1.
+    pub fn channel(self, dst: f32, src: f32) -> f32 {
+        match self {
+            BlendMode::Normal => src,
+            BlendMode::Multiply => dst * src,
+            BlendMode::Add => (dst + src).min(1.0),
+            BlendMode::Screen => 1.0 - (1.0 - dst) * (1.0 - src),
+            // Multiply in the darks and Screen in the lights of the layer below.
+            BlendMode::Overlay => {
+                if dst < 0.5 {
+                    2.0 * dst * src
+                } else {
+                    1.0 - 2.0 * (1.0 - dst) * (1.0 - src)
+                }
+            }
+            BlendMode::Darken => dst.min(src),
+            BlendMode::Lighten => dst.max(src),
+            BlendMode::Difference => (dst - src).abs(),
+            BlendMode::Subtract => (dst - src).max(0.0),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -32,6 +73,293 @@ pub enum VectorShape {
     },
 }
 
+/// Mirror direction: `Horizontal` swaps left and right, `Vertical` top and
+/// bottom.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FlipAxis {
+    Horizontal,
+    Vertical,
+}
+
+/// Mirrors the `(x, y, width, height)` region of `buffer` in place.
+pub fn flip_region<P: Pixel>(
+    buffer: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    axis: FlipAxis,
+    (x, y, width, height): (u32, u32, u32, u32),
+) {
+    use image::GenericImage;
+    let mut region = buffer.sub_image(x, y, width, height);
+    match axis {
+        FlipAxis::Horizontal => image::imageops::flip_horizontal_in_place(&mut *region),
+        FlipAxis::Vertical => image::imageops::flip_vertical_in_place(&mut *region),
+    }
+}
+
+impl VectorShape {
+    /// Mirrors the shape across the middle of a `width` x `height` canvas.
+    pub fn flip(&mut self, axis: FlipAxis, width: u32, height: u32) {
+        let mirror = |p: Pos2| match axis {
+            FlipAxis::Horizontal => Pos2::new(width as f32 - p.x, p.y),
+            FlipAxis::Vertical => Pos2::new(p.x, height as f32 - p.y),
+        };
+        match self {
+            VectorShape::Line { start, end, .. } => {
+                *start = mirror(*start);
+                *end = mirror(*end);
+            }
+            VectorShape::Rectangle { rect, .. } | VectorShape::Ellipse { rect, .. } => {
+                *rect = Rect::from_two_pos(mirror(rect.min), mirror(rect.max));
+            }
+        }
+    }
+
+    /// Rotates the shape clockwise by `quarter_turns` × 90° on a `width` x
+    /// `height` canvas, keeping it on the rotated canvas.
+    pub fn rotate(&mut self, quarter_turns: u32, width: u32, height: u32) {
+        let (w, h) = (width as f32, height as f32);
+        let turn = |p: Pos2| match quarter_turns % 4 {
+            1 => Pos2::new(h - p.y, p.x),
+            2 => Pos2::new(w - p.x, h - p.y),
+            3 => Pos2::new(p.y, w - p.x),
+            _ => p,
+        };
+        match self {
+            VectorShape::Line { start, end, .. } => {
+                *start = turn(*start);
+                *end = turn(*end);
+            }
+            VectorShape::Rectangle { rect, .. } | VectorShape::Ellipse { rect, .. } => {
+                *rect = Rect::from_two_pos(turn(rect.min), turn(rect.max));
+            }
+        }
+    }
+
+    /// Moves the shape by `offset` pixels.
+    pub fn translate(&mut self, offset: egui::Vec2) {
+        match self {
+            VectorShape::Line { start, end, .. } => {
+                *start += offset;
+                *end += offset;
+            }
+            VectorShape::Rectangle { rect, .. } | VectorShape::Ellipse { rect, .. } => {
+                *rect = rect.translate(offset);
+            }
+        }
+    }
+
+    /// Scales the shape about the origin, stroke width by the mean factor.
+    pub fn scale(&mut self, sx: f32, sy: f32) {
+        let factor = egui::vec2(sx, sy);
+        match self {
+            VectorShape::Line {
+                start, end, width, ..
+            } => {
+                *start = (start.to_vec2() * factor).to_pos2();
+                *end = (end.to_vec2() * factor).to_pos2();
+                *width *= (sx + sy) / 2.0;
+            }
+            VectorShape::Rectangle { rect, width, .. }
+            | VectorShape::Ellipse { rect, width, .. } => {
+                *rect = Rect::from_min_max(
+                    (rect.min.to_vec2() * factor).to_pos2(),
+                    (rect.max.to_vec2() * factor).to_pos2(),
+                );
+                *width *= (sx + sy) / 2.0;
+            }
+        }
+    }
+
+    fn style(&self) -> (Rgba<u8>, f32) {
+        match *self {
+            VectorShape::Line { color, width, .. }
+            | VectorShape::Rectangle { color, width, .. }
+            | VectorShape::Ellipse { color, width, .. } => (color, width),
+        }
+    }
+
+    /// The area the shape can paint, stroke included.
+    pub fn bounds(&self) -> Rect {
+        let (_, width) = self.style();
+        let outline = match *self {
+            VectorShape::Line { start, end, .. } => Rect::from_two_pos(start, end),
+            VectorShape::Rectangle { rect, .. } | VectorShape::Ellipse { rect, .. } => rect,
+        };
+        outline.expand(width + 1.0)
+    }
+
+    /// Draws the shape over `target`. Strokes are stamped the way the Line,
+    /// Rectangle and Ellipse tools stamp them, with `width` as the radius.
+    pub fn rasterize(&self, target: &mut RgbaImage) {
+        self.rasterize_at(target, (0, 0));
+    }
+
+    /// Like `rasterize`, for a `target` that holds only the part of the
+    /// canvas starting at `origin`.
+    pub fn rasterize_at(&self, target: &mut RgbaImage, origin: (u32, u32)) {
+        let (color, width) = self.style();
+        let window = Rect::from_min_size(
+            Pos2::new(origin.0 as f32, origin.1 as f32),
+            egui::vec2(target.width() as f32, target.height() as f32),
+        );
+        let area = self.bounds().intersect(window);
+        let area = Rect::from_min_max(area.min.floor(), area.max.ceil());
+        if area.width() < 1.0 || area.height() < 1.0 {
+            return;
+        }
+
+        // Coverage first, then one blend per pixel, so overlapping stamps of
+        // a translucent color don't build up.
+        let mut cover = Coverage {
+            mask: GrayImage::new(area.width() as u32, area.height() as u32),
+            origin: (area.min.x as i32, area.min.y as i32),
+        };
+        match *self {
+            VectorShape::Line { start, end, .. } => cover.segment(start, end, width),
+            VectorShape::Rectangle { rect, fill, .. } => {
+                if fill {
+                    cover.fill(|p| rect.contains(p));
+                }
+                let [tl, tr, br, bl] = [
+                    rect.left_top(),
+                    rect.right_top(),
+                    rect.right_bottom(),
+                    rect.left_bottom(),
+                ];
+                cover.segment(tl, tr, width);
+                cover.segment(tr, br, width);
+                cover.segment(br, bl, width);
+                cover.segment(bl, tl, width);
+            }
+            VectorShape::Ellipse { rect, fill, .. } => {
+                let center = rect.center();
+                let (rx, ry) = (rect.width() / 2.0, rect.height() / 2.0);
+                if fill && rx > 0.0 && ry > 0.0 {
+                    cover.fill(|p| {
+                        let (dx, dy) = ((p.x - center.x) / rx, (p.y - center.y) / ry);
+                        dx * dx + dy * dy <= 1.0
+                    });
+                }
+                let circ = std::f32::consts::TAU * ((rx * rx + ry * ry) / 2.0).sqrt();
+                let steps = circ.max(10.0) as u32;
+                for i in 0..=steps {
+                    let t = i as f32 / steps as f32 * std::f32::consts::TAU;
+                    cover.disc(center + egui::vec2(rx * t.cos(), ry * t.sin()), width);
+                }
+            }
+        }
+
+        let (ox, oy) = cover.origin;
+        for (lx, ly, c) in cover.mask.enumerate_pixels() {
+            if c[0] > 0 {
+                let (x, y) = (ox as u32 + lx - origin.0, oy as u32 + ly - origin.1);
+                let existing = *target.get_pixel(x, y);
+                target.put_pixel(x, y, crate::color::over(existing, color));
+            }
+        }
+    }
+}
+
+/// Renders `shapes` in order onto a transparent buffer covering the
+/// `(x, y, width, height)` area of the canvas.
+pub fn rasterize_shapes(
+    shapes: &[VectorShape],
+    (x, y, width, height): (u32, u32, u32, u32),
+) -> RgbaImage {
+    let mut buffer = ImageBuffer::new(width, height);
+    for shape in shapes {
+        shape.rasterize_at(&mut buffer, (x, y));
+    }
+    buffer
+}
+
+/// Pixels a shape covers, within a window of the canvas at `origin`.
+struct Coverage {
+    mask: GrayImage,
+    origin: (i32, i32),
+}
+
+impl Coverage {
+    fn mark(&mut self, x: i32, y: i32) {
+        let (lx, ly) = (x - self.origin.0, y - self.origin.1);
+        if lx >= 0 && ly >= 0 && (lx as u32) < self.mask.width() && (ly as u32) < self.mask.height()
+        {
+            self.mask.put_pixel(lx as u32, ly as u32, Luma([255]));
+        }
+    }
+
+    fn disc(&mut self, center: Pos2, radius: f32) {
+        let (x, y) = (center.x as i32, center.y as i32);
+        let r = radius as i32;
+        for cy in y - r..=y + r {
+            for cx in x - r..=x + r {
+                if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r * r {
+                    self.mark(cx, cy);
+                }
+            }
+        }
+    }
+
+    fn segment(&mut self, start: Pos2, end: Pos2, radius: f32) {
+        let steps = start.distance(end).max(1.0) as u32;
+        for i in 0..=steps {
+            self.disc(start.lerp(end, i as f32 / steps as f32), radius);
+        }
+    }
+
+    fn fill(&mut self, inside: impl Fn(Pos2) -> bool) {
+        let (ox, oy) = self.origin;
+        for (lx, ly, p) in self.mask.enumerate_pixels_mut() {
+            let pos = Pos2::new((ox + lx as i32) as f32, (oy + ly as i32) as f32);
+            if inside(pos) {
+                *p = Luma([255]);
+            }
+        }
+    }
+}
+
+/// Turns a tone layer's painted buffer into a screentone: a 45° grid of dots,
+/// `frequency` per pixel, sized so the inked share of each cell is the
+/// paint's darkness times its alpha times `density`. Dots take the paint's
+/// color and have soft one-pixel edges. Only the `(x, y, width, height)`
+/// area is screened, so the result is that size.
+pub fn halftone(
+    buffer: &RgbaImage,
+    frequency: f32,
+    density: f32,
+    (ox, oy, width, height): (u32, u32, u32, u32),
+) -> RgbaImage {
+    let frequency = frequency.clamp(0.01, 1.0);
+    let density = density.clamp(0.0, 1.0);
+    let (sin, cos) = std::f32::consts::FRAC_PI_4.sin_cos();
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (x, y) = (ox + x, oy + y);
+        let Some(&paint) = buffer.get_pixel_checked(x, y) else {
+            return Rgba([0, 0, 0, 0]);
+        };
+        if paint[3] == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let darkness = 1.0 - paint.to_luma()[0] as f32 / 255.0;
+        let share = darkness * (paint[3] as f32 / 255.0) * density;
+        // A dot of this radius covers `share` of a unit cell.
+        let radius = (share / std::f32::consts::PI).sqrt();
+
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        let u = (px * cos + py * sin) * frequency;
+        let v = (py * cos - px * sin) * frequency;
+        let dist = egui::vec2(u - u.round(), v - v.round()).length();
+        let coverage = ((radius - dist) / frequency + 0.5).clamp(0.0, 1.0);
+
+        Rgba([
+            paint[0],
+            paint[1],
+            paint[2],
+            (coverage * 255.0).round() as u8,
+        ])
+    })
+}
+
 #[derive(Clone)]
 pub enum LayerData {
     Raster(RgbaImage),
@@ -39,7 +367,7 @@ pub enum LayerData {
     // Tone layers are essentially raster layers with a procedural effect applied during composite
     Tone {
         buffer: RgbaImage,
-        frequency: f32, // Dots per unit
+        frequency: f32, // Dots per pixel along each axis
         density: f32,   // 0-1
     },
 }
@@ -50,10 +378,16 @@ pub struct Layer {
     pub visible: bool,
     pub locked: bool,
     pub alpha_locked: bool,
+    /// Per-channel write protection in R, G, B, A order. Locked channels keep
+    /// the existing pixel's value when tools commit.
+    pub channel_lock: [bool; 4],
     pub clipped: bool,
     pub opacity: f32,
     pub blend: BlendMode,
     pub data: LayerData,
+    /// Non-destructive mask multiplied into the layer's alpha when
+    /// compositing: black hides, white reveals.
+    pub mask: Option<GrayImage>,
 }
 
 impl Layer {
@@ -63,10 +397,25 @@ impl Layer {
             visible: true,
             locked: false,
             alpha_locked: false,
+            channel_lock: [false; 4],
             clipped: false,
             opacity: 1.0,
             blend: BlendMode::Normal,
             data: LayerData::Raster(ImageBuffer::new(width, height)),
+            mask: None,
+        }
+    }
+
+    /// A tone layer with a medium screen. Paint on it like a raster layer
+    /// and it composites as dots.
+    pub fn new_tone(width: u32, height: u32, name: String) -> Self {
+        Self {
+            data: LayerData::Tone {
+                buffer: ImageBuffer::new(width, height),
+                frequency: 0.1,
+                density: 0.5,
+            },
+            ..Self::new_raster(width, height, name)
         }
     }
 
@@ -76,10 +425,93 @@ impl Layer {
             visible: true,
             locked: false,
             alpha_locked: false,
+            channel_lock: [false; 4],
             clipped: false,
             opacity: 1.0,
             blend: BlendMode::Normal,
             data: LayerData::Vector(Vec::new()),
+            mask: None,
+        }
+    }
+
+    /// Approximate bytes held by the layer's pixels, shapes and mask.
+    pub fn memory_size(&self) -> usize {
+        let data = match &self.data {
+            LayerData::Raster(buffer) | LayerData::Tone { buffer, .. } => buffer.as_raw().len(),
+            LayerData::Vector(shapes) => std::mem::size_of_val(shapes.as_slice()),
+        };
+        data + self.mask.as_ref().map_or(0, |m| m.as_raw().len())
+    }
+
+    /// Mirrors the `(x, y, width, height)` region of the layer's pixels and
+    /// mask. Vector shapes are only mirrored when the region is the whole
+    /// `canvas`, since they can't be cut to a region.
+    pub fn flip(&mut self, axis: FlipAxis, region: (u32, u32, u32, u32), canvas: (u32, u32)) {
+        match &mut self.data {
+            LayerData::Raster(buffer) | LayerData::Tone { buffer, .. } => {
+                flip_region(buffer, axis, region)
+            }
+            LayerData::Vector(shapes) => {
+                if region == (0, 0, canvas.0, canvas.1) {
+                    for shape in shapes {
+                        shape.flip(axis, canvas.0, canvas.1);
+                    }
+                }
+            }
+        }
+        if let Some(mask) = &mut self.mask {
+            flip_region(mask, axis, region);
+        }
+    }
+}
+
+/// The user-editable settings of a layer, everything except its pixel data.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LayerProperties {
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+    pub alpha_locked: bool,
+    pub channel_lock: [bool; 4],
+    pub clipped: bool,
+    pub opacity: f32,
+    pub blend: BlendMode,
+}
+
+impl Layer {
+    pub fn properties(&self) -> LayerProperties {
+        LayerProperties {
+            name: self.name.clone(),
+            visible: self.visible,
+            locked: self.locked,
+            alpha_locked: self.alpha_locked,
+            channel_lock: self.channel_lock,
+            clipped: self.clipped,
+            opacity: self.opacity,
+            blend: self.blend,
+        }
+    }
+
+    pub fn set_properties(&mut self, props: &LayerProperties) {
+        self.name = props.name.clone();
+        self.visible = props.visible;
+        self.locked = props.locked;
+        self.alpha_locked = props.alpha_locked;
+        self.channel_lock = props.channel_lock;
+        self.clipped = props.clipped;
+        self.opacity = props.opacity;
+        self.blend = props.blend;
+    }
+}
+
+/// Merges `new` over `existing`, keeping every channel marked in `lock` from
+/// `existing`.
+pub fn lock_channels(lock: [bool; 4], existing: Rgba<u8>, new: Rgba<u8>) -> Rgba<u8> {
+    let mut out = new;
+    for c in 0..4 {
+        if lock[c] {
+            out[c] = existing[c];
         }
     }
+    out
 }
@@ -1,6 +1,18 @@
+mod adjustments;
+mod clipboard;
+mod color;
+mod color_picker;
 mod commands;
+mod config;
+mod export;
+mod guides;
 mod image_store;
+mod jobs;
 mod layers;
+mod mask_ops;
+mod palette;
+mod presets;
+mod project;
 mod state;
 mod tools;
 mod ui;
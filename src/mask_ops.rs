@@ -0,0 +1,249 @@
+use image::{GrayImage, Luma};
+
+/// Settings for [`refine`]. Zero everywhere leaves the mask unchanged.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct RefineParams {
+    /// Pixels to expand (positive) or contract (negative) the mask by.
+    pub shift: i32,
+    /// Median filter radius that rounds off jagged edges and specks.
+    pub smooth: u32,
+    /// Blur radius for a soft falloff at the edge.
+    pub feather: f32,
+}
+
+/// Expands, smooths, then feathers `mask`, in that order so the feather is
+/// applied to the final edge.
+pub fn refine(mask: &GrayImage, params: RefineParams) -> GrayImage {
+    let mut out = match params.shift {
+        0 => mask.clone(),
+        s if s > 0 => grow(mask, s as u32),
+        s => shrink(mask, s.unsigned_abs()),
+    };
+    if params.smooth > 0 {
+        out = median(&out, params.smooth);
+    }
+    if params.feather > 0.0 {
+        out = feather(&out, params.feather);
+    }
+    out
+}
+
+/// Dilates the mask by `radius` pixels.
+pub fn grow(mask: &GrayImage, radius: u32) -> GrayImage {
+    morph(mask, radius, u8::max)
+}
+
+/// Erodes the mask by `radius` pixels. The canvas border counts as inside,
+/// so a selection touching the edge does not pull away from it.
+pub fn shrink(mask: &GrayImage, radius: u32) -> GrayImage {
+    morph(mask, radius, u8::min)
+}
+
+/// Separable square-window min/max filter with edges clamped.
+fn morph(mask: &GrayImage, radius: u32, pick: fn(u8, u8) -> u8) -> GrayImage {
+    let horizontal = pass(mask, radius, true, |window| {
+        window.iter().copied().reduce(pick).unwrap_or(0)
+    });
+    pass(&horizontal, radius, false, |window| {
+        window.iter().copied().reduce(pick).unwrap_or(0)
+    })
+}
+
+/// Softens the mask edge with an approximately gaussian blur of the given
+/// radius (three box passes each way). Edges are clamped, so a mask that
+/// touches the canvas border stays solid along it.
+pub fn feather(mask: &GrayImage, radius: f32) -> GrayImage {
+    let sigma = radius / 2.0;
+    let box_radius = (((4.0 * sigma * sigma + 1.0).sqrt() - 1.0) / 2.0).round() as u32;
+    if box_radius == 0 {
+        return mask.clone();
+    }
+    let mut out = mask.clone();
+    for _ in 0..3 {
+        out = box_blur(&out, box_radius, true);
+        out = box_blur(&out, box_radius, false);
+    }
+    out
+}
+
+fn box_blur(mask: &GrayImage, radius: u32, horizontal: bool) -> GrayImage {
+    let (w, h) = mask.dimensions();
+    let mut out = GrayImage::new(w, h);
+    let (len, lines) = if horizontal { (w, h) } else { (h, w) };
+    let at = |line: u32, i: i64| -> u32 {
+        let i = i.clamp(0, len as i64 - 1) as u32;
+        let (x, y) = if horizontal { (i, line) } else { (line, i) };
+        mask.get_pixel(x, y)[0] as u32
+    };
+    let r = radius as i64;
+    let count = (2 * r + 1) as u32;
+
+    for line in 0..lines {
+        let mut sum: u32 = (-r..=r).map(|i| at(line, i)).sum();
+        for i in 0..len {
+            let (x, y) = if horizontal { (i, line) } else { (line, i) };
+            out.put_pixel(x, y, Luma([((sum + count / 2) / count) as u8]));
+            sum = sum + at(line, i as i64 + r + 1) - at(line, i as i64 - r);
+        }
+    }
+    out
+}
+
+/// Median filter over a square window, using a sliding histogram per row.
+pub fn median(mask: &GrayImage, radius: u32) -> GrayImage {
+    let (w, h) = mask.dimensions();
+    let mut out = GrayImage::new(w, h);
+    let r = radius as i64;
+    let at = |x: i64, y: i64| -> usize {
+        let x = x.clamp(0, w as i64 - 1) as u32;
+        let y = y.clamp(0, h as i64 - 1) as u32;
+        mask.get_pixel(x, y)[0] as usize
+    };
+    let half = ((2 * r + 1) * (2 * r + 1) / 2) as u32;
+
+    for y in 0..h as i64 {
+        let mut hist = [0u32; 256];
+        for dy in -r..=r {
+            for dx in -r..=r {
+                hist[at(dx, y + dy)] += 1;
+            }
+        }
+        for x in 0..w as i64 {
+            let mut seen = 0;
+            let value = hist
+                .iter()
+                .position(|&n| {
+                    seen += n;
+                    seen > half
+                })
+                .unwrap_or(0);
+            out.put_pixel(x as u32, y as u32, Luma([value as u8]));
+
+            for dy in -r..=r {
+                hist[at(x - r, y + dy)] -= 1;
+                hist[at(x + r + 1, y + dy)] += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Runs `reduce` over a clamped window of `2 * radius + 1` pixels around
+/// every pixel, along rows or columns.
+fn pass(
+    mask: &GrayImage,
+    radius: u32,
+    horizontal: bool,
+    reduce: impl Fn(&[u8]) -> u8,
+) -> GrayImage {
+    let (w, h) = mask.dimensions();
+    let mut out = GrayImage::new(w, h);
+    let mut window = Vec::with_capacity(2 * radius as usize + 1);
+    let r = radius as i64;
+
+    for y in 0..h {
+        for x in 0..w {
+            window.clear();
+            for d in -r..=r {
+                let (sx, sy) = if horizontal {
+                    ((x as i64 + d).clamp(0, w as i64 - 1) as u32, y)
+                } else {
+                    (x, (y as i64 + d).clamp(0, h as i64 - 1) as u32)
+                };
+                window.push(mask.get_pixel(sx, sy)[0]);
+            }
+            out.put_pixel(x, y, Luma([reduce(&window)]));
+        }
+    }
+    out
+}
+
+/// Traces the outline of the selected (nonzero) pixels: the pixel edges
+/// between selected and unselected pixels, the canvas border counting as
+/// unselected. Each straight run of edges comes back as one segment, as the
+/// corner points `[start, end]` in pixel coordinates.
+pub fn boundary(mask: &GrayImage) -> Vec<[(u32, u32); 2]> {
+    let (w, h) = mask.dimensions();
+    let selected = |x: u32, y: u32| x < w && y < h && mask.get_pixel(x, y)[0] > 0;
+    let mut edges = Vec::new();
+
+    // Horizontal edges along each row line y, between rows y - 1 and y.
+    for y in 0..=h {
+        let mut start = None;
+        for x in 0..=w {
+            let edge = x < w && (y > 0 && selected(x, y - 1)) != selected(x, y);
+            match (edge, start) {
+                (true, None) => start = Some(x),
+                (false, Some(s)) => {
+                    edges.push([(s, y), (x, y)]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    // Vertical edges along each column line x, between columns x - 1 and x.
+    for x in 0..=w {
+        let mut start = None;
+        for y in 0..=h {
+            let edge = y < h && (x > 0 && selected(x - 1, y)) != selected(x, y);
+            match (edge, start) {
+                (true, None) => start = Some(y),
+                (false, Some(s)) => {
+                    edges.push([(x, s), (x, y)]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundary_of_a_square_is_its_four_sides() {
+        let mask = GrayImage::from_fn(6, 6, |x, y| {
+            Luma([if (1..4).contains(&x) && (2..5).contains(&y) {
+                255
+            } else {
+                0
+            }])
+        });
+        let mut edges = boundary(&mask);
+        edges.sort();
+        assert_eq!(
+            edges,
+            [
+                [(1, 2), (1, 5)],
+                [(1, 2), (4, 2)],
+                [(1, 5), (4, 5)],
+                [(4, 2), (4, 5)],
+            ]
+        );
+    }
+
+    #[test]
+    fn boundary_follows_the_canvas_border_and_holes() {
+        // Everything selected but the middle pixel.
+        let mask = GrayImage::from_fn(3, 3, |x, y| Luma([if (x, y) == (1, 1) { 0 } else { 255 }]));
+        let mut edges = boundary(&mask);
+        edges.sort();
+        assert_eq!(
+            edges,
+            [
+                [(0, 0), (0, 3)],
+                [(0, 0), (3, 0)],
+                [(0, 3), (3, 3)],
+                [(1, 1), (1, 2)],
+                [(1, 1), (2, 1)],
+                [(1, 2), (2, 2)],
+                [(2, 1), (2, 2)],
+                [(3, 0), (3, 3)],
+            ]
+        );
+    }
+}
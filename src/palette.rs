@@ -0,0 +1,272 @@
+use anyhow::{bail, ensure, Context, Result};
+use image::Rgba;
+use std::path::Path;
+
+/// Reads a palette, by extension: GIMP `.gpl`, Adobe `.ase`, or the JSON list
+/// of hex colors that `save` writes.
+pub fn load(path: &Path) -> Result<Vec<Rgba<u8>>> {
+    let colors = match extension(path).as_str() {
+        "gpl" => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read palette: {:?}", path))?;
+            parse_gpl(&text)
+        }
+        "ase" => {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read palette: {:?}", path))?;
+            parse_ase(&bytes)
+        }
+        _ => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read palette: {:?}", path))?;
+            parse_json(&text)
+        }
+    }
+    .with_context(|| format!("Invalid palette: {:?}", path))?;
+    ensure!(!colors.is_empty(), "No colors in {:?}", path);
+    Ok(colors)
+}
+
+/// Writes `colors` as a GIMP palette if `path` ends in `.gpl`, dropping
+/// alpha, which that format has no room for. Anything else gets JSON hex
+/// strings, which keep it.
+pub fn save(path: &Path, colors: &[Rgba<u8>]) -> Result<()> {
+    let text = if extension(path) == "gpl" {
+        to_gpl(colors, &name(path))
+    } else {
+        let hex: Vec<String> = colors.iter().map(|&c| crate::color::to_hex(c)).collect();
+        serde_json::to_string_pretty(&hex)?
+    };
+    std::fs::write(path, text).with_context(|| format!("Failed to write {:?}", path))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+fn name(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Palette")
+        .to_string()
+}
+
+fn parse_json(text: &str) -> Result<Vec<Rgba<u8>>> {
+    let hex: Vec<String> = serde_json::from_str(text)?;
+    hex.iter()
+        .map(|h| crate::color::parse_hex(h).with_context(|| format!("Bad color {:?}", h)))
+        .collect()
+}
+
+/// Parses a GIMP palette: a `GIMP Palette` header, optional `Name:` and
+/// `Columns:` lines, `#` comments, then one `R G B [name]` line per color.
+fn parse_gpl(text: &str) -> Result<Vec<Rgba<u8>>> {
+    let mut lines = text.lines();
+    ensure!(
+        lines.next().map(str::trim) == Some("GIMP Palette"),
+        "Missing \"GIMP Palette\" header"
+    );
+    let mut colors = Vec::new();
+    for (line_no, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        // Anything after the three channels is the color's name.
+        let mut words = line.split_whitespace();
+        let mut rgb = [0u8; 3];
+        for channel in &mut rgb {
+            *channel = words.next().and_then(|w| w.parse().ok()).with_context(|| {
+                format!("Line {}: expected three values from 0 to 255", line_no + 2)
+            })?;
+        }
+        colors.push(Rgba([rgb[0], rgb[1], rgb[2], 255]));
+    }
+    Ok(colors)
+}
+
+fn to_gpl(colors: &[Rgba<u8>], name: &str) -> String {
+    let mut text = format!("GIMP Palette\nName: {}\nColumns: 8\n#\n", name);
+    for c in colors {
+        text.push_str(&format!(
+            "{:3} {:3} {:3}\t{}\n",
+            c[0],
+            c[1],
+            c[2],
+            crate::color::to_hex(*c)
+        ));
+    }
+    text
+}
+
+/// Parses an Adobe swatch exchange file. RGB, CMYK and gray swatches are
+/// read; groups are flattened and Lab swatches are skipped.
+fn parse_ase(bytes: &[u8]) -> Result<Vec<Rgba<u8>>> {
+    let mut reader = Reader { bytes, pos: 0 };
+    ensure!(reader.take(4)? == b"ASEF", "Not an ASE file");
+    reader.take(4)?; // Version
+    let blocks = reader.u32()?;
+
+    let mut colors = Vec::new();
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let len = reader.u32()? as usize;
+        let mut block = Reader {
+            bytes: reader.take(len)?,
+            pos: 0,
+        };
+        // 0xC001 and 0xC002 open and close groups, which carry no colors.
+        if kind != 0x0001 {
+            continue;
+        }
+        let name_len = block.u16()? as usize;
+        block.take(name_len * 2)?; // UTF-16 name
+        let model = block.take(4)?;
+        let mut value = || block.f32().map(|v| v.clamp(0.0, 1.0));
+        let (r, g, b) = match model {
+            b"RGB " => (value()?, value()?, value()?),
+            b"CMYK" => {
+                let (c, m, y, k) = (value()?, value()?, value()?, value()?);
+                (
+                    (1.0 - c) * (1.0 - k),
+                    (1.0 - m) * (1.0 - k),
+                    (1.0 - y) * (1.0 - k),
+                )
+            }
+            b"Gray" => {
+                let v = value()?;
+                (v, v, v)
+            }
+            b"LAB " => {
+                log::warn!("Skipping Lab swatch");
+                continue;
+            }
+            other => bail!("Unknown color model {:?}", String::from_utf8_lossy(other)),
+        };
+        let to_u8 = |v: f32| (v * 255.0).round() as u8;
+        colors.push(Rgba([to_u8(r), to_u8(g), to_u8(b), 255]));
+    }
+    Ok(colors)
+}
+
+/// Big-endian reads over a byte slice, failing instead of panicking at the end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).context("Block too large")?;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .context("Unexpected end of file")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [Rgba<u8>; 3] = [
+        Rgba([255, 0, 0, 255]),
+        Rgba([18, 52, 86, 255]),
+        Rgba([0, 128, 255, 64]),
+    ];
+
+    /// An ASE block: kind, then the length-prefixed body.
+    fn block(kind: u16, body: &[u8]) -> Vec<u8> {
+        let mut bytes = kind.to_be_bytes().to_vec();
+        bytes.extend((body.len() as u32).to_be_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    /// A swatch block named "c" in `model` with `values`.
+    fn swatch(model: &[u8; 4], values: &[f32]) -> Vec<u8> {
+        let mut body = vec![0, 2, 0, b'c', 0, 0];
+        body.extend(model);
+        for v in values {
+            body.extend(v.to_be_bytes());
+        }
+        body.extend([0, 2]); // Color type: normal
+        block(0x0001, &body)
+    }
+
+    fn ase(blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"ASEF".to_vec();
+        bytes.extend([0, 1, 0, 0]);
+        bytes.extend((blocks.len() as u32).to_be_bytes());
+        for b in blocks {
+            bytes.extend(b);
+        }
+        bytes
+    }
+
+    #[test]
+    fn json_keeps_alpha() {
+        let hex: Vec<String> = COLORS.iter().map(|&c| crate::color::to_hex(c)).collect();
+        let text = serde_json::to_string(&hex).unwrap();
+        assert_eq!(parse_json(&text).unwrap(), COLORS);
+    }
+
+    #[test]
+    fn gpl_round_trips_without_alpha() {
+        let colors = parse_gpl(&to_gpl(&COLORS, "Test")).unwrap();
+        assert_eq!(colors[..2], COLORS[..2]);
+        assert_eq!(colors[2], Rgba([0, 128, 255, 255]));
+    }
+
+    #[test]
+    fn gpl_without_header_is_rejected() {
+        assert!(parse_gpl("255 0 0 Red\n").is_err());
+    }
+
+    #[test]
+    fn ase_reads_rgb_cmyk_and_gray_and_skips_lab() {
+        let bytes = ase(&[
+            block(0xC001, &[0, 2, 0, b'g', 0, 0]),
+            swatch(b"RGB ", &[1.0, 0.5, 0.0]),
+            swatch(b"CMYK", &[0.0, 1.0, 1.0, 0.5]),
+            swatch(b"LAB ", &[0.5, 0.0, 0.0]),
+            swatch(b"Gray", &[0.25]),
+            block(0xC002, &[]),
+        ]);
+        assert_eq!(
+            parse_ase(&bytes).unwrap(),
+            [
+                Rgba([255, 128, 0, 255]),
+                Rgba([128, 0, 0, 255]),
+                Rgba([64, 64, 64, 255]),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_ase_is_rejected() {
+        let bytes = ase(&[swatch(b"RGB ", &[1.0, 0.5, 0.0])]);
+        assert!(parse_ase(&bytes[..bytes.len() - 3]).is_err());
+    }
+}
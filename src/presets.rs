@@ -0,0 +1,94 @@
+use crate::config::Config;
+use crate::state::ToolSettings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A saved brush: the tip and stroke settings from `ToolSettings`, plus the
+/// file the brush texture was loaded from, if any.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrushPreset {
+    pub name: String,
+    pub size: f32,
+    pub spacing: f32,
+    pub hardness: f32,
+    pub opacity: f32,
+    pub flow: f32,
+    pub angle: f32,
+    pub roundness: f32,
+    pub antialias: bool,
+    pub pressure_size: bool,
+    pub pressure_opacity: bool,
+    pub texture: Option<PathBuf>,
+}
+
+impl Default for BrushPreset {
+    fn default() -> Self {
+        Self::capture(String::new(), &ToolSettings::default(), None)
+    }
+}
+
+impl BrushPreset {
+    pub fn capture(name: String, settings: &ToolSettings, texture: Option<PathBuf>) -> Self {
+        Self {
+            name,
+            size: settings.brush_size,
+            spacing: settings.brush_spacing,
+            hardness: settings.brush_hardness,
+            opacity: settings.brush_opacity,
+            flow: settings.brush_flow,
+            angle: settings.brush_angle,
+            roundness: settings.brush_roundness,
+            antialias: settings.brush_antialias,
+            pressure_size: settings.pressure_size,
+            pressure_opacity: settings.pressure_opacity,
+            texture,
+        }
+    }
+
+    /// Copies the stored values into `settings`. The texture is left to the
+    /// caller, which owns the loaded image.
+    pub fn apply(&self, settings: &mut ToolSettings) {
+        settings.brush_size = self.size;
+        settings.brush_spacing = self.spacing;
+        settings.brush_hardness = self.hardness;
+        settings.brush_opacity = self.opacity;
+        settings.brush_flow = self.flow;
+        settings.brush_angle = self.angle;
+        settings.brush_roundness = self.roundness;
+        settings.brush_antialias = self.antialias;
+        settings.pressure_size = self.pressure_size;
+        settings.pressure_opacity = self.pressure_opacity;
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    Config::dir().map(|dir| dir.join("brush_presets.json"))
+}
+
+/// Reads the saved presets, or none if the file is missing or unreadable.
+pub fn load() -> Vec<BrushPreset> {
+    let Some(path) = path().filter(|p| p.exists()) else {
+        return Vec::new();
+    };
+    let result = std::fs::read_to_string(&path)
+        .context("Failed to read brush presets")
+        .and_then(|text| serde_json::from_str(&text).context("Invalid brush presets"));
+    match result {
+        Ok(presets) => presets,
+        Err(e) => {
+            log::warn!("Ignoring {:?}: {:#}", path, e);
+            Vec::new()
+        }
+    }
+}
+
+pub fn save(presets: &[BrushPreset]) -> Result<()> {
+    let path = path().context("No config directory on this system")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    let text = serde_json::to_string_pretty(presets)?;
+    std::fs::write(&path, text).with_context(|| format!("Failed to write {:?}", path))
+}
@@ -0,0 +1,368 @@
+use crate::image_store::ImageStore;
+use crate::layers::{BlendMode, Layer, LayerData, VectorShape};
+use crate::state::ToolSettings;
+use anyhow::{bail, ensure, Context, Result};
+use egui::{Pos2, Rect};
+use image::{GrayImage, ImageFormat, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Bumped whenever the manifest changes in a way older builds can't read.
+const VERSION: u32 = 1;
+const MANIFEST: &str = "project.json";
+
+/// The `.ars` manifest. The archive holds it as `project.json` next to one
+/// PNG per raster or tone layer (`layers/<n>.png`) and per mask
+/// (`masks/<n>.png`), numbered by stack position from the bottom.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    width: u32,
+    height: u32,
+    active_layer: usize,
+    background: Option<[u8; 4]>,
+    layers: Vec<LayerEntry>,
+    /// Absent in projects saved before tools were stored.
+    #[serde(default)]
+    tool: Option<SavedTool>,
+}
+
+/// The tool that was active when a project was saved, by `Tool::name`,
+/// with the settings it was used with.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedTool {
+    pub name: String,
+    pub settings: ToolSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LayerEntry {
+    name: String,
+    visible: bool,
+    locked: bool,
+    alpha_locked: bool,
+    channel_lock: [bool; 4],
+    clipped: bool,
+    opacity: f32,
+    blend: BlendMode,
+    kind: LayerKind,
+    has_mask: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+enum LayerKind {
+    Raster,
+    Tone { frequency: f32, density: f32 },
+    Vector(Vec<ShapeEntry>),
+}
+
+/// `VectorShape` in plain arrays, since egui and image types aren't serde.
+#[derive(Serialize, Deserialize)]
+enum ShapeEntry {
+    Line {
+        start: [f32; 2],
+        end: [f32; 2],
+        color: [u8; 4],
+        width: f32,
+    },
+    Rectangle {
+        min: [f32; 2],
+        max: [f32; 2],
+        color: [u8; 4],
+        width: f32,
+        fill: bool,
+    },
+    Ellipse {
+        min: [f32; 2],
+        max: [f32; 2],
+        color: [u8; 4],
+        width: f32,
+        fill: bool,
+    },
+}
+
+impl From<&VectorShape> for ShapeEntry {
+    fn from(shape: &VectorShape) -> Self {
+        let xy = |p: Pos2| [p.x, p.y];
+        match *shape {
+            VectorShape::Line {
+                start,
+                end,
+                color,
+                width,
+            } => ShapeEntry::Line {
+                start: xy(start),
+                end: xy(end),
+                color: color.0,
+                width,
+            },
+            VectorShape::Rectangle {
+                rect,
+                color,
+                width,
+                fill,
+            } => ShapeEntry::Rectangle {
+                min: xy(rect.min),
+                max: xy(rect.max),
+                color: color.0,
+                width,
+                fill,
+            },
+            VectorShape::Ellipse {
+                rect,
+                color,
+                width,
+                fill,
+            } => ShapeEntry::Ellipse {
+                min: xy(rect.min),
+                max: xy(rect.max),
+                color: color.0,
+                width,
+                fill,
+            },
+        }
+    }
+}
+
+impl From<&ShapeEntry> for VectorShape {
+    fn from(entry: &ShapeEntry) -> Self {
+        let pos = |[x, y]: [f32; 2]| Pos2::new(x, y);
+        match *entry {
+            ShapeEntry::Line {
+                start,
+                end,
+                color,
+                width,
+            } => VectorShape::Line {
+                start: pos(start),
+                end: pos(end),
+                color: Rgba(color),
+                width,
+            },
+            ShapeEntry::Rectangle {
+                min,
+                max,
+                color,
+                width,
+                fill,
+            } => VectorShape::Rectangle {
+                rect: Rect::from_min_max(pos(min), pos(max)),
+                color: Rgba(color),
+                width,
+                fill,
+            },
+            ShapeEntry::Ellipse {
+                min,
+                max,
+                color,
+                width,
+                fill,
+            } => VectorShape::Ellipse {
+                rect: Rect::from_min_max(pos(min), pos(max)),
+                color: Rgba(color),
+                width,
+                fill,
+            },
+        }
+    }
+}
+
+/// Writes every layer of `image`, with its settings, pixels and mask, to an
+/// `.ars` archive at `path`, along with the active `tool`. The selection is
+/// not saved.
+pub fn save(image: &ImageStore, tool: &SavedTool, path: &Path) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+    let mut zip = ZipWriter::new(file);
+    // PNGs are already compressed.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let mut entries = Vec::with_capacity(image.layers.len());
+    for (i, layer) in image.layers.iter().enumerate() {
+        let kind = match &layer.data {
+            LayerData::Raster(buffer) => {
+                write_png(&mut zip, &format!("layers/{}.png", i), buffer, stored)?;
+                LayerKind::Raster
+            }
+            LayerData::Tone {
+                buffer,
+                frequency,
+                density,
+            } => {
+                write_png(&mut zip, &format!("layers/{}.png", i), buffer, stored)?;
+                LayerKind::Tone {
+                    frequency: *frequency,
+                    density: *density,
+                }
+            }
+            LayerData::Vector(shapes) => LayerKind::Vector(shapes.iter().map(Into::into).collect()),
+        };
+        if let Some(mask) = &layer.mask {
+            write_png(&mut zip, &format!("masks/{}.png", i), mask, stored)?;
+        }
+        entries.push(LayerEntry {
+            name: layer.name.clone(),
+            visible: layer.visible,
+            locked: layer.locked,
+            alpha_locked: layer.alpha_locked,
+            channel_lock: layer.channel_lock,
+            clipped: layer.clipped,
+            opacity: layer.opacity,
+            blend: layer.blend,
+            kind,
+            has_mask: layer.mask.is_some(),
+        });
+    }
+
+    let manifest = Manifest {
+        version: VERSION,
+        width: image.width(),
+        height: image.height(),
+        active_layer: image.active_index(),
+        background: image.background.map(|c| c.0),
+        layers: entries,
+        tool: Some(tool.clone()),
+    };
+    zip.start_file(MANIFEST, SimpleFileOptions::default())?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())
+        .context("Failed to write project manifest")?;
+    zip.finish().context("Failed to finish project file")?;
+    Ok(())
+}
+
+/// Reads an `.ars` archive written by `save`, with the tool saved in it.
+pub fn load(path: &Path) -> Result<(ImageStore, Option<SavedTool>)> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut zip = ZipArchive::new(file).context("Not an ArsPaint project")?;
+
+    let mut text = String::new();
+    zip.by_name(MANIFEST)
+        .context("Project has no manifest")?
+        .read_to_string(&mut text)?;
+    let manifest: Manifest = serde_json::from_str(&text).context("Invalid project manifest")?;
+    if manifest.version > VERSION {
+        bail!(
+            "Project was saved by a newer version (format {})",
+            manifest.version
+        );
+    }
+    let (width, height) = (manifest.width, manifest.height);
+    ensure!(width > 0 && height > 0, "Project has an empty canvas");
+    ensure!(!manifest.layers.is_empty(), "Project has no layers");
+
+    let mut layers = Vec::with_capacity(manifest.layers.len());
+    for (i, entry) in manifest.layers.iter().enumerate() {
+        let mut pixels = || -> Result<RgbaImage> {
+            let png = read_png(&mut zip, &format!("layers/{}.png", i))?;
+            let buffer = png.to_rgba8();
+            ensure!(
+                buffer.dimensions() == (width, height),
+                "Layer {} does not match the canvas size",
+                i
+            );
+            Ok(buffer)
+        };
+        let data = match &entry.kind {
+            LayerKind::Raster => LayerData::Raster(pixels()?),
+            LayerKind::Tone { frequency, density } => LayerData::Tone {
+                buffer: pixels()?,
+                frequency: *frequency,
+                density: *density,
+            },
+            LayerKind::Vector(shapes) => LayerData::Vector(shapes.iter().map(Into::into).collect()),
+        };
+        let mask: Option<GrayImage> = if entry.has_mask {
+            let mask = read_png(&mut zip, &format!("masks/{}.png", i))?.to_luma8();
+            ensure!(
+                mask.dimensions() == (width, height),
+                "Mask {} does not match the canvas size",
+                i
+            );
+            Some(mask)
+        } else {
+            None
+        };
+        layers.push(Layer {
+            name: entry.name.clone(),
+            visible: entry.visible,
+            locked: entry.locked,
+            alpha_locked: entry.alpha_locked,
+            channel_lock: entry.channel_lock,
+            clipped: entry.clipped,
+            opacity: entry.opacity,
+            blend: entry.blend,
+            data,
+            mask,
+        });
+    }
+
+    let mut store = ImageStore::from_layers(width, height, layers, manifest.active_layer);
+    store.set_background(manifest.background.map(Rgba));
+    Ok((store, manifest.tool))
+}
+
+fn write_png<P, C>(
+    zip: &mut ZipWriter<File>,
+    name: &str,
+    buffer: &image::ImageBuffer<P, C>,
+    options: SimpleFileOptions,
+) -> Result<()>
+where
+    P: image::PixelWithColorType,
+    [P::Subpixel]: image::EncodableLayout,
+    C: std::ops::Deref<Target = [P::Subpixel]>,
+{
+    let mut bytes = Cursor::new(Vec::new());
+    buffer
+        .write_to(&mut bytes, ImageFormat::Png)
+        .with_context(|| format!("Failed to encode {}", name))?;
+    zip.start_file(name, options)?;
+    zip.write_all(bytes.get_ref())
+        .with_context(|| format!("Failed to write {}", name))
+}
+
+fn read_png(zip: &mut ZipArchive<File>, name: &str) -> Result<image::DynamicImage> {
+    let mut bytes = Vec::new();
+    zip.by_name(name)
+        .with_context(|| format!("Project is missing {}", name))?
+        .read_to_end(&mut bytes)?;
+    image::load_from_memory_with_format(&bytes, ImageFormat::Png)
+        .with_context(|| format!("Failed to decode {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_round_trips() {
+        let path = std::env::temp_dir().join(format!("arspaint-tool-{}.ars", std::process::id()));
+        let tool = SavedTool {
+            name: "Eraser".to_string(),
+            settings: ToolSettings {
+                eraser_size: 42.0,
+                ..Default::default()
+            },
+        };
+
+        save(&ImageStore::new(4, 4), &tool, &path).unwrap();
+        let (store, loaded) = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((store.width(), store.height()), (4, 4));
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.name, "Eraser");
+        assert_eq!(loaded.settings.eraser_size, 42.0);
+    }
+
+    #[test]
+    fn manifest_without_tool_loads() {
+        let text =
+            r#"{"version":1,"width":1,"height":1,"active_layer":0,"background":null,"layers":[]}"#;
+        let manifest: Manifest = serde_json::from_str(text).unwrap();
+        assert!(manifest.tool.is_none());
+    }
+}
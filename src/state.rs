@@ -1,30 +1,155 @@
-use crate::commands::CommandStack;
+use crate::commands::{
+    AddLayerCommand, CommandStack, DocumentCommand, FlipCommand, LayerPropertyCommand,
+    RemoveLayerCommand, ReorderLayerCommand, SetMaskCommand,
+};
 use crate::image_store::ImageStore;
-use crate::tools::{BrushTool, Tool};
+use crate::layers::FlipAxis;
+use crate::tools::{create_tool, BrushTool, Tool};
 use image::Rgba;
+use serde::{Deserialize, Serialize};
 
+/// How `BrushTool` smooths the raw pointer path before stamping.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum StabilizerKind {
+    /// Exponential moving average weighted by `brush_stabilization`.
+    Ema,
+    /// Mean of the last `stabilizer_window` pointer positions.
+    MovingAverage,
+    /// The stroke trails the pointer on a leash of `stabilizer_leash` pixels.
+    Pull,
+}
+
+/// What a flip mirrors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FlipScope {
+    /// Every layer, and the selection with them.
+    Image,
+    /// The active layer across the whole canvas.
+    Layer,
+    /// The rectangle around the selection on the active layer.
+    Selection,
+}
+
+/// How brush strokes are applied to the pixels underneath.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BrushMode {
+    /// Paint the brush color over the layer.
+    Normal,
+    /// Take hue and saturation from the brush, keep the layer's value.
+    Color,
+    /// Take value from the brush, keep the layer's hue and saturation.
+    Value,
+}
+
+/// What the paint bucket fills.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum FillMode {
+    /// Pixels connected to the click that match its color within tolerance.
+    Flood,
+    /// Every selected pixel, whatever its color.
+    Selection,
+}
+
+/// How the gradient tool spreads its colors.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum GradientType {
+    /// Along the dragged line, constant across it.
+    Linear,
+    /// By distance from the drag's start, reaching the end color at its end.
+    Radial,
+}
+
+/// Tool options shared by every tool. Saved with `.ars` projects; fields
+/// missing from older files keep their defaults.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ToolSettings {
     pub brush_size: f32,
+    pub brush_mode: BrushMode,
+    /// Rotation of the brush tip in degrees.
+    pub brush_angle: f32,
+    /// Minor/major axis ratio of the brush tip; 1.0 is a round brush.
+    pub brush_roundness: f32,
+    /// Soften the brush tip's edge over one pixel instead of a hard cutoff.
+    pub brush_antialias: bool,
+    /// Share of the tip's radius painted at full strength before it fades
+    /// out; 1.0 is a solid tip, 0.0 fades all the way from the center.
+    pub brush_hardness: f32,
     pub brush_stabilization: f32,
+    pub stabilizer: StabilizerKind,
+    pub stabilizer_window: usize,
+    pub stabilizer_leash: f32,
     pub brush_spacing: f32,
+    /// Highest alpha a single stroke reaches, as a share of the color's alpha.
+    pub brush_opacity: f32,
+    /// Share of each dab added to the stroke; overlapping dabs build up to
+    /// `brush_opacity`.
+    pub brush_flow: f32,
+    /// Scale the brush size by stylus pressure.
+    pub pressure_size: bool,
+    /// Scale the brush opacity by stylus pressure.
+    pub pressure_opacity: bool,
     pub eraser_size: f32,
+    /// Share of alpha one eraser stroke removes.
+    pub eraser_strength: f32,
     pub line_width: f32,
+    /// Rectangle and ellipse fill their interior as well as stroking it.
+    pub shape_fill: bool,
+    pub fill_mode: FillMode,
+    /// Largest per-channel difference from the seed color a flood still fills.
+    pub fill_tolerance: u8,
+    /// Largest per-channel difference from the clicked color the magic wand
+    /// still selects.
+    pub wand_tolerance: u8,
+    /// Magic wand selects only pixels connected to the click.
+    pub wand_contiguous: bool,
+    pub gradient_type: GradientType,
+    /// Gradients composite over the layer rather than replacing its pixels.
+    pub gradient_blend: bool,
+    /// Brush and fill only paint where the layer is fully transparent, the
+    /// mirror of alpha lock.
+    pub fill_behind: bool,
+    /// Brush and eraser paint the active layer's mask instead of its pixels.
+    pub edit_mask: bool,
 }
 
 impl Default for ToolSettings {
     fn default() -> Self {
         Self {
             brush_size: 5.0,
+            brush_mode: BrushMode::Normal,
+            brush_angle: 0.0,
+            brush_roundness: 1.0,
+            brush_antialias: true,
+            brush_hardness: 1.0,
             brush_stabilization: 0.5,
+            stabilizer: StabilizerKind::Ema,
+            stabilizer_window: 8,
+            stabilizer_leash: 10.0,
             brush_spacing: 0.1,
+            brush_opacity: 1.0,
+            brush_flow: 1.0,
+            pressure_size: true,
+            pressure_opacity: false,
             eraser_size: 10.0,
+            eraser_strength: 1.0,
             line_width: 2.0,
+            shape_fill: false,
+            fill_mode: FillMode::Flood,
+            fill_tolerance: 32,
+            wand_tolerance: 32,
+            wand_contiguous: true,
+            gradient_type: GradientType::Linear,
+            gradient_blend: true,
+            fill_behind: false,
+            edit_mask: false,
         }
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Shortcut {
+    #[serde(with = "key_name")]
     pub key: egui::Key,
     pub ctrl: bool,
     pub shift: bool,
@@ -57,12 +182,45 @@ impl Shortcut {
     }
 
     pub fn matches(&self, i: &egui::InputState) -> bool {
-        i.key_pressed(self.key)
+        (i.key_pressed(self.key) || self.clipboard_event(i))
             && i.modifiers.ctrl == self.ctrl
             && i.modifiers.shift == self.shift
             && i.modifiers.alt == self.alt
     }
 
+    /// Like `matches`, but while `typing` into a text field a shortcut
+    /// without Ctrl or Alt is left alone: its key types a character there.
+    pub fn triggered(&self, i: &egui::InputState, typing: bool) -> bool {
+        (!typing || self.ctrl || self.alt) && self.matches(i)
+    }
+
+    /// egui reports Ctrl+X and Ctrl+C as clipboard events instead of key
+    /// presses, so those shortcuts are matched on the events. Ctrl+V only
+    /// becomes an event when the clipboard holds text, so it is matched when
+    /// V is released instead.
+    fn clipboard_event(&self, i: &egui::InputState) -> bool {
+        use egui::{Event, Key};
+
+        if !self.ctrl || self.shift || self.alt {
+            return false;
+        }
+        i.events.iter().any(|e| {
+            matches!(
+                (self.key, e),
+                (Key::X, Event::Cut)
+                    | (Key::C, Event::Copy)
+                    | (
+                        Key::V,
+                        Event::Key {
+                            key: Key::V,
+                            pressed: false,
+                            ..
+                        }
+                    )
+            )
+        })
+    }
+
     pub fn format(&self) -> String {
         let mut s = String::new();
         if self.ctrl {
@@ -79,6 +237,26 @@ impl Shortcut {
     }
 }
 
+/// Stores an `egui::Key` by its name ("A", "Space", "F1", ...), which is
+/// what `Key::from_name` reads back.
+mod key_name {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &egui::Key, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(key.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<egui::Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        egui::Key::from_name(&name)
+            .ok_or_else(|| D::Error::custom(format!("unknown key {:?}", name)))
+    }
+}
+
+/// Saved in the config file. Bindings missing from it keep their defaults.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Keybindings {
     pub undo: Shortcut,
     pub redo: Shortcut,
@@ -88,11 +266,60 @@ pub struct Keybindings {
     pub rect: Shortcut,
     pub ellipse: Shortcut,
     pub select: Shortcut,
+    pub ellipse_select: Shortcut,
+    pub magic_wand: Shortcut,
     pub deselect: Shortcut,
     pub transform: Shortcut,
+    pub eyedropper: Shortcut,
+    pub toggle_visibility: Shortcut,
+    pub layer_up: Shortcut,
+    pub layer_down: Shortcut,
+    pub copy: Shortcut,
+    pub paste: Shortcut,
+    pub invert_selection: Shortcut,
+    pub select_all: Shortcut,
+    pub fit_view: Shortcut,
+    pub swap_colors: Shortcut,
+    pub reset_colors: Shortcut,
+    pub size_down: Shortcut,
+    pub size_up: Shortcut,
+    #[serde(with = "key_name")]
     pub pan: egui::Key,
 }
 
+impl Keybindings {
+    /// Every remappable shortcut with the label shown in the shortcuts popup.
+    pub fn entries_mut(&mut self) -> [(&'static str, &mut Shortcut); 25] {
+        [
+            ("Undo", &mut self.undo),
+            ("Redo", &mut self.redo),
+            ("Brush", &mut self.brush),
+            ("Eraser", &mut self.eraser),
+            ("Line", &mut self.line),
+            ("Rectangle", &mut self.rect),
+            ("Ellipse", &mut self.ellipse),
+            ("Select", &mut self.select),
+            ("Ellipse Select", &mut self.ellipse_select),
+            ("Magic Wand", &mut self.magic_wand),
+            ("Deselect", &mut self.deselect),
+            ("Transform", &mut self.transform),
+            ("Eyedropper", &mut self.eyedropper),
+            ("Toggle Layer Visibility", &mut self.toggle_visibility),
+            ("Move Layer Up", &mut self.layer_up),
+            ("Move Layer Down", &mut self.layer_down),
+            ("Copy", &mut self.copy),
+            ("Paste", &mut self.paste),
+            ("Invert Selection", &mut self.invert_selection),
+            ("Select All", &mut self.select_all),
+            ("Fit to Window", &mut self.fit_view),
+            ("Swap Colors", &mut self.swap_colors),
+            ("Default Colors", &mut self.reset_colors),
+            ("Decrease Tool Size", &mut self.size_down),
+            ("Increase Tool Size", &mut self.size_up),
+        ]
+    }
+}
+
 impl Default for Keybindings {
     fn default() -> Self {
         Self {
@@ -104,19 +331,77 @@ impl Default for Keybindings {
             rect: Shortcut::new(egui::Key::R),
             ellipse: Shortcut::new(egui::Key::O),
             select: Shortcut::new(egui::Key::S),
+            ellipse_select: Shortcut::new(egui::Key::S).shift(true),
+            magic_wand: Shortcut::new(egui::Key::W),
             deselect: Shortcut::new(egui::Key::D).ctrl(true),
             transform: Shortcut::new(egui::Key::T).ctrl(true),
+            eyedropper: Shortcut::new(egui::Key::I),
+            toggle_visibility: Shortcut::new(egui::Key::V),
+            layer_up: Shortcut::new(egui::Key::CloseBracket).ctrl(true),
+            layer_down: Shortcut::new(egui::Key::OpenBracket).ctrl(true),
+            copy: Shortcut::new(egui::Key::C).ctrl(true),
+            paste: Shortcut::new(egui::Key::V).ctrl(true),
+            invert_selection: Shortcut::new(egui::Key::I).ctrl(true).shift(true),
+            select_all: Shortcut::new(egui::Key::A).ctrl(true),
+            fit_view: Shortcut::new(egui::Key::Num0).ctrl(true),
+            swap_colors: Shortcut::new(egui::Key::X),
+            // Plain D is the usual key, but too easily confused with Ctrl+D.
+            reset_colors: Shortcut::new(egui::Key::X).shift(true),
+            size_down: Shortcut::new(egui::Key::OpenBracket),
+            size_up: Shortcut::new(egui::Key::CloseBracket),
             pan: egui::Key::Space,
         }
     }
 }
 
+/// How the selection is drawn over the canvas.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum SelectionStyle {
+    /// Marching ants along the mask boundary.
+    Outline,
+    /// A translucent tint over the selected area.
+    Fill,
+    /// Marching ants over a lighter tint.
+    Both,
+}
+
+/// Application-wide preferences that are not tied to a tool or document.
+/// Saved in the config file.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// Largest width or height the canvas can be resized to.
+    pub max_canvas_size: u32,
+    /// Resizes whose pixel buffers would exceed this many bytes ask first.
+    pub resize_warn_bytes: u64,
+    /// Most undo steps kept per document.
+    pub undo_levels: usize,
+    pub selection_style: SelectionStyle,
+    pub selection_color: [u8; 3],
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            max_canvas_size: 16384,
+            resize_warn_bytes: 512 * 1024 * 1024,
+            undo_levels: 100,
+            selection_style: SelectionStyle::Both,
+            selection_color: [0, 100, 255],
+        }
+    }
+}
+
+const DEFAULT_PRIMARY: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const DEFAULT_SECONDARY: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
 pub struct AppState {
     pub image: ImageStore,
     pub command_stack: CommandStack,
     pub active_tool: Box<dyn Tool>,
     pub tool_settings: ToolSettings,
     pub keybindings: Keybindings,
+    pub preferences: Preferences,
     pub primary_color: Rgba<u8>,
     pub secondary_color: Rgba<u8>,
     pub palette: Vec<Rgba<u8>>,
@@ -137,13 +422,217 @@ impl AppState {
 
         Self {
             image: ImageStore::new(width, height),
-            command_stack: CommandStack::new(),
+            command_stack: CommandStack::new(Preferences::default().undo_levels),
             active_tool: Box::new(BrushTool::new(width, height)),
             tool_settings: ToolSettings::default(),
             keybindings: Keybindings::default(),
-            primary_color: Rgba([0, 0, 0, 255]),
-            secondary_color: Rgba([255, 255, 255, 255]),
+            preferences: Preferences::default(),
+            primary_color: DEFAULT_PRIMARY,
+            secondary_color: DEFAULT_SECONDARY,
             palette,
         }
     }
+
+    /// Replaces the document, resetting history. The active tool is rebuilt by
+    /// name for the new document so the same tool and settings carry over
+    /// without any state tied to the previous image.
+    pub fn set_image(&mut self, image: ImageStore) {
+        let name = self.active_tool.name().to_string();
+        self.active_tool = create_tool(&name, image.width(), image.height())
+            .unwrap_or_else(|| Box::new(BrushTool::new(image.width(), image.height())));
+        self.image = image;
+        self.command_stack = CommandStack::new(self.preferences.undo_levels);
+    }
+
+    pub fn swap_colors(&mut self) {
+        std::mem::swap(&mut self.primary_color, &mut self.secondary_color);
+    }
+
+    /// Back to black on white.
+    pub fn reset_colors(&mut self) {
+        self.primary_color = DEFAULT_PRIMARY;
+        self.secondary_color = DEFAULT_SECONDARY;
+    }
+
+    /// Grows or shrinks the active tool's size by about a tenth, at least a
+    /// pixel, within the range its toolbar allows. Tools without a size are
+    /// left alone.
+    pub fn step_tool_size(&mut self, grow: bool) {
+        let settings = &mut self.tool_settings;
+        let (size, max) = match self.active_tool.name() {
+            "Brush" => (&mut settings.brush_size, 500.0),
+            "Eraser" => (&mut settings.eraser_size, 100.0),
+            "Line" | "Rectangle" | "Ellipse" | "Vector Shape" => (&mut settings.line_width, 20.0),
+            _ => return,
+        };
+        let step = (*size * 0.1).max(1.0);
+        let stepped = if grow { *size + step } else { *size - step };
+        *size = stepped.round().clamp(1.0, max);
+    }
+
+    /// Shows or hides the active layer as an undoable step.
+    pub fn toggle_active_layer_visibility(&mut self) {
+        let idx = self.image.active_index();
+        let Some(layer) = self.image.layers.get(idx) else {
+            return;
+        };
+        let before = layer.properties();
+        let mut after = before.clone();
+        after.visible = !before.visible;
+        let name = if after.visible {
+            "Show Layer"
+        } else {
+            "Hide Layer"
+        };
+        self.command_stack.execute(
+            Box::new(LayerPropertyCommand {
+                name: name.to_string(),
+                layer_index: idx,
+                before,
+                after,
+            }),
+            &mut self.image,
+        );
+    }
+
+    /// Moves the active layer `delta` positions up (positive) or down the
+    /// stack as an undoable step. Does nothing at either end of the stack.
+    pub fn move_active_layer(&mut self, delta: isize) {
+        let from = self.image.active_index();
+        if let Some(to) = from.checked_add_signed(delta) {
+            self.reorder_layer(from, to);
+        }
+    }
+
+    /// Moves the layer at `from` to `to` as an undoable step.
+    pub fn reorder_layer(&mut self, from: usize, to: usize) {
+        if from == to || from >= self.image.layer_count() || to >= self.image.layer_count() {
+            return;
+        }
+        self.command_stack
+            .execute(Box::new(ReorderLayerCommand { from, to }), &mut self.image);
+    }
+
+    /// Adds `layer` directly above the active layer as an undoable step.
+    pub fn add_layer_above(&mut self, name: &str, layer: crate::layers::Layer) {
+        let index = (self.image.active_index() + 1).min(self.image.layer_count());
+        self.command_stack.execute(
+            Box::new(AddLayerCommand {
+                name: name.to_string(),
+                index,
+                layer,
+            }),
+            &mut self.image,
+        );
+    }
+
+    /// Puts a copy of the active layer above it and makes the copy active.
+    pub fn duplicate_active_layer(&mut self) {
+        let Some(mut copy) = self.image.active_layer().cloned() else {
+            return;
+        };
+        copy.name.push_str(" copy");
+        self.add_layer_above("Duplicate Layer", copy);
+    }
+
+    /// Deletes the active layer as an undoable step. Does nothing if it is the
+    /// only layer.
+    pub fn delete_active_layer(&mut self) {
+        let index = self.image.active_index();
+        if self.image.layer_count() <= 1 {
+            return;
+        }
+        let Some(layer) = self.image.layers.get(index).cloned() else {
+            return;
+        };
+        self.command_stack.execute(
+            Box::new(RemoveLayerCommand { index, layer }),
+            &mut self.image,
+        );
+    }
+
+    /// Mirrors part of the document as an undoable step; see `FlipScope`.
+    pub fn flip(&mut self, axis: FlipAxis, scope: FlipScope) {
+        let (w, h) = (self.image.width(), self.image.height());
+        let active = self.image.active_index();
+        let (name, layers, region, selection) = match scope {
+            FlipScope::Image => (
+                "Flip Image",
+                (0..self.image.layer_count()).collect(),
+                (0, 0, w, h),
+                true,
+            ),
+            FlipScope::Layer => ("Flip Layer", vec![active], (0, 0, w, h), false),
+            FlipScope::Selection => {
+                let Some((x0, y0, x1, y1)) = self.image.selection_bounds() else {
+                    return;
+                };
+                (
+                    "Flip Selection",
+                    vec![active],
+                    (x0, y0, x1 - x0 + 1, y1 - y0 + 1),
+                    true,
+                )
+            }
+        };
+        self.command_stack.execute(
+            Box::new(FlipCommand {
+                name: name.to_string(),
+                axis,
+                layers,
+                region,
+                selection,
+            }),
+            &mut self.image,
+        );
+    }
+
+    /// Crops the document to the selection's bounding box as an undoable
+    /// step. Returns false if nothing is selected.
+    pub fn crop_to_selection(&mut self) -> bool {
+        let Some((x0, y0, x1, y1)) = self.image.selection_bounds() else {
+            return false;
+        };
+        let before = self.image.snapshot();
+        self.image.crop(x0, y0, x1 - x0 + 1, y1 - y0 + 1);
+        let after = self.image.snapshot();
+        self.command_stack.push(Box::new(DocumentCommand {
+            name: "Crop to Selection".to_string(),
+            before,
+            after,
+        }));
+        true
+    }
+
+    /// Gives the active layer a fully revealing mask as an undoable step.
+    pub fn add_active_layer_mask(&mut self) {
+        let (w, h) = (self.image.width(), self.image.height());
+        let mask = image::GrayImage::from_pixel(w, h, image::Luma([255]));
+        self.set_active_layer_mask("Add Mask", Some(mask));
+    }
+
+    /// Discards the active layer's mask as an undoable step.
+    pub fn delete_active_layer_mask(&mut self) {
+        self.set_active_layer_mask("Delete Mask", None);
+    }
+
+    fn set_active_layer_mask(&mut self, name: &str, after: Option<image::GrayImage>) {
+        let layer_index = self.image.active_index();
+        let Some(layer) = self.image.layers.get(layer_index) else {
+            return;
+        };
+        let before = layer.mask.clone();
+        if before.is_none() && after.is_none() {
+            return;
+        }
+        self.command_stack.execute(
+            Box::new(SetMaskCommand {
+                name: name.to_string(),
+                layer_index,
+                before,
+                after,
+            }),
+            &mut self.image,
+        );
+    }
 }
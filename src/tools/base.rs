@@ -1,12 +1,267 @@
-use crate::commands::{Command, PatchCommand};
+use crate::commands::{Command, CompoundCommand, MaskPatchCommand, PatchCommand};
 use crate::image_store::ImageStore;
+use crate::presets::{self, BrushPreset};
 use egui::{Color32, Painter, Pos2, Rect, Ui, Vec2};
-use image::{GenericImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use image::{GenericImage, GenericImageView, GrayImage, ImageBuffer, Pixel, Rgba, RgbaImage};
+use std::collections::VecDeque;
+use std::path::PathBuf;
 
 pub struct ToolInput {
     pub pos: Option<Pos2>,
     pub is_pressed: bool,
     pub is_released: bool,
+    pub shift: bool,
+    pub alt: bool,
+    /// Stylus pressure from 0.0 to 1.0; always 1.0 for a mouse.
+    pub pressure: f32,
+    /// Screen points per image pixel, for hit-testing handles at a constant
+    /// on-screen size.
+    pub zoom: f32,
+    /// Perspective vanishing point that lines should aim at, if guide
+    /// snapping is on.
+    pub vanishing_point: Option<Pos2>,
+    /// The color swatch not being painted with: secondary for a left-button
+    /// drag, primary for a right-button one.
+    pub other_color: Rgba<u8>,
+}
+
+#[cfg(test)]
+impl ToolInput {
+    /// The mouse held down at `pos` at 100% zoom, for driving tools in tests.
+    pub fn pressed_at(pos: Pos2) -> Self {
+        Self {
+            pos: Some(pos),
+            is_pressed: true,
+            is_released: false,
+            shift: false,
+            alt: false,
+            pressure: 1.0,
+            zoom: 1.0,
+            vanishing_point: None,
+            other_color: Rgba([255, 255, 255, 255]),
+        }
+    }
+}
+
+/// Maps image pixel coordinates onto the screen for the current pan and zoom.
+#[derive(Clone, Copy)]
+pub struct ViewTransform {
+    /// Screen position of the image's top-left corner.
+    pub origin: Pos2,
+    pub zoom: f32,
+}
+
+impl ViewTransform {
+    pub fn screen_pos(&self, pos: Pos2) -> Pos2 {
+        self.origin + pos.to_vec2() * self.zoom
+    }
+
+    pub fn screen_rect(&self, rect: Rect) -> Rect {
+        Rect::from_min_max(self.screen_pos(rect.min), self.screen_pos(rect.max))
+    }
+
+    /// The image position under a screen position; the inverse of `screen_pos`.
+    pub fn image_pos(&self, pos: Pos2) -> Pos2 {
+        ((pos - self.origin) / self.zoom).to_pos2()
+    }
+}
+
+/// Writes a tool's temporary layer within `rect` onto the active layer and
+/// clears it, through `commit_stamp`. Returns the undo step, or `None` if
+/// nothing changed.
+pub fn commit_temp_layer(
+    image: &mut ImageStore,
+    temp: &mut RgbaImage,
+    rect: Rect,
+    name: &str,
+    behind: bool,
+    blend: impl Fn(Rgba<u8>, Rgba<u8>) -> Rgba<u8>,
+) -> Option<Box<dyn Command>> {
+    let (x, y, stamp) = take_stamp(image, temp, rect)?;
+    commit_stamp(
+        image,
+        image.active_layer,
+        &stamp,
+        (x, y),
+        name,
+        behind,
+        blend,
+    )
+}
+
+/// Writes `stamp`, placed at `(x, y)`, onto the raster or tone layer at
+/// `layer_index`. Each covered pixel inside the selection becomes
+/// `blend(existing, stamp)`, with the stamp's alpha scaled by the selection
+/// value, subject to the layer's alpha and channel locks.
+/// With `behind`, only fully transparent pixels are painted.
+/// Returns the undo step, or `None` if nothing changed.
+pub fn commit_stamp(
+    image: &mut ImageStore,
+    layer_index: usize,
+    stamp: &RgbaImage,
+    (x, y): (u32, u32),
+    name: &str,
+    behind: bool,
+    blend: impl Fn(Rgba<u8>, Rgba<u8>) -> Rgba<u8>,
+) -> Option<Box<dyn Command>> {
+    let (w, h) = stamp.dimensions();
+    let selection = &image.selection;
+    let layer = image.layers.get_mut(layer_index)?;
+    let alpha_locked = layer.alpha_locked;
+    let channel_lock = layer.channel_lock;
+    let target = match &mut layer.data {
+        crate::layers::LayerData::Raster(img) => img,
+        crate::layers::LayerData::Tone { buffer, .. } => buffer,
+        _ => return None,
+    };
+
+    let old_patch = target.view(x, y, w, h).to_image();
+    for (lx, ly, pixel) in stamp.enumerate_pixels() {
+        if pixel[3] == 0 {
+            continue;
+        }
+        let (tx, ty) = (x + lx, y + ly);
+        // Partly selected pixels take the stroke at partial strength.
+        let selected = selection
+            .as_ref()
+            .map_or(255, |mask| mask.get_pixel(tx, ty)[0]);
+        if selected == 0 {
+            continue;
+        }
+        let existing = *target.get_pixel(tx, ty);
+        if (alpha_locked && existing[3] == 0) || (behind && existing[3] > 0) {
+            continue;
+        }
+        let mut result = blend(existing, crate::color::fade(*pixel, selected));
+        if alpha_locked {
+            result[3] = existing[3];
+        }
+        target.put_pixel(
+            tx,
+            ty,
+            crate::layers::lock_channels(channel_lock, existing, result),
+        );
+    }
+    let new_patch = target.view(x, y, w, h).to_image();
+    image.mark_dirty_rect(x, y, w, h);
+
+    PatchCommand::if_changed(name, layer_index, x, y, old_patch, new_patch)
+}
+
+/// Cuts the part of `temp` within `rect`, clamped to the canvas, out of the
+/// temporary layer. Returns its position and pixels, or `None` if empty.
+fn take_stamp(
+    image: &ImageStore,
+    temp: &mut RgbaImage,
+    rect: Rect,
+) -> Option<(u32, u32, RgbaImage)> {
+    let (x, y, w, h) = canvas_region(image, rect)?;
+    let stamp = temp.view(x, y, w, h).to_image();
+    for (lx, ly, _) in stamp.enumerate_pixels() {
+        temp.put_pixel(x + lx, y + ly, Rgba([0, 0, 0, 0]));
+    }
+    Some((x, y, stamp))
+}
+
+/// The whole pixels of `rect` that lie on the canvas, as `(x, y, width,
+/// height)`, or `None` if there are none.
+fn canvas_region(image: &ImageStore, rect: Rect) -> Option<(u32, u32, u32, u32)> {
+    let clamp = |v: f32, max: u32| (v.max(0.0) as u32).min(max);
+    let (x0, y0) = (
+        clamp(rect.min.x, image.width()),
+        clamp(rect.min.y, image.height()),
+    );
+    let (x1, y1) = (
+        clamp(rect.max.x, image.width()),
+        clamp(rect.max.y, image.height()),
+    );
+    (x1 > x0 && y1 > y0).then(|| (x0, y0, x1 - x0, y1 - y0))
+}
+
+/// Whether brush and eraser strokes should go to the active layer's mask.
+pub fn editing_mask(image: &ImageStore, settings: &crate::state::ToolSettings) -> bool {
+    settings.edit_mask && image.active_layer().is_some_and(|l| l.mask.is_some())
+}
+
+/// Paints `stamp`, placed at (`x`, `y`), into the active layer's mask within
+/// the selection. Each covered mask value becomes `value(existing, stamp)`.
+/// Returns the undo step, or `None` if there is no mask or nothing changed.
+pub fn paint_mask(
+    image: &mut ImageStore,
+    stamp: &RgbaImage,
+    x: u32,
+    y: u32,
+    name: &str,
+    value: impl Fn(u8, Rgba<u8>) -> u8,
+) -> Option<Box<dyn Command>> {
+    let layer_index = image.active_layer;
+    let selection = &image.selection;
+    let mask = image.layers.get_mut(layer_index)?.mask.as_mut()?;
+
+    let (w, h) = stamp.dimensions();
+    let old_patch = mask.view(x, y, w, h).to_image();
+    for (lx, ly, pixel) in stamp.enumerate_pixels() {
+        let (tx, ty) = (x + lx, y + ly);
+        let selected = selection
+            .as_ref()
+            .map_or(255, |sel| sel.get_pixel(tx, ty)[0]);
+        if pixel[3] == 0 || selected == 0 {
+            continue;
+        }
+        let existing = mask.get_pixel(tx, ty)[0];
+        let stamp = crate::color::fade(*pixel, selected);
+        mask.put_pixel(tx, ty, image::Luma([value(existing, stamp)]));
+    }
+    let new_patch = mask.view(x, y, w, h).to_image();
+    image.mark_dirty_rect(x, y, w, h);
+
+    MaskPatchCommand::if_changed(name, layer_index, x, y, old_patch, new_patch)
+}
+
+/// Lowers `existing`'s alpha by the stamp pixel's coverage: half coverage
+/// halves it. Alpha lock leaves such pixels alone, since erasing changes
+/// nothing else.
+fn erase_alpha(existing: Rgba<u8>, stamp: Rgba<u8>) -> Rgba<u8> {
+    let coverage = stamp[3] as f32 / 255.0;
+    let mut erased = existing;
+    erased[3] = (existing[3] as f32 * (1.0 - coverage)).round() as u8;
+    erased
+}
+
+/// Moves `existing` toward `target` by the stamp pixel's coverage.
+fn mix_mask(existing: u8, target: u8, stamp: Rgba<u8>) -> u8 {
+    let t = stamp[3] as f32 / 255.0;
+    (existing as f32 + (target as f32 - existing as f32) * t).round() as u8
+}
+
+/// Rotates `pos` about `start` onto the nearest multiple of `step` degrees,
+/// keeping the line's length.
+pub fn snap_angle(start: Pos2, pos: Pos2, step: f32) -> Pos2 {
+    let delta = pos - start;
+    let length = delta.length();
+    if length == 0.0 {
+        return pos;
+    }
+    let step = step.to_radians();
+    let angle = (delta.y.atan2(delta.x) / step).round() * step;
+    let snapped = start + Vec2::angled(angle) * length;
+    Pos2::new(snapped.x.round(), snapped.y.round())
+}
+
+/// Opposite corners of a shape dragged from `start` to `end`. `square` grows
+/// the shorter side to match the longer one, for squares and circles;
+/// `centered` makes `start` the center and mirrors the drag around it.
+pub fn drag_rect(start: Pos2, end: Pos2, square: bool, centered: bool) -> (Pos2, Pos2) {
+    let mut delta = end - start;
+    if square {
+        let side = delta.x.abs().max(delta.y.abs());
+        delta = Vec2::new(side.copysign(delta.x), side.copysign(delta.y));
+    }
+    if centered {
+        (start - delta, start + delta)
+    } else {
+        (start, start + delta)
+    }
 }
 
 pub trait Tool {
@@ -20,35 +275,89 @@ pub trait Tool {
         color: Rgba<u8>,
     ) -> Option<Box<dyn Command>>;
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)>;
+    /// The stroke in progress, drawn over the canvas until it is committed:
+    /// a canvas-sized buffer and the area of it that holds paint.
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)>;
 
+    /// Draws the cursor at the screen position `pos`. Sizes in `settings` are
+    /// in image pixels; scale them by `view.zoom` to match what gets painted.
     fn draw_cursor(
         &self,
         ui: &mut Ui,
         painter: &Painter,
         settings: &crate::state::ToolSettings,
         pos: Pos2,
+        view: &ViewTransform,
     );
 
     fn configure(&mut self, ui: &mut Ui, settings: &mut crate::state::ToolSettings);
+
+    /// Draws tool state that lives on the canvas (frames, guides) every frame,
+    /// whether or not the pointer is over the image.
+    fn draw_overlay(&self, _painter: &Painter, _view: &ViewTransform) {}
+
+    /// Abandons any in-progress interaction. Called when Escape is pressed.
+    fn cancel(&mut self) {}
+
+    /// A color the tool sampled since the last call, for the UI to make the
+    /// current drawing color.
+    fn take_picked_color(&mut self) -> Option<Rgba<u8>> {
+        None
+    }
+
+    /// A short note on what the tool is doing or waiting for, shown in the
+    /// status bar.
+    fn status(&self) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Axis {
+    Horizontal,
+    Vertical,
 }
 
 pub struct BrushTool {
     pub texture: Option<RgbaImage>,
+    // File `texture` was loaded from, remembered for presets.
+    texture_path: Option<PathBuf>,
+    presets: Vec<BrushPreset>,
     layer: RgbaImage,
     last_pos: Option<Pos2>,
     stabilized_pos: Option<Pos2>,
+    recent_positions: VecDeque<Pos2>,
     dirty_rect: Option<Rect>,
+    // Shift-constrained stroke: the point Shift was pressed at and the axis
+    // chosen once the stroke has moved far enough to tell.
+    constrain_anchor: Option<Pos2>,
+    constrain_axis: Option<Axis>,
+    // Where the current press started, until the pointer leaves `CLICK_SLOP`
+    // of it. While set, the press is treated as a click placing one dab.
+    click_origin: Option<Pos2>,
+    // Pressure at `last_pos`, so a segment can ease to the new pressure.
+    last_pressure: f32,
 }
 
+/// How far, in image pixels, the pointer may drift during a click before it
+/// counts as a stroke and stabilization takes over.
+const CLICK_SLOP: f32 = 1.0;
+
 impl BrushTool {
     pub fn new(width: u32, height: u32) -> Self {
         Self {
             texture: None,
+            texture_path: None,
+            presets: presets::load(),
             layer: ImageBuffer::new(width, height),
             last_pos: None,
             stabilized_pos: None,
+            recent_positions: VecDeque::new(),
             dirty_rect: None,
+            constrain_anchor: None,
+            constrain_axis: None,
+            click_origin: None,
+            last_pressure: 1.0,
         }
     }
 
@@ -59,26 +368,229 @@ impl BrushTool {
         });
     }
 
-    fn draw_segment(&mut self, start: Pos2, end: Pos2, color: Rgba<u8>, size: f32, spacing: f32) {
+    /// Loads the brush texture from `path`, clearing it if that fails.
+    fn load_texture(&mut self, path: PathBuf) {
+        match image::open(&path) {
+            Ok(img) => {
+                self.texture = Some(img.to_rgba8());
+                self.texture_path = Some(path);
+            }
+            Err(e) => {
+                log::error!("Failed to load brush texture {:?}: {}", path, e);
+                self.texture = None;
+                self.texture_path = None;
+            }
+        }
+    }
+
+    /// The presets strip: one button per saved preset, plus "Save Current".
+    /// Right-click a preset to delete it.
+    fn presets_ui(&mut self, ui: &mut Ui, settings: &mut crate::state::ToolSettings) {
+        let mut apply = None;
+        let mut delete = None;
+        let mut changed = false;
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Presets:");
+            for (i, preset) in self.presets.iter().enumerate() {
+                let response = ui.button(&preset.name).on_hover_text(format!(
+                    "Size {:.0}, hardness {:.2}, opacity {:.2}, flow {:.2}",
+                    preset.size, preset.hardness, preset.opacity, preset.flow
+                ));
+                if response.clicked() {
+                    apply = Some(i);
+                }
+                response.context_menu(|ui| {
+                    if ui.button("Delete").clicked() {
+                        delete = Some(i);
+                        ui.close_menu();
+                    }
+                });
+            }
+            if ui.button("Save Current").clicked() {
+                let name = format!("Brush {}", self.presets.len() + 1);
+                self.presets.push(BrushPreset::capture(
+                    name,
+                    settings,
+                    self.texture_path.clone(),
+                ));
+                changed = true;
+            }
+        });
+
+        if let Some(i) = apply {
+            let preset = self.presets[i].clone();
+            preset.apply(settings);
+            match preset.texture {
+                Some(path) => self.load_texture(path),
+                None => {
+                    self.texture = None;
+                    self.texture_path = None;
+                }
+            }
+        }
+        if let Some(i) = delete {
+            self.presets.remove(i);
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = presets::save(&self.presets) {
+                log::error!("Failed to save brush presets: {:#}", e);
+            }
+        }
+    }
+
+    /// Resolves a stroke pixel against the layer pixel beneath it. Recolor
+    /// modes blend by the stroke's coverage and never change the layer's alpha.
+    fn apply_mode(mode: crate::state::BrushMode, target: Rgba<u8>, stroke: Rgba<u8>) -> Rgba<u8> {
+        use crate::color::{mix_rgb, over, with_hue_saturation_of, with_value_of};
+        use crate::state::BrushMode;
+
+        let coverage = stroke[3] as f32 / 255.0;
+        match mode {
+            BrushMode::Normal => over(target, stroke),
+            BrushMode::Color => mix_rgb(target, with_hue_saturation_of(target, stroke), coverage),
+            BrushMode::Value => mix_rgb(target, with_value_of(target, stroke), coverage),
+        }
+    }
+
+    /// Smooths the raw pointer position according to `settings.stabilizer`.
+    fn stabilize(&mut self, target: Pos2, settings: &crate::state::ToolSettings) -> Pos2 {
+        use crate::state::StabilizerKind;
+
+        let Some(last_s) = self.stabilized_pos else {
+            self.recent_positions.clear();
+            self.recent_positions.push_back(target);
+            return target;
+        };
+
+        match settings.stabilizer {
+            StabilizerKind::Ema => {
+                let weight = settings.brush_stabilization.clamp(0.0, 0.95);
+                let smoothed_x = last_s.x * weight + target.x * (1.0 - weight);
+                let smoothed_y = last_s.y * weight + target.y * (1.0 - weight);
+                Pos2::new(smoothed_x, smoothed_y)
+            }
+            StabilizerKind::MovingAverage => {
+                let window = settings.stabilizer_window.max(1);
+                self.recent_positions.push_back(target);
+                while self.recent_positions.len() > window {
+                    self.recent_positions.pop_front();
+                }
+                let n = self.recent_positions.len() as f32;
+                let sum = self
+                    .recent_positions
+                    .iter()
+                    .fold(Vec2::ZERO, |acc, p| acc + p.to_vec2());
+                (sum / n).to_pos2()
+            }
+            StabilizerKind::Pull => {
+                let leash = settings.stabilizer_leash.max(0.0);
+                let delta = target - last_s;
+                let dist = delta.length();
+                if dist > leash {
+                    last_s + delta * ((dist - leash) / dist)
+                } else {
+                    last_s
+                }
+            }
+        }
+    }
+
+    /// Locks `pos` to a horizontal or vertical line through the anchor captured
+    /// when Shift went down. The axis is whichever the stroke first moves along.
+    fn constrain(&mut self, pos: Pos2, shift: bool) -> Pos2 {
+        if !shift {
+            self.constrain_anchor = None;
+            self.constrain_axis = None;
+            return pos;
+        }
+
+        let anchor = *self
+            .constrain_anchor
+            .get_or_insert(self.last_pos.unwrap_or(pos));
+
+        if self.constrain_axis.is_none() {
+            let delta = pos - anchor;
+            if delta.length() < 2.0 {
+                return anchor;
+            }
+            self.constrain_axis = Some(if delta.x.abs() >= delta.y.abs() {
+                Axis::Horizontal
+            } else {
+                Axis::Vertical
+            });
+        }
+
+        match self.constrain_axis {
+            Some(Axis::Horizontal) => Pos2::new(pos.x, anchor.y),
+            Some(Axis::Vertical) => Pos2::new(anchor.x, pos.y),
+            None => pos,
+        }
+    }
+
+    /// Brush size and opacity for a dab at `pressure`, scaled on whichever
+    /// axes have pressure turned on.
+    fn dab_size_opacity(settings: &crate::state::ToolSettings, pressure: f32) -> (f32, f32) {
+        let pressure = pressure.clamp(0.0, 1.0);
+        let size = if settings.pressure_size {
+            (settings.brush_size * pressure).max(1.0)
+        } else {
+            settings.brush_size
+        };
+        let opacity = if settings.pressure_opacity {
+            settings.brush_opacity * pressure
+        } else {
+            settings.brush_opacity
+        };
+        (size, opacity)
+    }
+
+    fn draw_dab(
+        &mut self,
+        pos: Pos2,
+        color: Rgba<u8>,
+        settings: &crate::state::ToolSettings,
+        pressure: f32,
+    ) {
+        if self.texture.is_some() {
+            self.draw_texture_stamp(pos, color, settings, pressure);
+        } else {
+            self.draw_stamp(pos, color, settings, pressure);
+        }
+    }
+
+    /// Stamps dabs from `start` to `end`, easing the pressure between the two.
+    fn draw_segment(
+        &mut self,
+        (start, start_pressure): (Pos2, f32),
+        (end, end_pressure): (Pos2, f32),
+        color: Rgba<u8>,
+        settings: &crate::state::ToolSettings,
+    ) {
+        let (size, _) = Self::dab_size_opacity(settings, start_pressure.min(end_pressure));
         let dist = start.distance(end);
-        let step_dist = (size * spacing).max(1.0);
+        let step_dist = (size * settings.brush_spacing).max(1.0);
         let steps = (dist / step_dist).max(1.0) as u32;
 
         for i in 0..=steps {
             let t = i as f32 / steps as f32;
             let pos = start.lerp(end, t);
-            if self.texture.is_some() {
-                self.draw_texture_stamp(pos, color, size);
-            } else {
-                self.draw_circle(pos, color, size);
-            }
+            let pressure = start_pressure + (end_pressure - start_pressure) * t;
+            self.draw_dab(pos, color, settings, pressure);
         }
     }
 
-    fn draw_texture_stamp(&mut self, pos: Pos2, color: Rgba<u8>, size: f32) {
+    fn draw_texture_stamp(
+        &mut self,
+        pos: Pos2,
+        color: Rgba<u8>,
+        settings: &crate::state::ToolSettings,
+        pressure: f32,
+    ) {
         if self.texture.is_none() {
             return;
         }
+        let (size, opacity) = Self::dab_size_opacity(settings, pressure);
         let (tw, th) = self.texture.as_ref().unwrap().dimensions();
         let scale_x = size * 2.0 / tw as f32;
         let scale_y = size * 2.0 / th as f32;
@@ -106,35 +618,60 @@ impl BrushTool {
 
                     if target_x >= 0 && target_x < width && target_y >= 0 && target_y < height {
                         let tex_pixel = self.texture.as_ref().unwrap().get_pixel(tx, ty);
-                        let alpha = (tex_pixel[3] as f32 / 255.0) * (color[3] as f32 / 255.0);
-                        if alpha > 0.0 {
-                            let mut final_color = color;
-                            final_color[3] = (alpha * 255.0) as u8;
-                            let existing = self.layer.get_pixel(target_x as u32, target_y as u32);
-                            if alpha > (existing[3] as f32 / 255.0) {
-                                self.layer
-                                    .put_pixel(target_x as u32, target_y as u32, final_color);
-                            }
-                        }
+                        let dab = tex_pixel[3] as f32 / 255.0 * color[3] as f32 * opacity;
+                        self.build_up(
+                            target_x as u32,
+                            target_y as u32,
+                            color,
+                            dab,
+                            settings.brush_flow,
+                        );
                     }
                 }
             }
         }
     }
 
-    fn draw_circle(&mut self, pos: Pos2, color: Rgba<u8>, size: f32) {
+    /// Stamps the brush tip, an ellipse of radius `brush_size` squashed by
+    /// `brush_roundness` and rotated by `brush_angle`. A round, aliased tip
+    /// covers exactly the pixels the old circle stamp did; with
+    /// `brush_antialias` the edge fades out over one pixel instead. Below full
+    /// `brush_hardness` the tip also fades toward its outline.
+    fn draw_stamp(
+        &mut self,
+        pos: Pos2,
+        color: Rgba<u8>,
+        settings: &crate::state::ToolSettings,
+        pressure: f32,
+    ) {
+        let (size, opacity) = Self::dab_size_opacity(settings, pressure);
         let x = pos.x as i32;
         let y = pos.y as i32;
-        let r = size as i32;
-        let r_sq = r * r;
+        let a = (size as i32) as f32;
+        let minor = a * settings.brush_roundness.clamp(0.01, 1.0);
+        // Tips thinner than a pixel still hit a one-pixel line, but only with
+        // the coverage they actually have.
+        let b = minor.max(0.5);
+        let coverage = (minor / b).min(1.0);
+        let (sin, cos) = settings.brush_angle.to_radians().sin_cos();
+        let antialias = settings.brush_antialias;
+        let hardness = settings.brush_hardness.clamp(0.0, 1.0);
+        // The soft edge reaches half a pixel past the outline.
+        let fringe = if antialias { 1 } else { 0 };
+
+        let extent_x = (a * a * cos * cos + b * b * sin * sin).sqrt().ceil() as i32 + fringe;
+        let extent_y = (a * a * sin * sin + b * b * cos * cos).sqrt().ceil() as i32 + fringe;
 
         let width = self.layer.width() as i32;
         let height = self.layer.height() as i32;
 
-        let min_x = (x - r).max(0);
-        let max_x = (x + r).min(width - 1);
-        let min_y = (y - r).max(0);
-        let max_y = (y + r).min(height - 1);
+        let min_x = (x - extent_x).max(0);
+        let max_x = (x + extent_x).min(width - 1);
+        let min_y = (y - extent_y).max(0);
+        let max_y = (y + extent_y).min(height - 1);
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
 
         let rect = Rect::from_min_max(
             Pos2::new(min_x as f32, min_y as f32),
@@ -142,14 +679,78 @@ impl BrushTool {
         );
         self.expand_dirty_rect(rect);
 
+        let alpha = color[3] as f32 * opacity * coverage;
+        if alpha.round() == 0.0 {
+            return;
+        }
+
         for cy in min_y..=max_y {
             for cx in min_x..=max_x {
-                if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r_sq {
-                    self.layer.put_pixel(cx as u32, cy as u32, color);
-                }
+                let dx = (cx - x) as f32;
+                let dy = (cy - y) as f32;
+                // Offsets in the tip's own frame: u along the major axis.
+                let u = dx * cos + dy * sin;
+                let v = -dx * sin + dy * cos;
+                // Normalized radius: 1.0 on the tip's outline.
+                let n = ((u / a).powi(2) + (v / b).powi(2)).sqrt();
+                let edge = if antialias {
+                    tip_edge_coverage(u, v, n)
+                } else if u * u * b * b + v * v * a * a <= a * a * b * b {
+                    1.0
+                } else {
+                    0.0
+                };
+                let dab = alpha * edge * tip_falloff(n, hardness);
+                self.build_up(cx as u32, cy as u32, color, dab, settings.brush_flow);
             }
         }
     }
+
+    /// Adds `flow` of a dab with alpha `dab` to the stroke at (`x`, `y`).
+    /// Overlapping dabs add up, but never past the dab itself, so a pixel
+    /// ends at its strongest dab: full flow reaches it at once, lower flow
+    /// over several passes.
+    fn build_up(&mut self, x: u32, y: u32, color: Rgba<u8>, dab: f32, flow: f32) {
+        let existing = self.layer.get_pixel(x, y)[3] as f32;
+        if dab.round() <= existing {
+            return;
+        }
+        // At least one step per dab, or a low flow would never arrive.
+        let alpha = (existing + (dab * flow.clamp(0.0, 1.0)).max(1.0)).min(dab);
+        let mut stamp_color = color;
+        stamp_color[3] = alpha.round() as u8;
+        self.layer.put_pixel(x, y, stamp_color);
+    }
+}
+
+/// How much of the pixel at offset (`u`, `v`) from the center lies inside the
+/// tip, given its normalized radius `n`, smoothstepped across a one-pixel band
+/// centered on the outline.
+fn tip_edge_coverage(u: f32, v: f32, n: f32) -> f32 {
+    let dist = (u * u + v * v).sqrt();
+    if n == 0.0 {
+        return 1.0;
+    }
+    // Distance past the outline along the ray from the center; exact for a
+    // circle and close enough for the ellipses a brush tip uses.
+    let outside = dist - dist / n;
+    smoothstep((0.5 - outside).clamp(0.0, 1.0))
+}
+
+/// Strength of a soft tip at normalized radius `n`: full out to `hardness`,
+/// then easing to zero at the outline.
+fn tip_falloff(n: f32, hardness: f32) -> f32 {
+    // A solid tip leaves its outline, including the anti-aliased fringe
+    // past it, to `tip_edge_coverage`.
+    if n <= hardness || hardness >= 1.0 {
+        return 1.0;
+    }
+    let t = ((n - hardness) / (1.0 - hardness)).clamp(0.0, 1.0);
+    1.0 - smoothstep(t)
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
 }
 
 impl Tool for BrushTool {
@@ -167,119 +768,78 @@ impl Tool for BrushTool {
         if self.layer.width() != image.width() || self.layer.height() != image.height() {
             self.layer = ImageBuffer::new(image.width(), image.height());
         }
+        if image.active_layer_is_locked() {
+            return None;
+        }
 
         if input.is_pressed {
             if let Some(target_pos) = input.pos {
-                let current_stabilized = if let Some(last_s) = self.stabilized_pos {
-                    let weight = settings.brush_stabilization.clamp(0.0, 0.95);
-                    let smoothed_x = last_s.x * weight + target_pos.x * (1.0 - weight);
-                    let smoothed_y = last_s.y * weight + target_pos.y * (1.0 - weight);
-                    Pos2::new(smoothed_x, smoothed_y)
-                } else {
-                    target_pos
-                };
+                let first = self.last_pos.is_none();
+                if first {
+                    self.click_origin = Some(target_pos);
+                } else if self
+                    .click_origin
+                    .is_some_and(|origin| target_pos.distance(origin) > CLICK_SLOP)
+                {
+                    self.click_origin = None;
+                }
 
-                if let Some(last) = self.last_pos {
-                    self.draw_segment(
-                        last,
-                        current_stabilized,
-                        color,
-                        settings.brush_size,
-                        settings.brush_spacing,
-                    );
-                } else {
-                    if self.texture.is_some() {
-                        self.draw_texture_stamp(current_stabilized, color, settings.brush_size);
+                // Until the pointer leaves the slop the press is a click, and its
+                // one dab stays where it landed instead of drifting with jitter.
+                if first || self.click_origin.is_none() {
+                    let current_stabilized = self.stabilize(target_pos, settings);
+                    let current_stabilized = self.constrain(current_stabilized, input.shift);
+
+                    if let Some(last) = self.last_pos {
+                        self.draw_segment(
+                            (last, self.last_pressure),
+                            (current_stabilized, input.pressure),
+                            color,
+                            settings,
+                        );
                     } else {
-                        self.draw_circle(current_stabilized, color, settings.brush_size);
+                        self.draw_dab(current_stabilized, color, settings, input.pressure);
                     }
-                }
 
-                self.last_pos = Some(current_stabilized);
-                self.stabilized_pos = Some(current_stabilized);
+                    self.last_pos = Some(current_stabilized);
+                    self.last_pressure = input.pressure;
+                    self.stabilized_pos = Some(current_stabilized);
+                }
             }
         } else {
+            self.click_origin = None;
             self.last_pos = None;
             self.stabilized_pos = None;
+            self.constrain_anchor = None;
+            self.constrain_axis = None;
         }
 
         if input.is_released {
-            if let Some(rect) = self.dirty_rect {
-                let x = rect.min.x as u32;
-                let y = rect.min.y as u32;
-                let w = rect.width() as u32;
-                let h = rect.height() as u32;
-                let w = w.min(image.width() - x);
-                let h = h.min(image.height() - y);
-
-                let layer_index = image.active_layer;
-                let selection = &image.selection;
-                let layer = &mut image.layers[layer_index];
-                let alpha_locked = layer.alpha_locked;
-
-                let target_buffer = match &mut layer.data {
-                    crate::layers::LayerData::Raster(img) => Some(img),
-                    crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
-                    _ => None,
-                };
-
-                if let Some(target_buffer) = target_buffer {
-                    if w > 0 && h > 0 {
-                        let old_patch = target_buffer.view(x, y, w, h).to_image();
-                        let layer_patch = self.layer.view(x, y, w, h).to_image();
-                        for ly in 0..h {
-                            for lx in 0..w {
-                                let pixel = layer_patch.get_pixel(lx, ly);
-                                if pixel[3] > 0 {
-                                    let mut selected = true;
-                                    if let Some(mask) = selection {
-                                        let mask_val = mask.get_pixel(x + lx, y + ly)[0];
-                                        if mask_val == 0 {
-                                            selected = false;
-                                        }
-                                    }
-
-                                    if selected {
-                                        let target_pixel = target_buffer.get_pixel(x + lx, y + ly);
-                                        if !alpha_locked || target_pixel[3] > 0 {
-                                            let mut final_pixel = *pixel;
-                                            if alpha_locked {
-                                                final_pixel[3] = target_pixel[3];
-                                            }
-                                            target_buffer.put_pixel(x + lx, y + ly, final_pixel);
-                                        }
-                                    }
-                                    self.layer.put_pixel(x + lx, y + ly, Rgba([0, 0, 0, 0]));
-                                }
-                            }
-                        }
-                        let new_patch = target_buffer.view(x, y, w, h).to_image();
-                        image.mark_dirty();
-                        self.dirty_rect = None;
-
-                        return Some(Box::new(PatchCommand {
-                            name: "Brush Stroke".to_string(),
-                            layer_index,
-                            x,
-                            y,
-                            old_patch,
-                            new_patch,
-                        }));
-                    }
+            if let Some(rect) = self.dirty_rect.take() {
+                if editing_mask(image, settings) {
+                    // Black hides, white reveals, grays in between.
+                    let (x, y, stamp) = take_stamp(image, &mut self.layer, rect)?;
+                    return paint_mask(image, &stamp, x, y, "Paint Mask", |m, p| {
+                        mix_mask(m, p.to_luma()[0], p)
+                    });
                 }
+                let mode = settings.brush_mode;
+                return commit_temp_layer(
+                    image,
+                    &mut self.layer,
+                    rect,
+                    "Brush Stroke",
+                    settings.fill_behind,
+                    |t, p| Self::apply_mode(mode, t, p),
+                );
             }
-            self.dirty_rect = None;
         }
 
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
-        if self.dirty_rect.is_some() {
-            Some((&self.layer, 0, 0))
-        } else {
-            None
-        }
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        self.dirty_rect.map(|rect| (&self.layer, rect))
     }
 
     fn draw_cursor(
@@ -288,50 +848,150 @@ impl Tool for BrushTool {
         painter: &Painter,
         settings: &crate::state::ToolSettings,
         pos: Pos2,
+        view: &ViewTransform,
     ) {
-        painter.circle_stroke(
-            pos,
-            settings.brush_size,
-            egui::Stroke::new(1.0, Color32::WHITE),
-        );
+        let stroke = egui::Stroke::new(1.0, Color32::WHITE);
+        let a = settings.brush_size * view.zoom;
+        if settings.brush_roundness >= 1.0 || self.texture.is_some() {
+            painter.circle_stroke(pos, a, stroke);
+            return;
+        }
+
+        let b = a * settings.brush_roundness.max(0.01);
+        let (sin, cos) = settings.brush_angle.to_radians().sin_cos();
+        let points = (0..48)
+            .map(|i| {
+                let t = i as f32 / 48.0 * std::f32::consts::TAU;
+                let (u, v) = (a * t.cos(), b * t.sin());
+                pos + Vec2::new(u * cos - v * sin, u * sin + v * cos)
+            })
+            .collect();
+        painter.add(egui::Shape::closed_line(points, stroke));
     }
 
     fn configure(&mut self, ui: &mut Ui, settings: &mut crate::state::ToolSettings) {
+        use crate::state::{BrushMode, StabilizerKind};
+
         ui.horizontal(|ui| {
             ui.label("Size:");
             ui.add(egui::DragValue::new(&mut settings.brush_size).range(1.0..=500.0));
-            ui.label("Smoothing:");
-            ui.add(egui::Slider::new(
-                &mut settings.brush_stabilization,
-                0.0..=0.95,
-            ));
+            egui::ComboBox::from_id_salt("brush_stabilizer")
+                .selected_text(match settings.stabilizer {
+                    StabilizerKind::Ema => "Smoothing",
+                    StabilizerKind::MovingAverage => "Average",
+                    StabilizerKind::Pull => "Pull",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.stabilizer, StabilizerKind::Ema, "Smoothing");
+                    ui.selectable_value(
+                        &mut settings.stabilizer,
+                        StabilizerKind::MovingAverage,
+                        "Average",
+                    );
+                    ui.selectable_value(&mut settings.stabilizer, StabilizerKind::Pull, "Pull");
+                });
+            match settings.stabilizer {
+                StabilizerKind::Ema => {
+                    ui.add(egui::Slider::new(
+                        &mut settings.brush_stabilization,
+                        0.0..=0.95,
+                    ));
+                }
+                StabilizerKind::MovingAverage => {
+                    ui.label("Window:");
+                    ui.add(egui::DragValue::new(&mut settings.stabilizer_window).range(1..=64));
+                }
+                StabilizerKind::Pull => {
+                    ui.label("Leash:");
+                    ui.add(egui::DragValue::new(&mut settings.stabilizer_leash).range(0.0..=200.0));
+                }
+            }
         });
 
         ui.horizontal(|ui| {
+            ui.label("Angle:");
+            ui.add(
+                egui::DragValue::new(&mut settings.brush_angle)
+                    .range(-180.0..=180.0)
+                    .suffix("°"),
+            );
+            ui.label("Roundness:");
+            ui.add(egui::Slider::new(&mut settings.brush_roundness, 0.05..=1.0));
+            ui.label("Hardness:");
+            ui.add(egui::Slider::new(&mut settings.brush_hardness, 0.0..=1.0));
+            ui.checkbox(&mut settings.brush_antialias, "Anti-alias")
+                .on_hover_text("Soften the tip's edge; turn off for hard pixel edges");
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Opacity:");
+            ui.add(egui::Slider::new(&mut settings.brush_opacity, 0.0..=1.0))
+                .on_hover_text("Most a single stroke can cover");
+            ui.label("Flow:");
+            ui.add(egui::Slider::new(&mut settings.brush_flow, 0.01..=1.0))
+                .on_hover_text("How much each dab adds; low flow builds up as you paint over");
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.pressure_size, "Pressure → Size");
+            ui.checkbox(&mut settings.pressure_opacity, "Pressure → Opacity");
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Mode:");
+            egui::ComboBox::from_id_salt("brush_mode")
+                .selected_text(format!("{:?}", settings.brush_mode))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.brush_mode, BrushMode::Normal, "Normal");
+                    ui.selectable_value(&mut settings.brush_mode, BrushMode::Color, "Color")
+                        .on_hover_text("Recolor: keep the layer's shading, take the brush hue");
+                    ui.selectable_value(&mut settings.brush_mode, BrushMode::Value, "Value")
+                        .on_hover_text("Relight: keep the layer's hue, take the brush value");
+                });
             ui.label("Spacing:");
             ui.add(egui::Slider::new(&mut settings.brush_spacing, 0.01..=2.0));
+            ui.checkbox(&mut settings.fill_behind, "Behind")
+                .on_hover_text("Paint only where the layer is transparent");
 
             if ui.button("Load Texture").clicked() {
                 if let Some(path) = rfd::FileDialog::new()
                     .add_filter("Image", &["png", "jpg", "bmp"])
                     .pick_file()
                 {
-                    if let Ok(img) = image::open(path) {
-                        self.texture = Some(img.to_rgba8());
-                    }
+                    self.load_texture(path);
                 }
             }
             if self.texture.is_some() && ui.button("Clear Texture").clicked() {
                 self.texture = None;
+                self.texture_path = None;
             }
         });
+
+        self.presets_ui(ui, settings);
     }
 }
 
 pub struct EraserTool {
+    /// Erase strength accumulated over the stroke, in alpha.
     layer: RgbaImage,
     last_pos: Option<Pos2>,
     dirty_rect: Option<Rect>,
+    /// Erase from every visible layer rather than just the active one.
+    all_layers: bool,
+    /// What the stroke erases from, as it was before the stroke began. The
+    /// stroke is applied to the layers as it grows, so the canvas shows what
+    /// it reveals; each new dab's area is erased afresh from this.
+    before: Option<Unerased>,
+    /// Set by `cancel`, which can't reach the image: the next update puts
+    /// the layers back and the rest of the stroke is ignored.
+    cancelled: bool,
+}
+
+/// The eraser's targets before a stroke.
+enum Unerased {
+    /// The active layer's mask, at this layer index.
+    Mask(usize, GrayImage),
+    Layers(Vec<(usize, RgbaImage)>),
 }
 
 impl EraserTool {
@@ -340,6 +1000,9 @@ impl EraserTool {
             layer: ImageBuffer::new(width, height),
             last_pos: None,
             dirty_rect: None,
+            all_layers: false,
+            before: None,
+            cancelled: false,
         }
     }
 
@@ -350,27 +1013,33 @@ impl EraserTool {
         });
     }
 
-    fn draw_segment(&mut self, start: Pos2, end: Pos2, size: f32) {
+    /// Returns the area the dabs covered, if any of it is on the canvas.
+    fn draw_segment(&mut self, start: Pos2, end: Pos2, size: f32, strength: f32) -> Option<Rect> {
         let dist = start.distance(end);
         let steps = (dist / 1.0).max(1.0) as u32;
-        for i in 0..=steps {
-            let t = i as f32 / steps as f32;
-            let pos = start.lerp(end, t);
-            self.draw_circle(pos, size);
-        }
+        (0..=steps)
+            .filter_map(|i| {
+                let t = i as f32 / steps as f32;
+                let pos = start.lerp(end, t);
+                self.draw_circle(pos, size, strength)
+            })
+            .reduce(|a, b| a.union(b))
     }
 
-    fn draw_circle(&mut self, pos: Pos2, size: f32) {
+    fn draw_circle(&mut self, pos: Pos2, size: f32, strength: f32) -> Option<Rect> {
         let x = pos.x as i32;
         let y = pos.y as i32;
-        let r = size as i32;
-        let r_sq = r * r;
+        // One pixel more for the anti-aliased fringe.
+        let r = size as i32 + 1;
         let width = self.layer.width() as i32;
         let height = self.layer.height() as i32;
         let min_x = (x - r).max(0);
         let max_x = (x + r).min(width - 1);
         let min_y = (y - r).max(0);
         let max_y = (y + r).min(height - 1);
+        if min_x > max_x || min_y > max_y {
+            return None;
+        }
 
         let rect = Rect::from_min_max(
             Pos2::new(min_x as f32, min_y as f32),
@@ -378,14 +1047,134 @@ impl EraserTool {
         );
         self.expand_dirty_rect(rect);
 
-        let color = Rgba([255, 255, 255, 128]);
+        // Alpha is erase coverage: 255 removes the pixel outright. Dabs
+        // overlap along the stroke, so keep the strongest rather than adding
+        // up, and one stroke at half strength halves alpha once.
         for cy in min_y..=max_y {
             for cx in min_x..=max_x {
-                if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r_sq {
-                    self.layer.put_pixel(cx as u32, cy as u32, color);
+                let (u, v) = ((cx - x) as f32, (cy - y) as f32);
+                let n = (u * u + v * v).sqrt() / size.max(0.5);
+                let coverage = (strength * tip_edge_coverage(u, v, n) * 255.0).round() as u8;
+                let pixel = self.layer.get_pixel_mut(cx as u32, cy as u32);
+                if coverage > pixel[3] {
+                    *pixel = Rgba([255, 255, 255, coverage]);
+                }
+            }
+        }
+        Some(rect)
+    }
+
+    /// Copies what the stroke will erase from: the layer mask, the active
+    /// layer or every visible unlocked layer.
+    fn snapshot(&self, image: &ImageStore, settings: &crate::state::ToolSettings) -> Unerased {
+        if editing_mask(image, settings) {
+            let index = image.active_layer;
+            let mask = image.layers[index].mask.clone().unwrap_or_default();
+            return Unerased::Mask(index, mask);
+        }
+        let targets: Vec<usize> = if self.all_layers {
+            (0..image.layers.len())
+                .filter(|&i| image.layers[i].visible && !image.layers[i].locked)
+                .collect()
+        } else {
+            vec![image.active_layer]
+        };
+        Unerased::Layers(
+            targets
+                .into_iter()
+                .filter_map(|i| match &image.layers[i].data {
+                    crate::layers::LayerData::Raster(img) => Some((i, img.clone())),
+                    crate::layers::LayerData::Tone { buffer, .. } => Some((i, buffer.clone())),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Puts `rect` of the targets back as they were before the stroke, then
+    /// erases the stroke's coverage there unless `erase` is false.
+    fn reapply(&self, image: &mut ImageStore, rect: Rect, erase: bool) {
+        let (Some(before), Some((x, y, w, h))) = (&self.before, canvas_region(image, rect)) else {
+            return;
+        };
+        let stamp = self.layer.view(x, y, w, h).to_image();
+        match before {
+            Unerased::Mask(index, original) => {
+                if let Some(mask) = image.layers[*index].mask.as_mut() {
+                    let _ = mask.copy_from(&*original.view(x, y, w, h), x, y);
+                }
+                // Erasing a mask hides the layer, as erasing its pixels would.
+                if erase {
+                    paint_mask(image, &stamp, x, y, "Erase Mask", |m, p| mix_mask(m, 0, p));
                 }
             }
+            Unerased::Layers(originals) => {
+                for (index, original) in originals {
+                    if let Some(target) = layer_buffer(image, *index) {
+                        let _ = target.copy_from(&*original.view(x, y, w, h), x, y);
+                    }
+                    if erase {
+                        commit_stamp(image, *index, &stamp, (x, y), "Erase", false, erase_alpha);
+                    }
+                }
+            }
+        }
+        image.mark_dirty_rect(x, y, w, h);
+    }
+
+    /// Ends the stroke, clearing its coverage. Returns the undo step over
+    /// the whole stroke if anything changed.
+    fn finish(&mut self, image: &mut ImageStore) -> Option<Box<dyn Command>> {
+        let before = self.before.take();
+        let (x, y, w, h) = canvas_region(image, self.dirty_rect.take()?)?;
+        for ly in 0..h {
+            for lx in 0..w {
+                self.layer.put_pixel(x + lx, y + ly, Rgba([0, 0, 0, 0]));
+            }
         }
+
+        let mut commands: Vec<Box<dyn Command>> = match before? {
+            Unerased::Mask(index, original) => {
+                let mask = image.layers[index].mask.as_ref()?;
+                let (old_patch, new_patch) = (
+                    original.view(x, y, w, h).to_image(),
+                    mask.view(x, y, w, h).to_image(),
+                );
+                return MaskPatchCommand::if_changed(
+                    "Erase Mask",
+                    index,
+                    x,
+                    y,
+                    old_patch,
+                    new_patch,
+                );
+            }
+            Unerased::Layers(originals) => originals
+                .into_iter()
+                .filter_map(|(index, original)| {
+                    let new_patch = layer_buffer(image, index)?.view(x, y, w, h).to_image();
+                    let old_patch = original.view(x, y, w, h).to_image();
+                    PatchCommand::if_changed("Erase", index, x, y, old_patch, new_patch)
+                })
+                .collect(),
+        };
+        match commands.len() {
+            0 => None,
+            1 => commands.pop(),
+            _ => Some(Box::new(CompoundCommand {
+                name: "Erase All Layers".to_string(),
+                commands,
+            })),
+        }
+    }
+}
+
+/// The pixels of a raster or tone layer.
+fn layer_buffer(image: &mut ImageStore, index: usize) -> Option<&mut RgbaImage> {
+    match &mut image.layers.get_mut(index)?.data {
+        crate::layers::LayerData::Raster(img) => Some(img),
+        crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
+        _ => None,
     }
 }
 
@@ -404,94 +1193,51 @@ impl Tool for EraserTool {
         if self.layer.width() != image.width() || self.layer.height() != image.height() {
             self.layer = ImageBuffer::new(image.width(), image.height());
         }
+        // Erasing through all layers just skips the locked ones.
+        if image.active_layer_is_locked() && (!self.all_layers || settings.edit_mask) {
+            return None;
+        }
+
+        if self.cancelled {
+            if let Some(rect) = self.dirty_rect {
+                self.reapply(image, rect, false);
+                self.before = None;
+                self.finish(image);
+            }
+            self.cancelled = input.is_pressed;
+            self.last_pos = None;
+            return None;
+        }
 
         if input.is_pressed {
-            if let Some(pos) = input.pos {
-                if let Some(last) = self.last_pos {
-                    self.draw_segment(last, pos, settings.eraser_size);
-                } else {
-                    self.draw_circle(pos, settings.eraser_size);
+            if let Some(pos) = input.pos.filter(|&pos| self.last_pos != Some(pos)) {
+                if self.before.is_none() {
+                    self.before = Some(self.snapshot(image, settings));
                 }
+                let (size, strength) = (settings.eraser_size, settings.eraser_strength);
+                let dabs = match self.last_pos {
+                    Some(last) => self.draw_segment(last, pos, size, strength),
+                    None => self.draw_circle(pos, size, strength),
+                };
                 self.last_pos = Some(pos);
+                if let Some(rect) = dabs {
+                    self.reapply(image, rect, true);
+                }
             }
         } else {
             self.last_pos = None;
         }
 
         if input.is_released {
-            if let Some(rect) = self.dirty_rect {
-                let x = rect.min.x as u32;
-                let y = rect.min.y as u32;
-                let w = rect.width() as u32;
-                let h = rect.height() as u32;
-                let w = w.min(image.width() - x);
-                let h = h.min(image.height() - y);
-                let layer_index = image.active_layer;
-                let selection = &image.selection;
-                let layer = &mut image.layers[layer_index];
-                let alpha_locked = layer.alpha_locked;
-
-                let target_buffer = match &mut layer.data {
-                    crate::layers::LayerData::Raster(img) => Some(img),
-                    crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
-                    _ => None,
-                };
-
-                if let Some(target_buffer) = target_buffer {
-                    if w > 0 && h > 0 {
-                        let old_patch = target_buffer.view(x, y, w, h).to_image();
-                        let layer_patch = self.layer.view(x, y, w, h).to_image();
-
-                        for ly in 0..h {
-                            for lx in 0..w {
-                                let pixel = layer_patch.get_pixel(lx, ly);
-                                if pixel[3] > 0 {
-                                    let mut selected = true;
-                                    if let Some(mask) = selection {
-                                        let mask_val = mask.get_pixel(x + lx, y + ly)[0];
-                                        if mask_val == 0 {
-                                            selected = false;
-                                        }
-                                    }
-
-                                    if selected {
-                                        let white = Rgba([255, 255, 255, 255]);
-                                        let target_pixel = target_buffer.get_pixel(x + lx, y + ly);
-
-                                        if !alpha_locked || target_pixel[3] > 0 {
-                                            target_buffer.put_pixel(x + lx, y + ly, white);
-                                        }
-                                    }
-                                    self.layer.put_pixel(x + lx, y + ly, Rgba([0, 0, 0, 0]));
-                                }
-                            }
-                        }
-                        let new_patch = target_buffer.view(x, y, w, h).to_image();
-                        image.mark_dirty();
-                        self.dirty_rect = None;
-
-                        return Some(Box::new(PatchCommand {
-                            name: "Erase".to_string(),
-                            layer_index,
-                            x,
-                            y,
-                            old_patch,
-                            new_patch,
-                        }));
-                    }
-                }
-            }
-            self.dirty_rect = None;
+            // The layers already show the whole stroke.
+            return self.finish(image);
         }
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
-        if self.dirty_rect.is_some() {
-            Some((&self.layer, 0, 0))
-        } else {
-            None
-        }
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        // The stroke is previewed on the layers themselves.
+        None
     }
 
     fn draw_cursor(
@@ -500,10 +1246,11 @@ impl Tool for EraserTool {
         painter: &Painter,
         settings: &crate::state::ToolSettings,
         pos: Pos2,
+        view: &ViewTransform,
     ) {
         painter.circle_stroke(
             pos,
-            settings.eraser_size,
+            settings.eraser_size * view.zoom,
             egui::Stroke::new(1.0, Color32::RED),
         );
     }
@@ -512,8 +1259,22 @@ impl Tool for EraserTool {
         ui.horizontal(|ui| {
             ui.label("Size:");
             ui.add(egui::DragValue::new(&mut settings.eraser_size).range(1.0..=100.0));
+            ui.label("Strength:");
+            ui.add(egui::Slider::new(&mut settings.eraser_strength, 0.01..=1.0))
+                .on_hover_text("How much alpha one stroke removes; 0.5 halves it");
+            ui.checkbox(&mut self.all_layers, "All Layers")
+                .on_hover_text("Erase through every visible layer, not just the active one");
         });
     }
+
+    fn cancel(&mut self) {
+        self.cancelled = self.before.is_some();
+    }
+
+    fn status(&self) -> Option<String> {
+        self.all_layers
+            .then(|| "Erasing through all visible layers".to_string())
+    }
 }
 
 pub struct LineTool {
@@ -521,6 +1282,8 @@ pub struct LineTool {
     start_pos: Option<Pos2>,
     current_pos: Option<Pos2>,
     dirty_rect: Option<Rect>,
+    /// Angle increment in degrees that Shift snaps the line to.
+    snap_step: f32,
 }
 
 impl LineTool {
@@ -530,9 +1293,30 @@ impl LineTool {
             start_pos: None,
             current_pos: None,
             dirty_rect: None,
+            snap_step: 15.0,
         }
     }
 
+    /// Projects `pos` onto the line through `start` and `target`, so the line
+    /// runs toward the vanishing point.
+    fn snap_toward(start: Pos2, pos: Pos2, target: Pos2) -> Pos2 {
+        let axis = target - start;
+        if axis.length_sq() < f32::EPSILON {
+            return pos;
+        }
+        let t = (pos - start).dot(axis) / axis.length_sq();
+        let snapped = start + axis * t;
+        Pos2::new(snapped.x.round(), snapped.y.round())
+    }
+
+    /// Length in pixels and angle in degrees, counter-clockwise from the
+    /// positive x axis as it appears on screen.
+    fn measurement(&self) -> Option<(f32, f32)> {
+        let delta = self.current_pos? - self.start_pos?;
+        let angle = (-delta.y).atan2(delta.x).to_degrees();
+        Some((delta.length(), angle))
+    }
+
     fn draw_line_on_layer(&mut self, start: Pos2, end: Pos2, color: Rgba<u8>, width: f32) {
         if let Some(rect) = self.dirty_rect {
             let x = rect.min.x as u32;
@@ -602,12 +1386,21 @@ impl Tool for LineTool {
         if self.layer.width() != image.width() || self.layer.height() != image.height() {
             self.layer = ImageBuffer::new(image.width(), image.height());
         }
+        if image.active_layer_is_locked() {
+            return None;
+        }
 
         if input.is_pressed {
             if self.start_pos.is_none() {
                 self.start_pos = input.pos;
             }
-            if let Some(pos) = input.pos {
+            if let Some(mut pos) = input.pos {
+                if let (Some(start), true) = (self.start_pos, input.shift) {
+                    pos = snap_angle(start, pos, self.snap_step);
+                } else if let (Some(start), Some(target)) = (self.start_pos, input.vanishing_point)
+                {
+                    pos = Self::snap_toward(start, pos, target);
+                }
                 self.current_pos = Some(pos);
                 if let Some(start) = self.start_pos {
                     self.draw_line_on_layer(start, pos, color, settings.line_width);
@@ -616,88 +1409,26 @@ impl Tool for LineTool {
         }
 
         if input.is_released {
-            if let (Some(_start), Some(_end), Some(rect)) =
-                (self.start_pos, self.current_pos, self.dirty_rect)
-            {
-                let x = rect.min.x as u32;
-                let y = rect.min.y as u32;
-                let w = rect.width() as u32;
-                let h = rect.height() as u32;
-                let w = w.min(image.width() - x);
-                let h = h.min(image.height() - y);
-                let layer_index = image.active_layer;
-                let selection = &image.selection;
-                let layer = &mut image.layers[layer_index];
-                let alpha_locked = layer.alpha_locked;
-
-                let target_buffer = match &mut layer.data {
-                    crate::layers::LayerData::Raster(img) => Some(img),
-                    crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
-                    _ => None,
-                };
-
-                if let Some(target_buffer) = target_buffer {
-                    if w > 0 && h > 0 {
-                        let old_patch = target_buffer.view(x, y, w, h).to_image();
-                        let layer_patch = self.layer.view(x, y, w, h).to_image();
-
-                        for ly in 0..h {
-                            for lx in 0..w {
-                                let pixel = layer_patch.get_pixel(lx, ly);
-                                if pixel[3] > 0 {
-                                    let mut selected = true;
-                                    if let Some(mask) = selection {
-                                        let mask_val = mask.get_pixel(x + lx, y + ly)[0];
-                                        if mask_val == 0 {
-                                            selected = false;
-                                        }
-                                    }
-
-                                    if selected {
-                                        let target_pixel = target_buffer.get_pixel(x + lx, y + ly);
-                                        if !alpha_locked || target_pixel[3] > 0 {
-                                            let mut final_pixel = *pixel;
-                                            if alpha_locked {
-                                                final_pixel[3] = target_pixel[3];
-                                            }
-                                            target_buffer.put_pixel(x + lx, y + ly, final_pixel);
-                                        }
-                                    }
-                                    self.layer.put_pixel(x + lx, y + ly, Rgba([0, 0, 0, 0]));
-                                }
-                            }
-                        }
-
-                        let new_patch = target_buffer.view(x, y, w, h).to_image();
-                        image.mark_dirty();
-                        self.start_pos = None;
-                        self.current_pos = None;
-                        self.dirty_rect = None;
-
-                        return Some(Box::new(PatchCommand {
-                            name: "Line".to_string(),
-                            layer_index,
-                            x,
-                            y,
-                            old_patch,
-                            new_patch,
-                        }));
-                    }
-                }
-            }
+            let stroke = self.start_pos.and(self.current_pos).and(self.dirty_rect);
             self.start_pos = None;
             self.current_pos = None;
             self.dirty_rect = None;
+            if let Some(rect) = stroke {
+                return commit_temp_layer(
+                    image,
+                    &mut self.layer,
+                    rect,
+                    "Line",
+                    false,
+                    crate::color::over,
+                );
+            }
         }
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
-        if self.dirty_rect.is_some() {
-            Some((&self.layer, 0, 0))
-        } else {
-            None
-        }
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        self.dirty_rect.map(|rect| (&self.layer, rect))
     }
 
     fn draw_cursor(
@@ -706,14 +1437,429 @@ impl Tool for LineTool {
         painter: &Painter,
         settings: &crate::state::ToolSettings,
         pos: Pos2,
+        view: &ViewTransform,
     ) {
-        painter.circle_filled(pos, settings.line_width, Color32::WHITE);
+        painter.circle_filled(pos, settings.line_width * view.zoom, Color32::WHITE);
+
+        if let Some((length, angle)) = self.measurement() {
+            painter.text(
+                pos + Vec2::new(14.0, -14.0),
+                egui::Align2::LEFT_BOTTOM,
+                format!("{:.1} px  {:.1}°", length, angle),
+                egui::FontId::monospace(12.0),
+                Color32::WHITE,
+            );
+        }
     }
 
     fn configure(&mut self, ui: &mut Ui, settings: &mut crate::state::ToolSettings) {
         ui.horizontal(|ui| {
             ui.label("Width:");
             ui.add(egui::DragValue::new(&mut settings.line_width).range(1.0..=20.0));
+            ui.label("Shift snaps to:");
+            ui.selectable_value(&mut self.snap_step, 15.0, "15°");
+            ui.selectable_value(&mut self.snap_step, 45.0, "45°");
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ToolSettings;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    fn released_at(pos: Pos2) -> ToolInput {
+        ToolInput {
+            is_pressed: false,
+            is_released: true,
+            ..ToolInput::pressed_at(pos)
+        }
+    }
+
+    /// Drags `tool` from `from` to `to` in red and lets go, returning the
+    /// undo step.
+    fn stroke(
+        tool: &mut dyn Tool,
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        from: Pos2,
+        to: Pos2,
+    ) -> Option<Box<dyn Command>> {
+        stroke_with(tool, image, settings, from, to, RED)
+    }
+
+    fn stroke_with(
+        tool: &mut dyn Tool,
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        from: Pos2,
+        to: Pos2,
+        color: Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        tool.update(image, settings, &ToolInput::pressed_at(from), color);
+        tool.update(image, settings, &ToolInput::pressed_at(to), color);
+        tool.update(image, settings, &released_at(to), color)
+    }
+
+    fn pixels(image: &ImageStore) -> &RgbaImage {
+        match &image.layers[image.active_layer].data {
+            crate::layers::LayerData::Raster(img) => img,
+            _ => unreachable!(),
+        }
+    }
+
+    fn pixels_mut(image: &mut ImageStore) -> &mut RgbaImage {
+        match &mut image.layers[image.active_layer].data {
+            crate::layers::LayerData::Raster(img) => img,
+            _ => unreachable!(),
+        }
+    }
+
+    /// A temporary layer with `color` painted over `area`.
+    fn temp_layer(area: Rect, color: Rgba<u8>) -> RgbaImage {
+        ImageBuffer::from_fn(8, 8, |x, y| {
+            if area.contains(Pos2::new(x as f32 + 0.5, y as f32 + 0.5)) {
+                color
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    fn replace(_: Rgba<u8>, p: Rgba<u8>) -> Rgba<u8> {
+        p
+    }
+
+    #[test]
+    fn commit_patch_covers_the_rect_clamped_to_the_canvas() {
+        let mut image = ImageStore::with_fill(8, 8, None);
+        let area = Rect::from_min_max(Pos2::new(-2.0, 5.0), Pos2::new(3.0, 10.0));
+        let mut temp = temp_layer(area, RED);
+
+        let command = commit_temp_layer(&mut image, &mut temp, area, "Paint", false, replace);
+        assert_eq!(command.unwrap().dirty_region(), Some((0, 5, 3, 3)));
+        // The committed part of the temporary layer is cleared.
+        assert!(temp.pixels().all(|p| p[3] == 0));
+        for (x, y, p) in pixels(&image).enumerate_pixels() {
+            let inside = x < 3 && y >= 5;
+            assert_eq!(p[3], if inside { 255 } else { 0 }, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn commit_leaves_pixels_outside_the_rect_on_the_temp_layer() {
+        let mut image = ImageStore::with_fill(8, 8, None);
+        let mut temp = temp_layer(Rect::from_min_max(Pos2::ZERO, Pos2::new(8.0, 8.0)), RED);
+        let rect = Rect::from_min_max(Pos2::new(2.0, 2.0), Pos2::new(4.0, 4.0));
+
+        let command = commit_temp_layer(&mut image, &mut temp, rect, "Paint", false, replace);
+        assert_eq!(command.unwrap().dirty_region(), Some((2, 2, 2, 2)));
+        assert_eq!(temp.pixels().filter(|p| p[3] > 0).count(), 64 - 4);
+    }
+
+    #[test]
+    fn commit_keeps_alpha_under_alpha_lock() {
+        let mut image = ImageStore::with_fill(8, 8, None);
+        pixels_mut(&mut image).put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+        pixels_mut(&mut image).put_pixel(2, 0, Rgba([0, 0, 255, 100]));
+        image.layers[0].alpha_locked = true;
+        let area = Rect::from_min_max(Pos2::ZERO, Pos2::new(3.0, 1.0));
+        let mut temp = temp_layer(area, RED);
+
+        commit_temp_layer(&mut image, &mut temp, area, "Paint", false, replace);
+        let layer = pixels(&image);
+        assert_eq!(*layer.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+        assert_eq!(*layer.get_pixel(1, 0), RED);
+        assert_eq!(*layer.get_pixel(2, 0), Rgba([255, 0, 0, 100]));
+    }
+
+    #[test]
+    fn commit_fades_by_the_selection() {
+        let mut image = ImageStore::with_fill(8, 8, None);
+        let mut selection = image::GrayImage::new(8, 8);
+        selection.put_pixel(1, 0, image::Luma([128]));
+        selection.put_pixel(2, 0, image::Luma([255]));
+        image.selection = Some(selection);
+        let area = Rect::from_min_max(Pos2::ZERO, Pos2::new(3.0, 1.0));
+        let mut temp = temp_layer(area, RED);
+
+        let command = commit_temp_layer(&mut image, &mut temp, area, "Paint", false, replace);
+        assert!(command.is_some());
+        let layer = pixels(&image);
+        assert_eq!(*layer.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+        assert_eq!(*layer.get_pixel(1, 0), Rgba([255, 0, 0, 128]));
+        assert_eq!(*layer.get_pixel(2, 0), RED);
+    }
+
+    #[test]
+    fn commit_that_changes_nothing_records_nothing() {
+        let mut image = ImageStore::with_fill(8, 8, Some(RED));
+        let area = Rect::from_min_max(Pos2::ZERO, Pos2::new(4.0, 4.0));
+
+        // Painting the color that is already there.
+        let mut temp = temp_layer(area, RED);
+        assert!(commit_temp_layer(&mut image, &mut temp, area, "Paint", false, replace).is_none());
+        // An empty stroke.
+        let mut temp = RgbaImage::new(8, 8);
+        assert!(commit_temp_layer(&mut image, &mut temp, area, "Paint", false, replace).is_none());
+        // A rect entirely off the canvas.
+        let off = Rect::from_min_max(Pos2::new(9.0, 9.0), Pos2::new(12.0, 12.0));
+        assert!(commit_temp_layer(&mut image, &mut temp, off, "Paint", false, replace).is_none());
+    }
+
+    #[test]
+    fn translucent_line_composites_over_the_layer() {
+        let white = Rgba([255, 255, 255, 255]);
+        let mut image = ImageStore::with_fill(16, 16, Some(white));
+        let mut line = LineTool::new(16, 16);
+        let (from, to) = (Pos2::new(2.0, 8.0), Pos2::new(13.0, 8.0));
+
+        let command = stroke_with(
+            &mut line,
+            &mut image,
+            &ToolSettings::default(),
+            from,
+            to,
+            Rgba([0, 0, 255, 128]),
+        );
+        assert!(command.is_some());
+        // Glazed blue over white, still opaque.
+        assert_eq!(*pixels(&image).get_pixel(8, 8), Rgba([127, 127, 255, 255]));
+    }
+
+    #[test]
+    fn line_across_a_feathered_selection_keeps_the_layer_opaque() {
+        let white = Rgba([255, 255, 255, 255]);
+        let mut image = ImageStore::with_fill(16, 16, Some(white));
+        image.selection = Some(image::GrayImage::from_pixel(16, 16, image::Luma([128])));
+        let mut line = LineTool::new(16, 16);
+        let (from, to) = (Pos2::new(2.0, 8.0), Pos2::new(13.0, 8.0));
+
+        stroke(&mut line, &mut image, &ToolSettings::default(), from, to);
+        assert_eq!(*pixels(&image).get_pixel(8, 8), Rgba([255, 127, 127, 255]));
+        assert!(pixels(&image).pixels().all(|p| p[3] == 255));
+    }
+
+    /// The painting tools that commit a stroke from a temporary layer, by
+    /// name.
+    fn painting_tools() -> Vec<(&'static str, Box<dyn Tool>)> {
+        vec![
+            ("brush", Box::new(BrushTool::new(16, 16))),
+            (
+                "rectangle",
+                Box::new(crate::tools::RectangleTool::new(16, 16)),
+            ),
+            ("ellipse", Box::new(crate::tools::EllipseTool::new(16, 16))),
+        ]
+    }
+
+    /// The smallest `(x, y, width, height)` holding every pixel that differs
+    /// between `a` and `b`.
+    fn changed_area(a: &RgbaImage, b: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+        a.enumerate_pixels()
+            .filter(|&(x, y, p)| p != b.get_pixel(x, y))
+            .map(|(x, y, _)| (x, y, 1, 1))
+            .reduce(|r, p| crate::image_store::union_rect(Some(r), p))
+    }
+
+    #[test]
+    fn painting_tools_patch_what_they_painted() {
+        let (from, to) = (Pos2::new(4.0, 4.0), Pos2::new(10.0, 8.0));
+        for (name, mut tool) in painting_tools() {
+            let mut image = ImageStore::with_fill(16, 16, None);
+            let before = pixels(&image).clone();
+
+            let command = stroke(
+                tool.as_mut(),
+                &mut image,
+                &ToolSettings::default(),
+                from,
+                to,
+            );
+            let changed = changed_area(&before, pixels(&image));
+            assert!(changed.is_some(), "{name} painted nothing");
+            let command = command.unwrap_or_else(|| panic!("{name} recorded nothing"));
+            // The patch holds every painted pixel, plus at most the ring of
+            // anti-aliased fringe that came out fully transparent.
+            let (cx, cy, cw, ch) = changed.unwrap();
+            let (px, py, pw, ph) = command.dirty_region().unwrap();
+            assert!(px <= cx && py <= cy, "{name}");
+            assert!(px + pw >= cx + cw && py + ph >= cy + ch, "{name}");
+            assert!(pw <= cw + 2 && ph <= ch + 2, "{name}");
+
+            command.undo(&mut image);
+            assert!(*pixels(&image) == before, "{name} undo");
+        }
+    }
+
+    #[test]
+    fn painting_tools_keep_alpha_under_alpha_lock() {
+        let blue = Rgba([0, 0, 255, 255]);
+        let (from, to) = (Pos2::new(3.0, 4.0), Pos2::new(12.0, 11.0));
+        for (name, mut tool) in painting_tools() {
+            // Red on the left half, transparent on the right.
+            let mut image = ImageStore::with_fill(16, 16, None);
+            for y in 0..16 {
+                for x in 0..8 {
+                    pixels_mut(&mut image).put_pixel(x, y, RED);
+                }
+            }
+            image.layers[0].alpha_locked = true;
+
+            stroke_with(
+                tool.as_mut(),
+                &mut image,
+                &ToolSettings::default(),
+                from,
+                to,
+                blue,
+            );
+            let layer = pixels(&image);
+            assert!(layer.pixels().any(|p| *p == blue), "{name} painted nothing");
+            for (x, y, p) in layer.enumerate_pixels() {
+                assert_eq!(p[3], if x < 8 { 255 } else { 0 }, "{name} at ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn painting_tools_fade_by_the_selection() {
+        let white = Rgba([255, 255, 255, 255]);
+        let (from, to) = (Pos2::new(4.0, 4.0), Pos2::new(10.0, 8.0));
+        for (name, mut tool) in painting_tools() {
+            let mut image = ImageStore::with_fill(16, 16, Some(white));
+            // Only the left half is selected, at half strength.
+            image.selection = Some(image::GrayImage::from_fn(16, 16, |x, _| {
+                image::Luma([if x < 8 { 128 } else { 0 }])
+            }));
+
+            stroke(
+                tool.as_mut(),
+                &mut image,
+                &ToolSettings::default(),
+                from,
+                to,
+            );
+            let layer = pixels(&image);
+            let half_red = Rgba([255, 127, 127, 255]);
+            assert!(layer.pixels().any(|p| *p == half_red), "{name}");
+            for (x, y, p) in layer.enumerate_pixels() {
+                assert_ne!(*p, RED, "{name} at ({x}, {y})");
+                if x >= 8 {
+                    assert_eq!(*p, white, "{name} at ({x}, {y})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn painting_tools_record_nothing_when_nothing_changes() {
+        let (from, to) = (Pos2::new(4.0, 4.0), Pos2::new(10.0, 8.0));
+        for (name, mut tool) in painting_tools() {
+            // Painting the color that is already there.
+            let mut image = ImageStore::with_fill(16, 16, Some(RED));
+            let command = stroke(
+                tool.as_mut(),
+                &mut image,
+                &ToolSettings::default(),
+                from,
+                to,
+            );
+            assert!(command.is_none(), "{name}");
+            assert!(pixels(&image).pixels().all(|p| *p == RED), "{name}");
+        }
+    }
+
+    #[test]
+    fn erasing_an_alpha_locked_layer_records_nothing() {
+        let mut image = ImageStore::with_fill(16, 16, Some(RED));
+        image.layers[0].alpha_locked = true;
+        let mut eraser = EraserTool::new(16, 16);
+        let at = Pos2::new(8.0, 8.0);
+
+        let command = stroke(&mut eraser, &mut image, &ToolSettings::default(), at, at);
+        assert!(command.is_none());
+        assert!(pixels(&image).pixels().all(|p| *p == RED));
+    }
+
+    #[test]
+    fn half_strength_stroke_halves_alpha_once() {
+        let mut image = ImageStore::with_fill(32, 32, Some(RED));
+        let mut eraser = EraserTool::new(32, 32);
+        let settings = ToolSettings {
+            eraser_size: 4.0,
+            eraser_strength: 0.5,
+            ..ToolSettings::default()
+        };
+
+        // Dabs from each move overlap the last ones'.
+        for x in [8.0, 11.0, 14.0, 17.0, 20.0] {
+            eraser.update(
+                &mut image,
+                &settings,
+                &ToolInput::pressed_at(Pos2::new(x, 16.0)),
+                RED,
+            );
+        }
+        let end = Pos2::new(20.0, 16.0);
+        let command = eraser.update(&mut image, &settings, &released_at(end), RED);
+        assert_eq!(pixels(&image).get_pixel(14, 16)[3], 127);
+        assert_eq!(pixels(&image).get_pixel(14, 0)[3], 255);
+
+        command.expect("the stroke erased").undo(&mut image);
+        assert!(pixels(&image).pixels().all(|p| *p == RED));
+    }
+
+    #[test]
+    fn cancelled_stroke_puts_the_layer_back() {
+        let mut image = ImageStore::with_fill(32, 32, Some(RED));
+        let mut eraser = EraserTool::new(32, 32);
+        let settings = ToolSettings::default();
+
+        eraser.update(
+            &mut image,
+            &settings,
+            &ToolInput::pressed_at(Pos2::new(8.0, 8.0)),
+            RED,
+        );
+        eraser.update(
+            &mut image,
+            &settings,
+            &ToolInput::pressed_at(Pos2::new(20.0, 8.0)),
+            RED,
+        );
+        assert!(pixels(&image).get_pixel(14, 8)[3] < 255);
+
+        eraser.cancel();
+        // The rest of the drag erases nothing.
+        eraser.update(
+            &mut image,
+            &settings,
+            &ToolInput::pressed_at(Pos2::new(20.0, 20.0)),
+            RED,
+        );
+        let end = Pos2::new(24.0, 20.0);
+        assert!(eraser
+            .update(&mut image, &settings, &released_at(end), RED)
+            .is_none());
+        assert!(pixels(&image).pixels().all(|p| *p == RED));
+    }
+
+    #[test]
+    fn erasing_transparent_pixels_records_nothing() {
+        let mut image = ImageStore::with_fill(16, 16, None);
+        let mut eraser = EraserTool::new(16, 16);
+        let settings = ToolSettings::default();
+
+        let command = stroke(
+            &mut eraser,
+            &mut image,
+            &settings,
+            Pos2::new(4.0, 4.0),
+            Pos2::new(10.0, 8.0),
+        );
+        assert!(command.is_none());
+    }
+}
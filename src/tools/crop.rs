@@ -0,0 +1,289 @@
+use crate::commands::{Command, DocumentCommand};
+use crate::image_store::ImageStore;
+use crate::state::ToolSettings;
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::{Color32, Painter, Pos2, Rect, Stroke, Ui, Vec2};
+use image::Rgba;
+
+/// On-screen distance, in points, within which a corner handle is grabbed.
+const HANDLE_RADIUS: f32 = 8.0;
+
+#[derive(Clone, Copy)]
+enum CropDrag {
+    /// Dragging out a rect, or resizing one from the opposite `anchor` corner.
+    Resize { anchor: Pos2 },
+    /// Moving the whole rect; `offset` is the pointer's position within it.
+    Move { offset: Vec2 },
+}
+
+/// Frames a region of the canvas and crops the document to it on confirm.
+/// The frame is kept in image space so it follows pan and zoom.
+pub struct CropTool {
+    rect: Option<Rect>,
+    drag: Option<CropDrag>,
+    bounds: Vec2,
+    aspect_lock: bool,
+    /// Width over height used while `aspect_lock` is on.
+    aspect: f32,
+    show_thirds: bool,
+    confirm: bool,
+}
+
+impl CropTool {
+    pub fn new() -> Self {
+        Self {
+            rect: None,
+            drag: None,
+            bounds: Vec2::ZERO,
+            aspect_lock: false,
+            aspect: 1.0,
+            show_thirds: true,
+            confirm: false,
+        }
+    }
+
+    /// Builds the frame spanned by `anchor` and `pos`, honoring the aspect
+    /// lock, and keeps it inside the canvas.
+    fn span(&self, anchor: Pos2, pos: Pos2) -> Rect {
+        let mut delta = pos - anchor;
+        if self.aspect_lock && self.aspect > 0.0 {
+            let (w, h) = (delta.x.abs(), delta.y.abs());
+            let (w, h) = if w > h * self.aspect {
+                (h * self.aspect, h)
+            } else {
+                (w, w / self.aspect)
+            };
+            delta = Vec2::new(w.copysign(delta.x), h.copysign(delta.y));
+        }
+        Rect::from_two_pos(anchor, anchor + delta).intersect(self.canvas())
+    }
+
+    fn canvas(&self) -> Rect {
+        Rect::from_min_size(Pos2::ZERO, self.bounds)
+    }
+
+    fn begin_drag(&self, pos: Pos2, zoom: f32) -> CropDrag {
+        let Some(rect) = self.rect else {
+            return CropDrag::Resize { anchor: pos };
+        };
+        let reach = HANDLE_RADIUS / zoom.max(f32::EPSILON);
+        let corners = [
+            (rect.left_top(), rect.right_bottom()),
+            (rect.right_top(), rect.left_bottom()),
+            (rect.left_bottom(), rect.right_top()),
+            (rect.right_bottom(), rect.left_top()),
+        ];
+        for (corner, opposite) in corners {
+            if pos.distance(corner) <= reach {
+                return CropDrag::Resize { anchor: opposite };
+            }
+        }
+        if rect.contains(pos) {
+            CropDrag::Move {
+                offset: pos - rect.min,
+            }
+        } else {
+            CropDrag::Resize { anchor: pos }
+        }
+    }
+
+    /// Crops the document to the current frame, rounded to whole pixels.
+    fn apply(&mut self, image: &mut ImageStore) -> Option<Box<dyn Command>> {
+        let rect = self.rect.take()?.intersect(self.canvas());
+        let x = rect.min.x.round().max(0.0) as u32;
+        let y = rect.min.y.round().max(0.0) as u32;
+        let w = (rect.max.x.round() as u32)
+            .min(image.width())
+            .saturating_sub(x);
+        let h = (rect.max.y.round() as u32)
+            .min(image.height())
+            .saturating_sub(y);
+
+        if w == 0 || h == 0 {
+            log::info!("Crop: frame is empty, nothing to crop");
+            return None;
+        }
+        if (x, y, w, h) == (0, 0, image.width(), image.height()) {
+            return None;
+        }
+
+        let before = image.snapshot();
+        image.crop(x, y, w, h);
+        let after = image.snapshot();
+        Some(Box::new(DocumentCommand {
+            name: "Crop".to_string(),
+            before,
+            after,
+        }))
+    }
+}
+
+impl Tool for CropTool {
+    fn name(&self) -> &str {
+        "Crop"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        _settings: &ToolSettings,
+        input: &ToolInput,
+        _color: Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        let bounds = Vec2::new(image.width() as f32, image.height() as f32);
+        if bounds != self.bounds {
+            // The document changed size underneath us; the old frame is stale.
+            self.bounds = bounds;
+            self.rect = None;
+            self.drag = None;
+        }
+
+        if self.confirm {
+            self.confirm = false;
+            self.drag = None;
+            return self.apply(image);
+        }
+
+        if !input.is_pressed {
+            self.drag = None;
+            return None;
+        }
+        let pos = input.pos?;
+
+        let drag = match self.drag {
+            Some(drag) => drag,
+            None => self.begin_drag(pos, input.zoom),
+        };
+        self.drag = Some(drag);
+        self.rect = match drag {
+            CropDrag::Resize { anchor } => Some(self.span(anchor, pos)),
+            CropDrag::Move { offset } => self.rect.map(|rect| {
+                let max = (self.bounds - rect.size()).max(Vec2::ZERO);
+                let min = (pos - offset).to_vec2().clamp(Vec2::ZERO, max);
+                Rect::from_min_size(min.to_pos2(), rect.size())
+            }),
+        };
+
+        None
+    }
+
+    fn get_temp_layer(&self) -> Option<(&image::RgbaImage, egui::Rect)> {
+        None
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        _painter: &Painter,
+        _settings: &ToolSettings,
+        _pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+    }
+
+    fn draw_overlay(&self, painter: &Painter, view: &ViewTransform) {
+        let Some(rect) = self.rect else {
+            return;
+        };
+        let canvas = view.screen_rect(self.canvas());
+        let frame = view.screen_rect(rect);
+
+        // Dim everything that will be cut away.
+        let shade = Color32::from_black_alpha(140);
+        let outside = [
+            Rect::from_min_max(canvas.min, Pos2::new(canvas.max.x, frame.min.y)),
+            Rect::from_min_max(Pos2::new(canvas.min.x, frame.max.y), canvas.max),
+            Rect::from_min_max(
+                Pos2::new(canvas.min.x, frame.min.y),
+                Pos2::new(frame.min.x, frame.max.y),
+            ),
+            Rect::from_min_max(
+                Pos2::new(frame.max.x, frame.min.y),
+                Pos2::new(canvas.max.x, frame.max.y),
+            ),
+        ];
+        for part in outside {
+            if part.is_positive() {
+                painter.rect_filled(part, 0.0, shade);
+            }
+        }
+
+        if self.show_thirds {
+            let guide = Stroke::new(1.0, Color32::from_white_alpha(100));
+            for t in [1.0 / 3.0, 2.0 / 3.0] {
+                let x = egui::lerp(frame.min.x..=frame.max.x, t);
+                let y = egui::lerp(frame.min.y..=frame.max.y, t);
+                painter.line_segment(
+                    [Pos2::new(x, frame.min.y), Pos2::new(x, frame.max.y)],
+                    guide,
+                );
+                painter.line_segment(
+                    [Pos2::new(frame.min.x, y), Pos2::new(frame.max.x, y)],
+                    guide,
+                );
+            }
+        }
+
+        painter.rect_stroke(frame, 0.0, Stroke::new(1.0, Color32::WHITE));
+        for corner in [
+            frame.left_top(),
+            frame.right_top(),
+            frame.left_bottom(),
+            frame.right_bottom(),
+        ] {
+            painter.circle_filled(corner, 4.0, Color32::WHITE);
+        }
+    }
+
+    fn status(&self) -> Option<String> {
+        let rect = self.rect?;
+        Some(format!(
+            "Crop to {}×{}. Esc to cancel.",
+            rect.width().round() as u32,
+            rect.height().round() as u32
+        ))
+    }
+
+    fn cancel(&mut self) {
+        self.rect = None;
+        self.drag = None;
+        self.confirm = false;
+    }
+
+    fn configure(&mut self, ui: &mut Ui, _settings: &mut ToolSettings) {
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.aspect_lock, "Lock Ratio").changed() && self.aspect_lock {
+                if let Some(rect) = self.rect.filter(|r| r.height() > 0.0) {
+                    self.aspect = rect.width() / rect.height();
+                }
+            }
+            if self.aspect_lock {
+                ui.add(
+                    egui::DragValue::new(&mut self.aspect)
+                        .range(0.05..=20.0)
+                        .speed(0.01),
+                );
+            }
+            ui.checkbox(&mut self.show_thirds, "Thirds");
+
+            match self.rect {
+                Some(rect) => {
+                    ui.label(format!(
+                        "{}×{}",
+                        rect.width().round() as u32,
+                        rect.height().round() as u32
+                    ));
+                    if ui.button("Crop").clicked() {
+                        self.confirm = true;
+                    }
+                    if ui.button("Cancel").on_hover_text("Esc").clicked() {
+                        self.cancel();
+                    }
+                }
+                None => {
+                    ui.label("Drag on the canvas to frame the crop.");
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,117 @@
+use crate::commands::Command;
+use crate::image_store::ImageStore;
+use crate::state::ToolSettings;
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::{Color32, Painter, Pos2, Rect, Ui, Vec2};
+use image::{Rgba, RgbaImage};
+
+/// Where the eyedropper reads its color from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SampleSource {
+    /// The flattened image, as it appears on screen.
+    Composite,
+    /// The raw pixel of the active layer, ignoring every other layer.
+    ActiveLayer,
+}
+
+pub struct EyedropperTool {
+    source: SampleSource,
+    /// Keep the sampled alpha instead of picking an opaque color.
+    keep_alpha: bool,
+    last_sample: Option<Rgba<u8>>,
+    picked: Option<Rgba<u8>>,
+}
+
+impl EyedropperTool {
+    pub fn new() -> Self {
+        Self {
+            source: SampleSource::Composite,
+            keep_alpha: false,
+            last_sample: None,
+            picked: None,
+        }
+    }
+
+    fn sample(&self, image: &mut ImageStore, x: u32, y: u32) -> Option<Rgba<u8>> {
+        if x >= image.width() || y >= image.height() {
+            return None;
+        }
+        let pixel = match self.source {
+            SampleSource::Composite => *image.get_composite().get_pixel(x, y),
+            SampleSource::ActiveLayer => *image.get_active_raster_buffer_mut()?.get_pixel(x, y),
+        };
+        Some(pixel)
+    }
+}
+
+impl Tool for EyedropperTool {
+    fn name(&self) -> &str {
+        "Eyedropper"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        _settings: &ToolSettings,
+        input: &ToolInput,
+        _color: Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        if !input.is_pressed {
+            return None;
+        }
+        let pos = input.pos?;
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return None;
+        }
+
+        if let Some(mut pixel) = self.sample(image, pos.x as u32, pos.y as u32) {
+            self.last_sample = Some(pixel);
+            if !self.keep_alpha {
+                pixel[3] = 255;
+            }
+            self.picked = Some(pixel);
+        }
+        None
+    }
+
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        None
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+        painter.circle_stroke(pos, 4.0, egui::Stroke::new(1.0, Color32::WHITE));
+        if let Some(p) = self.last_sample {
+            let swatch = Rect::from_min_size(pos + Vec2::new(10.0, 10.0), Vec2::splat(16.0));
+            painter.rect_filled(
+                swatch,
+                0.0,
+                Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]),
+            );
+            painter.rect_stroke(swatch, 0.0, egui::Stroke::new(1.0, Color32::WHITE));
+        }
+    }
+
+    fn configure(&mut self, ui: &mut Ui, _settings: &mut ToolSettings) {
+        ui.horizontal(|ui| {
+            ui.label("Sample:");
+            ui.selectable_value(&mut self.source, SampleSource::Composite, "Composite");
+            ui.selectable_value(&mut self.source, SampleSource::ActiveLayer, "Active Layer");
+            ui.checkbox(&mut self.keep_alpha, "Pick Alpha")
+                .on_hover_text("Use the sampled pixel's transparency too");
+            if let Some(p) = self.last_sample {
+                ui.label(format!("RGBA {} {} {} {}", p[0], p[1], p[2], p[3]));
+            }
+        });
+    }
+
+    fn take_picked_color(&mut self) -> Option<Rgba<u8>> {
+        self.picked.take()
+    }
+}
@@ -0,0 +1,211 @@
+use crate::commands::{Command, PatchCommand};
+use crate::image_store::ImageStore;
+use crate::state::{FillMode, ToolSettings};
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::{Color32, Painter, Pos2, Rect, Ui};
+use image::{GenericImageView, Rgba, RgbaImage};
+
+/// Paint bucket. Floods the connected region around the click, or fills the
+/// whole selection, depending on `ToolSettings::fill_mode`.
+pub struct FillTool {
+    was_pressed: bool,
+}
+
+impl FillTool {
+    pub fn new() -> Self {
+        Self { was_pressed: false }
+    }
+
+    /// Marks the pixels to fill. Flood mode walks scanlines out from the seed
+    /// over pixels close to the seed color; both modes stay inside the
+    /// selection when there is one.
+    fn region(
+        buffer: &RgbaImage,
+        selection: Option<&image::GrayImage>,
+        seed: (u32, u32),
+        settings: &ToolSettings,
+    ) -> Vec<bool> {
+        let (w, h) = buffer.dimensions();
+        let selected = |x: u32, y: u32| selection.is_none_or(|m| m.get_pixel(x, y)[0] > 0);
+
+        if settings.fill_mode == FillMode::Selection {
+            return (0..w * h).map(|i| selected(i % w, i / w)).collect();
+        }
+        flood_region(buffer, seed, settings.fill_tolerance, selected)
+    }
+}
+
+/// Whether every channel of `a` is within `tolerance` of `b`.
+pub fn within_tolerance(a: Rgba<u8>, b: Rgba<u8>, tolerance: u8) -> bool {
+    (0..4).all(|c| a[c].abs_diff(b[c]) <= tolerance)
+}
+
+/// Marks the pixels connected to `seed` whose color is within `tolerance` of
+/// the seed's, walking scanlines out from it. Pixels where `allowed` is false
+/// are neither marked nor crossed. Indexed `y * width + x`.
+pub fn flood_region(
+    buffer: &RgbaImage,
+    seed: (u32, u32),
+    tolerance: u8,
+    allowed: impl Fn(u32, u32) -> bool,
+) -> Vec<bool> {
+    let (w, h) = buffer.dimensions();
+    let mut filled = vec![false; (w * h) as usize];
+    if !allowed(seed.0, seed.1) {
+        return filled;
+    }
+    let target = *buffer.get_pixel(seed.0, seed.1);
+    let matches = |x: u32, y: u32, filled: &[bool]| {
+        !filled[(y * w + x) as usize]
+            && allowed(x, y)
+            && within_tolerance(*buffer.get_pixel(x, y), target, tolerance)
+    };
+
+    let mut stack = vec![seed];
+    while let Some((x, y)) = stack.pop() {
+        if !matches(x, y, &filled) {
+            continue;
+        }
+        let mut left = x;
+        while left > 0 && matches(left - 1, y, &filled) {
+            left -= 1;
+        }
+        let mut right = x;
+        while right + 1 < w && matches(right + 1, y, &filled) {
+            right += 1;
+        }
+        for fx in left..=right {
+            filled[(y * w + fx) as usize] = true;
+            if y > 0 && matches(fx, y - 1, &filled) {
+                stack.push((fx, y - 1));
+            }
+            if y + 1 < h && matches(fx, y + 1, &filled) {
+                stack.push((fx, y + 1));
+            }
+        }
+    }
+    filled
+}
+
+impl Tool for FillTool {
+    fn name(&self) -> &str {
+        "Fill"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        input: &ToolInput,
+        color: Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        if image.active_layer_is_locked() {
+            return None;
+        }
+
+        // Fill once per click, not on every frame the button is held.
+        let just_pressed = input.is_pressed && !self.was_pressed;
+        self.was_pressed = input.is_pressed;
+        if !just_pressed {
+            return None;
+        }
+
+        let pos = input.pos?;
+        if pos.x < 0.0
+            || pos.y < 0.0
+            || pos.x >= image.width() as f32
+            || pos.y >= image.height() as f32
+        {
+            return None;
+        }
+        let seed = (pos.x as u32, pos.y as u32);
+
+        let layer_index = image.active_layer;
+        let selection = &image.selection;
+        let layer = image.layers.get_mut(layer_index)?;
+        let alpha_locked = layer.alpha_locked;
+        let channel_lock = layer.channel_lock;
+        let target = match &mut layer.data {
+            crate::layers::LayerData::Raster(img) => img,
+            crate::layers::LayerData::Tone { buffer, .. } => buffer,
+            _ => return None,
+        };
+
+        let region = Self::region(target, selection.as_ref(), seed, settings);
+        let w = target.width();
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for (i, _) in region.iter().enumerate().filter(|(_, &f)| f) {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            bounds = Some(match bounds {
+                Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+        let (x0, y0, x1, y1) = bounds?;
+        let (bw, bh) = (x1 - x0 + 1, y1 - y0 + 1);
+
+        let old_patch = target.view(x0, y0, bw, bh).to_image();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                if !region[(y * w + x) as usize] {
+                    continue;
+                }
+                let existing = *target.get_pixel(x, y);
+                if (alpha_locked && existing[3] == 0) || (settings.fill_behind && existing[3] > 0) {
+                    continue;
+                }
+                // Feathered selection edges fill at partial strength.
+                let selected = selection
+                    .as_ref()
+                    .map_or(255, |mask| mask.get_pixel(x, y)[0]);
+                let mut filled = crate::color::over(existing, crate::color::fade(color, selected));
+                if alpha_locked {
+                    filled[3] = existing[3];
+                }
+                target.put_pixel(
+                    x,
+                    y,
+                    crate::layers::lock_channels(channel_lock, existing, filled),
+                );
+            }
+        }
+        let new_patch = target.view(x0, y0, bw, bh).to_image();
+        image.mark_dirty_rect(x0, y0, bw, bh);
+
+        PatchCommand::if_changed("Fill", layer_index, x0, y0, old_patch, new_patch)
+    }
+
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        None
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+        painter.circle_stroke(pos, 3.0, egui::Stroke::new(1.0, Color32::WHITE));
+    }
+
+    fn configure(&mut self, ui: &mut Ui, settings: &mut ToolSettings) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut settings.fill_mode, FillMode::Flood, "Flood Fill")
+                .on_hover_text("Fill connected pixels matching the clicked color");
+            ui.selectable_value(
+                &mut settings.fill_mode,
+                FillMode::Selection,
+                "Fill Selection",
+            )
+            .on_hover_text("Fill every selected pixel regardless of color");
+            if settings.fill_mode == FillMode::Flood {
+                ui.label("Tolerance:");
+                ui.add(egui::Slider::new(&mut settings.fill_tolerance, 0..=255));
+            }
+            ui.checkbox(&mut settings.fill_behind, "Behind")
+                .on_hover_text("Paint only where the layer is transparent");
+        });
+    }
+}
@@ -0,0 +1,192 @@
+use crate::commands::{Command, PatchCommand};
+use crate::image_store::ImageStore;
+use crate::state::{GradientType, ToolSettings};
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::{Color32, Painter, Pos2, Rect, Stroke, Ui, Vec2};
+use image::{GenericImageView, Rgba, RgbaImage};
+
+/// Drag a line to fill the active layer, or the selection within it, with a
+/// gradient from the drawing color to the other one.
+pub struct GradientTool {
+    start: Option<Pos2>,
+    end: Option<Pos2>,
+}
+
+impl GradientTool {
+    pub fn new() -> Self {
+        Self {
+            start: None,
+            end: None,
+        }
+    }
+
+    /// Where `p` falls along the gradient: 0.0 at `start` or before it, 1.0
+    /// at `end` or past it.
+    fn position(kind: GradientType, start: Pos2, axis: Vec2, p: Pos2) -> f32 {
+        let t = match kind {
+            GradientType::Linear => (p - start).dot(axis) / axis.length_sq(),
+            GradientType::Radial => p.distance(start) / axis.length(),
+        };
+        t.clamp(0.0, 1.0)
+    }
+
+    /// Fills the active layer between `start` and `end`. Returns the undo
+    /// patch, or `None` if nothing changed.
+    fn apply(
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        (start, end): (Pos2, Pos2),
+        (from, to): (Rgba<u8>, Rgba<u8>),
+    ) -> Option<Box<dyn Command>> {
+        let axis = end - start;
+        if axis.length() < 0.5 {
+            return None;
+        }
+        let (x0, y0, x1, y1) = match image.selection {
+            Some(_) => image.selection_bounds()?,
+            None => (
+                0,
+                0,
+                image.width().checked_sub(1)?,
+                image.height().checked_sub(1)?,
+            ),
+        };
+
+        let layer_index = image.active_layer;
+        let selection = &image.selection;
+        let layer = image.layers.get_mut(layer_index)?;
+        let alpha_locked = layer.alpha_locked;
+        let channel_lock = layer.channel_lock;
+        let target = match &mut layer.data {
+            crate::layers::LayerData::Raster(img) => img,
+            crate::layers::LayerData::Tone { buffer, .. } => buffer,
+            _ => return None,
+        };
+        let (w, h) = (x1 - x0 + 1, y1 - y0 + 1);
+
+        let old_patch = target.view(x0, y0, w, h).to_image();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let selected = selection
+                    .as_ref()
+                    .map_or(255, |mask| mask.get_pixel(x, y)[0]);
+                let existing = *target.get_pixel(x, y);
+                if selected == 0 || (alpha_locked && existing[3] == 0) {
+                    continue;
+                }
+                let center = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let color = mix(
+                    from,
+                    to,
+                    Self::position(settings.gradient_type, start, axis, center),
+                );
+                let mut filled = if settings.gradient_blend {
+                    crate::color::over(existing, crate::color::fade(color, selected))
+                } else {
+                    // Replace outright, easing into it across a feathered edge.
+                    mix(existing, color, selected as f32 / 255.0)
+                };
+                if alpha_locked {
+                    filled[3] = existing[3];
+                }
+                target.put_pixel(
+                    x,
+                    y,
+                    crate::layers::lock_channels(channel_lock, existing, filled),
+                );
+            }
+        }
+        let new_patch = target.view(x0, y0, w, h).to_image();
+        image.mark_dirty_rect(x0, y0, w, h);
+
+        PatchCommand::if_changed("Gradient", layer_index, x0, y0, old_patch, new_patch)
+    }
+}
+
+/// Linear blend of all four channels from `a` to `b` by `t` in `[0, 1]`.
+fn mix(a: Rgba<u8>, b: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let lerp = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * t).round() as u8;
+    Rgba([
+        lerp(a[0], b[0]),
+        lerp(a[1], b[1]),
+        lerp(a[2], b[2]),
+        lerp(a[3], b[3]),
+    ])
+}
+
+impl Tool for GradientTool {
+    fn name(&self) -> &str {
+        "Gradient"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        input: &ToolInput,
+        color: Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        if image.active_layer_is_locked() {
+            return None;
+        }
+
+        if input.is_pressed {
+            if let Some(pos) = input.pos {
+                self.start.get_or_insert(pos);
+                self.end = Some(pos);
+            }
+        }
+
+        if input.is_released {
+            let line = self.start.take().zip(self.end.take())?;
+            return Self::apply(image, settings, line, (color, input.other_color));
+        }
+        if !input.is_pressed {
+            // A click that never became a drag leaves nothing to fill.
+            self.cancel();
+        }
+        None
+    }
+
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        None
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+        painter.circle_stroke(pos, 3.0, Stroke::new(1.0, Color32::WHITE));
+    }
+
+    fn draw_overlay(&self, painter: &Painter, view: &ViewTransform) {
+        let (Some(start), Some(end)) = (self.start, self.end) else {
+            return;
+        };
+        let (start, end) = (view.screen_pos(start), view.screen_pos(end));
+        painter.line_segment([start, end], Stroke::new(3.0, Color32::BLACK));
+        painter.line_segment([start, end], Stroke::new(1.0, Color32::WHITE));
+        painter.circle_filled(start, 4.0, Color32::WHITE);
+        painter.circle_stroke(end, 4.0, Stroke::new(1.5, Color32::WHITE));
+    }
+
+    fn cancel(&mut self) {
+        self.start = None;
+        self.end = None;
+    }
+
+    fn configure(&mut self, ui: &mut Ui, settings: &mut ToolSettings) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut settings.gradient_type, GradientType::Linear, "Linear")
+                .on_hover_text("Blend along the dragged line");
+            ui.selectable_value(&mut settings.gradient_type, GradientType::Radial, "Radial")
+                .on_hover_text("Blend outward from where the drag started");
+            ui.checkbox(&mut settings.gradient_blend, "Blend")
+                .on_hover_text("Composite over the layer instead of replacing its pixels");
+        });
+    }
+}
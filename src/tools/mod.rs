@@ -1,12 +1,47 @@
 pub mod base;
+pub mod crop;
 pub mod ellipse;
+pub mod eyedropper;
+pub mod fill;
+pub mod gradient;
 pub mod rect;
 pub mod selection;
 pub mod transform;
+pub mod vector;
 
 // Re-export core traits and structs
-pub use base::{BrushTool, EraserTool, LineTool, Tool, ToolInput};
+pub use base::{BrushTool, EraserTool, LineTool, Tool, ToolInput, ViewTransform};
+pub use crop::CropTool;
 pub use ellipse::EllipseTool;
+pub use eyedropper::EyedropperTool;
+pub use fill::FillTool;
+pub use gradient::GradientTool;
 pub use rect::RectangleTool;
-pub use selection::{LassoSelectionTool, RectSelectionTool};
+pub use selection::{EllipseSelectionTool, LassoSelectionTool, MagicWandTool, RectSelectionTool};
 pub use transform::TransformTool;
+pub use vector::VectorShapeTool;
+
+/// Builds a fresh tool from its `Tool::name`, sized for a `width`×`height`
+/// document. Used to restore a document's active tool without carrying over
+/// per-document state such as temp layers or floating selections.
+pub fn create_tool(name: &str, width: u32, height: u32) -> Option<Box<dyn Tool>> {
+    let tool: Box<dyn Tool> = match name {
+        "Brush" => Box::new(BrushTool::new(width, height)),
+        "Eraser" => Box::new(EraserTool::new(width, height)),
+        "Line" => Box::new(LineTool::new(width, height)),
+        "Rectangle" => Box::new(RectangleTool::new(width, height)),
+        "Ellipse" => Box::new(EllipseTool::new(width, height)),
+        "Rect Selection" => Box::new(RectSelectionTool::new()),
+        "Ellipse Selection" => Box::new(EllipseSelectionTool::new()),
+        "Lasso Selection" => Box::new(LassoSelectionTool::new()),
+        "Magic Wand" => Box::new(MagicWandTool::new()),
+        "Transform" => Box::new(TransformTool::new()),
+        "Crop" => Box::new(CropTool::new()),
+        "Eyedropper" => Box::new(EyedropperTool::new()),
+        "Fill" => Box::new(FillTool::new()),
+        "Vector Shape" => Box::new(VectorShapeTool::new(width, height)),
+        "Gradient" => Box::new(GradientTool::new()),
+        _ => return None,
+    };
+    Some(tool)
+}
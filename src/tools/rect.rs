@@ -1,8 +1,9 @@
-use crate::commands::{Command, PatchCommand};
+use crate::commands::Command;
 use crate::image_store::ImageStore;
-use crate::tools::{Tool, ToolInput};
-use egui::{Color32, Painter, Pos2, Rect, Ui, Vec2};
-use image::{GenericImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use crate::tools::base::{commit_temp_layer, drag_rect};
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::{Color32, Painter, Pos2, Rect, Ui};
+use image::{ImageBuffer, Rgba, RgbaImage};
 
 pub struct RectangleTool {
     layer: RgbaImage,
@@ -21,7 +22,14 @@ impl RectangleTool {
         }
     }
 
-    fn draw_rect_on_layer(&mut self, start: Pos2, end: Pos2, color: Rgba<u8>, width: f32) {
+    fn draw_rect_on_layer(
+        &mut self,
+        start: Pos2,
+        end: Pos2,
+        color: Rgba<u8>,
+        width: f32,
+        fill: bool,
+    ) {
         if let Some(rect) = self.dirty_rect {
             let x = rect.min.x as u32;
             let y = rect.min.y as u32;
@@ -49,46 +57,63 @@ impl RectangleTool {
 
         let mut new_dirty: Option<Rect> = None;
 
-        let mut draw_line =
-            |p1: Pos2, p2: Pos2, layer: &mut RgbaImage, dirty: &mut Option<Rect>| {
-                let dist = p1.distance(p2);
-                let steps = (dist / 1.0).max(1.0) as u32;
-
-                for i in 0..=steps {
-                    let t = i as f32 / steps as f32;
-                    let pos = p1.lerp(p2, t);
-
-                    let x = pos.x as i32;
-                    let y = pos.y as i32;
-                    let r = width as i32;
-                    let r_sq = r * r;
-
-                    let width_img = layer.width() as i32;
-                    let height_img = layer.height() as i32;
-
-                    let min_x = (x - r).max(0);
-                    let max_x = (x + r).min(width_img - 1);
-                    let min_y = (y - r).max(0);
-                    let max_y = (y + r).min(height_img - 1);
+        if fill {
+            let max_px = (self.layer.width() as f32 - 1.0).max(0.0);
+            let max_py = (self.layer.height() as f32 - 1.0).max(0.0);
+            let x0 = min_x.clamp(0.0, max_px) as u32;
+            let x1 = max_x.clamp(0.0, max_px) as u32;
+            let y0 = min_y.clamp(0.0, max_py) as u32;
+            let y1 = max_y.clamp(0.0, max_py) as u32;
+            for py in y0..=y1 {
+                for px in x0..=x1 {
+                    self.layer.put_pixel(px, py, color);
+                }
+            }
+            new_dirty = Some(Rect::from_min_max(
+                Pos2::new(x0 as f32, y0 as f32),
+                Pos2::new(x1 as f32 + 1.0, y1 as f32 + 1.0),
+            ));
+        }
 
-                    let rect = Rect::from_min_max(
-                        Pos2::new(min_x as f32, min_y as f32),
-                        Pos2::new(max_x as f32 + 1.0, max_y as f32 + 1.0),
-                    );
-                    *dirty = Some(match *dirty {
-                        Some(r) => r.union(rect),
-                        None => rect,
-                    });
-
-                    for cy in min_y..=max_y {
-                        for cx in min_x..=max_x {
-                            if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r_sq {
-                                layer.put_pixel(cx as u32, cy as u32, color);
-                            }
+        let draw_line = |p1: Pos2, p2: Pos2, layer: &mut RgbaImage, dirty: &mut Option<Rect>| {
+            let dist = p1.distance(p2);
+            let steps = (dist / 1.0).max(1.0) as u32;
+
+            for i in 0..=steps {
+                let t = i as f32 / steps as f32;
+                let pos = p1.lerp(p2, t);
+
+                let x = pos.x as i32;
+                let y = pos.y as i32;
+                let r = width as i32;
+                let r_sq = r * r;
+
+                let width_img = layer.width() as i32;
+                let height_img = layer.height() as i32;
+
+                let min_x = (x - r).max(0);
+                let max_x = (x + r).min(width_img - 1);
+                let min_y = (y - r).max(0);
+                let max_y = (y + r).min(height_img - 1);
+
+                let rect = Rect::from_min_max(
+                    Pos2::new(min_x as f32, min_y as f32),
+                    Pos2::new(max_x as f32 + 1.0, max_y as f32 + 1.0),
+                );
+                *dirty = Some(match *dirty {
+                    Some(r) => r.union(rect),
+                    None => rect,
+                });
+
+                for cy in min_y..=max_y {
+                    for cx in min_x..=max_x {
+                        if (cx - x) * (cx - x) + (cy - y) * (cy - y) <= r_sq {
+                            layer.put_pixel(cx as u32, cy as u32, color);
                         }
                     }
                 }
-            };
+            }
+        };
 
         draw_line(tl, tr, &mut self.layer, &mut new_dirty);
         draw_line(tr, br, &mut self.layer, &mut new_dirty);
@@ -114,6 +139,9 @@ impl Tool for RectangleTool {
         if self.layer.width() != image.width() || self.layer.height() != image.height() {
             self.layer = ImageBuffer::new(image.width(), image.height());
         }
+        if image.active_layer_is_locked() {
+            return None;
+        }
 
         if input.is_pressed {
             if self.start_pos.is_none() {
@@ -122,94 +150,39 @@ impl Tool for RectangleTool {
             if let Some(pos) = input.pos {
                 self.current_pos = Some(pos);
                 if let Some(start) = self.start_pos {
-                    self.draw_rect_on_layer(start, pos, color, settings.line_width);
+                    let (from, to) = drag_rect(start, pos, input.shift, input.alt);
+                    self.draw_rect_on_layer(
+                        from,
+                        to,
+                        color,
+                        settings.line_width,
+                        settings.shape_fill,
+                    );
                 }
             }
         }
 
         if input.is_released {
-            if let (Some(_start), Some(_end), Some(rect)) =
-                (self.start_pos, self.current_pos, self.dirty_rect)
-            {
-                let x = rect.min.x as u32;
-                let y = rect.min.y as u32;
-                let w = rect.width() as u32;
-                let h = rect.height() as u32;
-                let w = w.min(image.width() - x);
-                let h = h.min(image.height() - y);
-                let layer_index = image.active_layer;
-                let selection = &image.selection;
-                let layer = &mut image.layers[layer_index];
-                let alpha_locked = layer.alpha_locked;
-
-                let target_buffer = match &mut layer.data {
-                    crate::layers::LayerData::Raster(img) => Some(img),
-                    crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
-                    _ => None,
-                };
-
-                if let Some(target_buffer) = target_buffer {
-                    if w > 0 && h > 0 {
-                        let old_patch = target_buffer.view(x, y, w, h).to_image();
-                        let layer_patch = self.layer.view(x, y, w, h).to_image();
-
-                        for ly in 0..h {
-                            for lx in 0..w {
-                                let pixel = layer_patch.get_pixel(lx, ly);
-                                if pixel[3] > 0 {
-                                    let mut selected = true;
-                                    if let Some(mask) = selection {
-                                        let mask_val = mask.get_pixel(x + lx, y + ly)[0];
-                                        if mask_val == 0 {
-                                            selected = false;
-                                        }
-                                    }
-
-                                    if selected {
-                                        let target_pixel = target_buffer.get_pixel(x + lx, y + ly);
-                                        if !alpha_locked || target_pixel[3] > 0 {
-                                            let mut final_pixel = *pixel;
-                                            if alpha_locked {
-                                                final_pixel[3] = target_pixel[3];
-                                            }
-                                            target_buffer.put_pixel(x + lx, y + ly, final_pixel);
-                                        }
-                                    }
-                                    self.layer.put_pixel(x + lx, y + ly, Rgba([0, 0, 0, 0]));
-                                }
-                            }
-                        }
-
-                        let new_patch = target_buffer.view(x, y, w, h).to_image();
-                        image.mark_dirty();
-                        self.start_pos = None;
-                        self.current_pos = None;
-                        self.dirty_rect = None;
-
-                        return Some(Box::new(PatchCommand {
-                            name: "Rectangle".to_string(),
-                            layer_index,
-                            x,
-                            y,
-                            old_patch,
-                            new_patch,
-                        }));
-                    }
-                }
-            }
+            let stroke = self.start_pos.and(self.current_pos).and(self.dirty_rect);
             self.start_pos = None;
             self.current_pos = None;
             self.dirty_rect = None;
+            if let Some(rect) = stroke {
+                return commit_temp_layer(
+                    image,
+                    &mut self.layer,
+                    rect,
+                    "Rectangle",
+                    false,
+                    crate::color::over,
+                );
+            }
         }
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
-        if self.dirty_rect.is_some() {
-            Some((&self.layer, 0, 0))
-        } else {
-            None
-        }
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        self.dirty_rect.map(|rect| (&self.layer, rect))
     }
 
     fn draw_cursor(
@@ -218,10 +191,11 @@ impl Tool for RectangleTool {
         painter: &Painter,
         settings: &crate::state::ToolSettings,
         pos: Pos2,
+        view: &ViewTransform,
     ) {
         painter.circle_stroke(
             pos,
-            settings.line_width,
+            settings.line_width * view.zoom,
             egui::Stroke::new(1.0, Color32::WHITE),
         );
     }
@@ -230,6 +204,7 @@ impl Tool for RectangleTool {
         ui.horizontal(|ui| {
             ui.label("Width:");
             ui.add(egui::DragValue::new(&mut settings.line_width).range(1.0..=20.0));
+            ui.checkbox(&mut settings.shape_fill, "Fill");
         });
     }
 }
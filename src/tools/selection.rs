@@ -1,9 +1,41 @@
 use crate::commands::Command;
 use crate::image_store::ImageStore;
 use crate::state::ToolSettings;
-use crate::tools::{Tool, ToolInput};
+use crate::tools::fill::{flood_region, within_tolerance};
+use crate::tools::{Tool, ToolInput, ViewTransform};
 use egui::{Color32, Painter, Pos2, Rect, Ui};
-use image::{ImageBuffer, Luma, RgbaImage};
+use image::{GrayImage, ImageBuffer, Luma, RgbaImage};
+
+/// Applies a newly drawn selection region according to the held modifiers:
+/// Shift adds it to the current selection, Alt subtracts it, and with neither
+/// it replaces the selection. `None` is an empty region.
+fn apply_selection(image: &mut ImageStore, region: Option<GrayImage>, input: &ToolInput) {
+    if !input.shift && !input.alt {
+        image.selection = region;
+        return;
+    }
+    // An empty region adds or removes nothing.
+    let Some(region) = region else {
+        return;
+    };
+    let Some(selection) = image.selection.as_mut() else {
+        // Without a selection there is nothing to subtract from.
+        if !input.alt {
+            image.selection = Some(region);
+        }
+        return;
+    };
+    for (selected, new) in selection.pixels_mut().zip(region.pixels()) {
+        selected.0[0] = if input.alt {
+            selected.0[0].min(255 - new.0[0])
+        } else {
+            selected.0[0].max(new.0[0])
+        };
+    }
+    if selection.pixels().all(|p| p.0[0] == 0) {
+        image.selection = None;
+    }
+}
 
 pub struct RectSelectionTool {
     start_pos: Option<Pos2>,
@@ -55,11 +87,8 @@ impl Tool for RectSelectionTool {
                     }
                 }
 
-                if max_x > min_x && max_y > min_y {
-                    image.selection = Some(mask);
-                } else {
-                    image.selection = None;
-                }
+                let region = (max_x > min_x && max_y > min_y).then_some(mask);
+                apply_selection(image, region, input);
             }
             self.start_pos = None;
             self.current_pos = None;
@@ -68,11 +97,18 @@ impl Tool for RectSelectionTool {
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
         None
     }
 
-    fn draw_cursor(&self, _ui: &mut Ui, painter: &Painter, _settings: &ToolSettings, pos: Pos2) {
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
         painter.circle_filled(pos, 2.0, Color32::LIGHT_BLUE);
         if let (Some(start), Some(current)) = (self.start_pos, Some(pos)) {
             let rect = Rect::from_two_pos(start, current);
@@ -81,7 +117,115 @@ impl Tool for RectSelectionTool {
     }
 
     fn configure(&mut self, ui: &mut Ui, _settings: &mut ToolSettings) {
-        ui.label("Drag to select a rectangular area.");
+        ui.label("Drag to select a rectangular area. Shift adds, Alt subtracts.");
+    }
+}
+
+pub struct EllipseSelectionTool {
+    start_pos: Option<Pos2>,
+    current_pos: Option<Pos2>,
+}
+
+impl EllipseSelectionTool {
+    pub fn new() -> Self {
+        Self {
+            start_pos: None,
+            current_pos: None,
+        }
+    }
+}
+
+impl Tool for EllipseSelectionTool {
+    fn name(&self) -> &str {
+        "Ellipse Selection"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        _settings: &ToolSettings,
+        input: &ToolInput,
+        _color: image::Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        if input.is_pressed {
+            if self.start_pos.is_none() {
+                self.start_pos = input.pos;
+            }
+            self.current_pos = input.pos;
+        }
+
+        if input.is_released {
+            if let (Some(start), Some(end)) = (self.start_pos, self.current_pos) {
+                let bounds = Rect::from_two_pos(start, end);
+                let center = bounds.center();
+                let (rx, ry) = (bounds.width() / 2.0, bounds.height() / 2.0);
+
+                let w = image.width();
+                let h = image.height();
+                let mut mask = ImageBuffer::new(w, h);
+
+                if rx > 0.0 && ry > 0.0 {
+                    let min_x = (bounds.min.x.max(0.0) as u32).min(w);
+                    let max_x = (bounds.max.x.max(0.0).ceil() as u32).min(w);
+                    let min_y = (bounds.min.y.max(0.0) as u32).min(h);
+                    let max_y = (bounds.max.y.max(0.0).ceil() as u32).min(h);
+                    for y in min_y..max_y {
+                        for x in min_x..max_x {
+                            // Test pixel centers so the mask is symmetric.
+                            let dx = (x as f32 + 0.5 - center.x) / rx;
+                            let dy = (y as f32 + 0.5 - center.y) / ry;
+                            if dx * dx + dy * dy <= 1.0 {
+                                mask.put_pixel(x, y, Luma([255]));
+                            }
+                        }
+                    }
+                    apply_selection(image, Some(mask), input);
+                } else {
+                    apply_selection(image, None, input);
+                }
+            }
+            self.start_pos = None;
+            self.current_pos = None;
+        }
+
+        None
+    }
+
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        None
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+        painter.circle_filled(pos, 2.0, Color32::LIGHT_BLUE);
+    }
+
+    fn draw_overlay(&self, painter: &Painter, view: &ViewTransform) {
+        let (Some(start), Some(current)) = (self.start_pos, self.current_pos) else {
+            return;
+        };
+        let rect = view.screen_rect(Rect::from_two_pos(start, current));
+        let (rx, ry) = (rect.width() / 2.0, rect.height() / 2.0);
+        let points = (0..64)
+            .map(|i| {
+                let t = i as f32 / 64.0 * std::f32::consts::TAU;
+                rect.center() + egui::vec2(rx * t.cos(), ry * t.sin())
+            })
+            .collect();
+        painter.add(egui::Shape::closed_line(
+            points,
+            egui::Stroke::new(1.0, Color32::LIGHT_BLUE),
+        ));
+    }
+
+    fn configure(&mut self, ui: &mut Ui, _settings: &mut ToolSettings) {
+        ui.label("Drag to select an elliptical area. Shift adds, Alt subtracts.");
     }
 }
 
@@ -160,7 +304,7 @@ impl Tool for LassoSelectionTool {
                         }
                     }
                 }
-                image.selection = Some(mask);
+                apply_selection(image, Some(mask), input);
             }
 
             self.points.clear();
@@ -169,11 +313,18 @@ impl Tool for LassoSelectionTool {
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
         None
     }
 
-    fn draw_cursor(&self, _ui: &mut Ui, painter: &Painter, _settings: &ToolSettings, pos: Pos2) {
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
         painter.circle_filled(pos, 2.0, Color32::LIGHT_BLUE);
         if self.points.len() > 1 {
             for i in 0..self.points.len() - 1 {
@@ -190,6 +341,101 @@ impl Tool for LassoSelectionTool {
     }
 
     fn configure(&mut self, ui: &mut Ui, _settings: &mut ToolSettings) {
-        ui.label("Draw a free-form path to select an area.");
+        ui.label("Draw a free-form path to select an area. Shift adds, Alt subtracts.");
+    }
+
+    fn status(&self) -> Option<String> {
+        match self.points.len() {
+            0 => None,
+            1 => Some("1 point".to_string()),
+            n => Some(format!("{} points", n)),
+        }
+    }
+}
+
+/// Selects pixels of the composite close in color to the clicked one: the
+/// connected region around the click, or every match with `wand_contiguous`
+/// off.
+pub struct MagicWandTool {
+    was_pressed: bool,
+}
+
+impl MagicWandTool {
+    pub fn new() -> Self {
+        Self { was_pressed: false }
+    }
+}
+
+impl Tool for MagicWandTool {
+    fn name(&self) -> &str {
+        "Magic Wand"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        input: &ToolInput,
+        _color: image::Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        // Select once per click, not on every frame the button is held.
+        let just_pressed = input.is_pressed && !self.was_pressed;
+        self.was_pressed = input.is_pressed;
+        if !just_pressed {
+            return None;
+        }
+
+        let pos = input.pos?;
+        let (w, h) = (image.width(), image.height());
+        if pos.x < 0.0 || pos.y < 0.0 || pos.x >= w as f32 || pos.y >= h as f32 {
+            return None;
+        }
+        let seed = (pos.x as u32, pos.y as u32);
+        let tolerance = settings.wand_tolerance;
+
+        let composite = image.get_composite();
+        let region = if settings.wand_contiguous {
+            flood_region(composite, seed, tolerance, |_, _| true)
+        } else {
+            let target = *composite.get_pixel(seed.0, seed.1);
+            composite
+                .pixels()
+                .map(|p| within_tolerance(*p, target, tolerance))
+                .collect()
+        };
+
+        let mask = ImageBuffer::from_fn(w, h, |x, y| {
+            Luma([if region[(y * w + x) as usize] { 255 } else { 0 }])
+        });
+        apply_selection(image, Some(mask), input);
+        None
+    }
+
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        None
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+        painter.circle_filled(pos, 2.0, Color32::LIGHT_BLUE);
+        painter.line_segment(
+            [pos, pos + egui::vec2(10.0, -10.0)],
+            egui::Stroke::new(1.5, Color32::LIGHT_BLUE),
+        );
+    }
+
+    fn configure(&mut self, ui: &mut Ui, settings: &mut ToolSettings) {
+        ui.horizontal(|ui| {
+            ui.label("Tolerance:");
+            ui.add(egui::Slider::new(&mut settings.wand_tolerance, 0..=255));
+            ui.checkbox(&mut settings.wand_contiguous, "Contiguous")
+                .on_hover_text("Only select pixels connected to the click");
+        });
     }
 }
@@ -1,10 +1,10 @@
 use crate::commands::{Command, PatchCommand};
 use crate::image_store::ImageStore;
-use crate::layers::LayerData;
 use crate::state::ToolSettings;
-use crate::tools::{Tool, ToolInput};
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::emath::Rot2;
 use egui::{Color32, Painter, Pos2, Rect, Ui, Vec2};
-use image::{GenericImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use image::{ImageBuffer, Rgba, RgbaImage};
 
 pub struct TransformTool {
     floating_buffer: Option<RgbaImage>,
@@ -17,8 +17,22 @@ pub struct TransformTool {
     committed: bool,
     original_layer_snapshot: Option<RgbaImage>,
     layer_index: usize,
+    empty_selection_logged: bool,
+    /// Align the placed pixels to the pixel grid on commit so Nearest
+    /// resampling stays crisp.
+    snap_to_pixels: bool,
+    /// Clockwise rotation of `current_rect` about its center, in radians.
+    rotation: f32,
+    /// The corner opposite the one being dragged, fixed for the whole drag.
+    anchor: Option<Pos2>,
+    /// Keep width and height in proportion, for both the size fields and
+    /// the corner handles.
+    lock_aspect: bool,
 }
 
+/// Distance of the rotation handle above the top edge, in screen points.
+const ROTATE_HANDLE_OFFSET: f32 = 24.0;
+
 #[derive(Clone, Copy, PartialEq)]
 enum HandleType {
     Center,
@@ -26,6 +40,7 @@ enum HandleType {
     TopRight,
     BottomLeft,
     BottomRight,
+    Rotate,
 }
 
 impl TransformTool {
@@ -41,65 +56,209 @@ impl TransformTool {
             committed: false,
             original_layer_snapshot: None,
             layer_index: 0,
+            empty_selection_logged: false,
+            snap_to_pixels: true,
+            rotation: 0.0,
+            anchor: None,
+            lock_aspect: false,
         }
     }
 
-    fn pick_up_selection(&mut self, image: &mut ImageStore) {
-        if let Some(mask) = &image.selection {
-            let mut min_x = mask.width();
-            let mut max_x = 0;
-            let mut min_y = mask.height();
-            let mut max_y = 0;
-            let mut found = false;
-
-            for (x, y, p) in mask.enumerate_pixels() {
-                if p[0] > 0 {
-                    min_x = min_x.min(x);
-                    max_x = max_x.max(x);
-                    min_y = min_y.min(y);
-                    max_y = max_y.max(y);
-                    found = true;
+    /// Editable position, size and angle of the floating selection.
+    fn numeric_ui(&mut self, ui: &mut Ui) {
+        let Some(current) = self.current_rect else {
+            return;
+        };
+        let current = Rect::from_two_pos(current.min, current.max);
+        let (mut x, mut y) = (current.min.x, current.min.y);
+        let (mut w, mut h) = (current.width(), current.height());
+
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("X:");
+            changed |= ui.add(egui::DragValue::new(&mut x).speed(1.0)).changed();
+            ui.label("Y:");
+            changed |= ui.add(egui::DragValue::new(&mut y).speed(1.0)).changed();
+        });
+        ui.horizontal(|ui| {
+            ui.label("W:");
+            let width_changed = ui
+                .add(
+                    egui::DragValue::new(&mut w)
+                        .speed(1.0)
+                        .range(1.0..=f32::MAX),
+                )
+                .changed();
+            ui.label("H:");
+            let height_changed = ui
+                .add(
+                    egui::DragValue::new(&mut h)
+                        .speed(1.0)
+                        .range(1.0..=f32::MAX),
+                )
+                .changed();
+            ui.checkbox(&mut self.lock_aspect, "Lock Aspect");
+            if self.lock_aspect && current.height() > 0.0 {
+                let aspect = current.width() / current.height();
+                if width_changed {
+                    h = (w / aspect).max(1.0);
+                } else if height_changed {
+                    w = (h * aspect).max(1.0);
                 }
             }
+            changed |= width_changed || height_changed;
+        });
+        ui.horizontal(|ui| {
+            ui.label("Angle:");
+            let mut degrees = self.rotation.to_degrees();
+            if ui
+                .add(
+                    egui::DragValue::new(&mut degrees)
+                        .range(-360.0..=360.0)
+                        .suffix("°"),
+                )
+                .changed()
+            {
+                self.rotation = degrees.to_radians();
+            }
+        });
 
-            if found {
-                let w = max_x - min_x + 1;
-                let h = max_y - min_y + 1;
-                let mut buffer = ImageBuffer::new(w, h);
+        if changed {
+            self.current_rect = Some(Rect::from_min_size(Pos2::new(x, y), Vec2::new(w, h)));
+        }
+    }
 
-                self.layer_index = image.active_layer;
-                let layer_img = match &mut image.layers[self.layer_index].data {
-                    crate::layers::LayerData::Raster(img) => Some(img),
-                    crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
-                    _ => None,
-                };
+    /// Maps a point in the rect's own unrotated frame onto the image.
+    fn to_image(&self, rect: Rect, local: Pos2) -> Pos2 {
+        rect.center() + Rot2::from_angle(self.rotation) * (local - rect.center())
+    }
 
-                if let Some(layer_img) = layer_img {
-                    self.original_layer_snapshot = Some(layer_img.clone());
+    /// Maps an image point into the rect's own unrotated frame.
+    fn to_local(&self, rect: Rect, pos: Pos2) -> Pos2 {
+        rect.center() + Rot2::from_angle(-self.rotation) * (pos - rect.center())
+    }
 
-                    for y in 0..h {
-                        for x in 0..w {
-                            let cx = min_x + x;
-                            let cy = min_y + y;
-                            if mask.get_pixel(cx, cy)[0] > 0 {
-                                buffer.put_pixel(x, y, *layer_img.get_pixel(cx, cy));
-                                layer_img.put_pixel(cx, cy, Rgba([0, 0, 0, 0]));
-                            }
-                        }
-                    }
-                    image.mark_dirty();
+    /// Where the rotation handle sits, `offset` image pixels above the
+    /// middle of the rect's top edge.
+    fn rotate_handle(&self, rect: Rect, offset: f32) -> Pos2 {
+        self.to_image(rect, rect.center_top() - Vec2::new(0.0, offset))
+    }
+
+    /// Stretches `diagonal`, a corner-to-corner vector in the rect's frame,
+    /// to the picked-up region's proportions, following its longer side.
+    fn keep_aspect(&self, diagonal: Vec2) -> Vec2 {
+        let Some(source) = self.source_rect.filter(|r| r.height() > 0.0) else {
+            return diagonal;
+        };
+        let aspect = source.width() / source.height();
+        let (w, h) = (diagonal.x.abs(), diagonal.y.abs());
+        let (w, h) = if w / aspect > h {
+            (w, w / aspect)
+        } else {
+            (h * aspect, h)
+        };
+        Vec2::new(w.copysign(diagonal.x), h.copysign(diagonal.y))
+    }
 
-                    let rect = Rect::from_min_max(
-                        Pos2::new(min_x as f32, min_y as f32),
-                        Pos2::new((max_x + 1) as f32, (max_y + 1) as f32),
+    /// Draws the floating buffer into `target`, rotated by `rotation` about
+    /// the center of `rect`. Each target pixel samples its nearest source
+    /// pixel.
+    fn place_rotated(
+        &self,
+        buffer: &RgbaImage,
+        rect: Rect,
+        target: &mut RgbaImage,
+        channel_lock: [bool; 4],
+    ) {
+        let (w, h) = target.dimensions();
+        let (bw, bh) = buffer.dimensions();
+        let corners = [
+            rect.left_top(),
+            rect.right_top(),
+            rect.left_bottom(),
+            rect.right_bottom(),
+        ]
+        .map(|c| self.to_image(rect, c));
+        let bounds = Rect::from_points(&corners);
+        let min_x = (bounds.min.x.floor().max(0.0) as u32).min(w);
+        let max_x = (bounds.max.x.ceil().max(0.0) as u32).min(w);
+        let min_y = (bounds.min.y.floor().max(0.0) as u32).min(h);
+        let max_y = (bounds.max.y.ceil().max(0.0) as u32).min(h);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let local = self.to_local(rect, Pos2::new(x as f32 + 0.5, y as f32 + 0.5));
+                if !rect.contains(local) {
+                    continue;
+                }
+                let u = ((local.x - rect.min.x) / rect.width() * bw as f32) as u32;
+                let v = ((local.y - rect.min.y) / rect.height() * bh as f32) as u32;
+                let p = buffer.get_pixel(u.min(bw - 1), v.min(bh - 1));
+                if p[3] > 0 {
+                    let existing = *target.get_pixel(x, y);
+                    target.put_pixel(
+                        x,
+                        y,
+                        crate::layers::lock_channels(channel_lock, existing, *p),
                     );
-                    self.floating_buffer = Some(buffer);
-                    self.source_rect = Some(rect);
-                    self.current_rect = Some(rect);
                 }
             }
         }
     }
+
+    fn pick_up_selection(&mut self, image: &mut ImageStore) {
+        if image.selection.is_none() {
+            return;
+        }
+
+        // An all-zero mask has no region to lift. Bail without a floating
+        // buffer so the handles never operate on a degenerate rect.
+        let Some((min_x, min_y, max_x, max_y)) = image.selection_bounds() else {
+            if !self.empty_selection_logged {
+                log::info!("Transform: selection is empty, nothing to pick up");
+                self.empty_selection_logged = true;
+            }
+            return;
+        };
+        self.empty_selection_logged = false;
+
+        if let Some(mask) = &image.selection {
+            let w = max_x - min_x + 1;
+            let h = max_y - min_y + 1;
+            let mut buffer = ImageBuffer::new(w, h);
+
+            self.layer_index = image.active_layer;
+            let layer_img = match &mut image.layers[self.layer_index].data {
+                crate::layers::LayerData::Raster(img) => Some(img),
+                crate::layers::LayerData::Tone { buffer, .. } => Some(buffer),
+                _ => None,
+            };
+
+            if let Some(layer_img) = layer_img {
+                self.original_layer_snapshot = Some(layer_img.clone());
+
+                for y in 0..h {
+                    for x in 0..w {
+                        let cx = min_x + x;
+                        let cy = min_y + y;
+                        if mask.get_pixel(cx, cy)[0] > 0 {
+                            buffer.put_pixel(x, y, *layer_img.get_pixel(cx, cy));
+                            layer_img.put_pixel(cx, cy, Rgba([0, 0, 0, 0]));
+                        }
+                    }
+                }
+                image.mark_dirty();
+
+                let rect = Rect::from_min_max(
+                    Pos2::new(min_x as f32, min_y as f32),
+                    Pos2::new((max_x + 1) as f32, (max_y + 1) as f32),
+                );
+                self.floating_buffer = Some(buffer);
+                self.source_rect = Some(rect);
+                self.current_rect = Some(rect);
+            }
+        }
+    }
 }
 
 impl Tool for TransformTool {
@@ -122,6 +281,7 @@ impl Tool for TransformTool {
             ) {
                 let layer_index = self.layer_index;
                 let (w, h) = (image.width(), image.height());
+                let channel_lock = image.layers[layer_index].channel_lock;
 
                 let target_buffer = match &mut image.layers[layer_index].data {
                     crate::layers::LayerData::Raster(img) => Some(img),
@@ -129,9 +289,45 @@ impl Tool for TransformTool {
                     _ => None,
                 };
 
+                // Handles can be dragged past each other, so normalize first.
+                let mut current = Rect::from_two_pos(current.min, current.max);
+                if self.snap_to_pixels && self.rotation == 0.0 {
+                    let min = current.min.floor();
+                    current = Rect::from_min_size(min, current.size().round());
+                }
+                let nw = current.width().round() as u32;
+                let nh = current.height().round() as u32;
+
                 if let Some(target_buffer) = target_buffer {
-                    let nw = current.width().max(1.0) as u32;
-                    let nh = current.height().max(1.0) as u32;
+                    if nw == 0 || nh == 0 {
+                        // Nothing to resample into; put the lifted pixels back.
+                        log::info!("Transform: target size is zero, nothing to apply");
+                        *target_buffer = old_snapshot.clone();
+                        image.mark_dirty();
+                        self.committed = false;
+                        self.floating_buffer = None;
+                        self.current_rect = None;
+                        self.rotation = 0.0;
+                        return None;
+                    }
+
+                    if self.rotation != 0.0 {
+                        self.place_rotated(buffer, current, target_buffer, channel_lock);
+                        let new_snapshot = target_buffer.clone();
+                        image.mark_dirty();
+                        self.committed = false;
+                        self.floating_buffer = None;
+                        self.rotation = 0.0;
+
+                        return PatchCommand::if_changed(
+                            "Transform",
+                            layer_index,
+                            0,
+                            0,
+                            old_snapshot.clone(),
+                            new_snapshot,
+                        );
+                    }
 
                     let resized = image::imageops::resize(
                         buffer,
@@ -140,8 +336,8 @@ impl Tool for TransformTool {
                         image::imageops::FilterType::Nearest,
                     );
 
-                    let tx = current.min.x as i32;
-                    let ty = current.min.y as i32;
+                    let tx = current.min.x.floor() as i32;
+                    let ty = current.min.y.floor() as i32;
 
                     for y in 0..nh {
                         for x in 0..nw {
@@ -151,7 +347,10 @@ impl Tool for TransformTool {
                             if cx >= 0 && cx < w as i32 && cy >= 0 && cy < h as i32 {
                                 let p = resized.get_pixel(x, y);
                                 if p[3] > 0 {
-                                    target_buffer.put_pixel(cx as u32, cy as u32, *p);
+                                    let existing = *target_buffer.get_pixel(cx as u32, cy as u32);
+                                    let p =
+                                        crate::layers::lock_channels(channel_lock, existing, *p);
+                                    target_buffer.put_pixel(cx as u32, cy as u32, p);
                                 }
                             }
                         }
@@ -162,19 +361,19 @@ impl Tool for TransformTool {
                     self.committed = false;
                     self.floating_buffer = None;
 
-                    return Some(Box::new(PatchCommand {
-                        name: "Transform".to_string(),
+                    return PatchCommand::if_changed(
+                        "Transform",
                         layer_index,
-                        x: 0,
-                        y: 0,
-                        old_patch: old_snapshot.clone(),
-                        new_patch: new_snapshot,
-                    }));
+                        0,
+                        0,
+                        old_snapshot.clone(),
+                        new_snapshot,
+                    );
                 }
             }
         }
 
-        if self.floating_buffer.is_none() && !self.committed {
+        if self.floating_buffer.is_none() && !self.committed && !image.active_layer_is_locked() {
             self.pick_up_selection(image);
         }
 
@@ -183,15 +382,41 @@ impl Tool for TransformTool {
                 if let Some(mouse_pos) = input.pos {
                     if !self.is_dragging {
                         let handle_size = 12.0;
-                        if mouse_pos.distance(current.left_top()) < handle_size {
-                            self.handle_drag = Some(HandleType::TopLeft);
-                        } else if mouse_pos.distance(current.right_top()) < handle_size {
-                            self.handle_drag = Some(HandleType::TopRight);
-                        } else if mouse_pos.distance(current.left_bottom()) < handle_size {
-                            self.handle_drag = Some(HandleType::BottomLeft);
-                        } else if mouse_pos.distance(current.right_bottom()) < handle_size {
-                            self.handle_drag = Some(HandleType::BottomRight);
-                        } else if current.contains(mouse_pos) {
+                        let rotate_handle =
+                            self.rotate_handle(current, ROTATE_HANDLE_OFFSET / input.zoom);
+                        // Each corner handle with the corner it scales from.
+                        let corners = [
+                            (
+                                HandleType::TopLeft,
+                                current.left_top(),
+                                current.right_bottom(),
+                            ),
+                            (
+                                HandleType::TopRight,
+                                current.right_top(),
+                                current.left_bottom(),
+                            ),
+                            (
+                                HandleType::BottomLeft,
+                                current.left_bottom(),
+                                current.right_top(),
+                            ),
+                            (
+                                HandleType::BottomRight,
+                                current.right_bottom(),
+                                current.left_top(),
+                            ),
+                        ];
+                        let corner = corners.into_iter().find(|(_, corner, _)| {
+                            mouse_pos.distance(self.to_image(current, *corner)) < handle_size
+                        });
+
+                        if mouse_pos.distance(rotate_handle) < 8.0 / input.zoom {
+                            self.handle_drag = Some(HandleType::Rotate);
+                        } else if let Some((handle, _, opposite)) = corner {
+                            self.handle_drag = Some(handle);
+                            self.anchor = Some(self.to_image(current, opposite));
+                        } else if current.contains(self.to_local(current, mouse_pos)) {
                             self.handle_drag = Some(HandleType::Center);
                             self.drag_offset = mouse_pos - current.min;
                         }
@@ -207,21 +432,27 @@ impl Tool for TransformTool {
                                 let size = current.size();
                                 current = Rect::from_min_size(new_min, size);
                             }
-                            Some(HandleType::TopLeft) => {
-                                current.min = mouse_pos;
-                            }
-                            Some(HandleType::TopRight) => {
-                                current.max.x = mouse_pos.x;
-                                current.min.y = mouse_pos.y;
-                            }
-                            Some(HandleType::BottomLeft) => {
-                                current.min.x = mouse_pos.x;
-                                current.max.y = mouse_pos.y;
+                            Some(HandleType::Rotate) => {
+                                // The handle starts straight above the center.
+                                let to_mouse = mouse_pos - current.center();
+                                if to_mouse.length() > 0.0 {
+                                    self.rotation = to_mouse.angle() + std::f32::consts::FRAC_PI_2;
+                                }
                             }
-                            Some(HandleType::BottomRight) => {
-                                current.max = mouse_pos;
+                            Some(_) => {
+                                if let Some(anchor) = self.anchor {
+                                    // Scale in the rect's own frame so the
+                                    // opposite corner stays where it is.
+                                    let rot = Rot2::from_angle(self.rotation);
+                                    let mut diagonal = rot.inverse() * (mouse_pos - anchor);
+                                    if input.shift || self.lock_aspect {
+                                        diagonal = self.keep_aspect(diagonal);
+                                    }
+                                    let center = anchor + rot * diagonal / 2.0;
+                                    current = Rect::from_center_size(center, diagonal.abs());
+                                }
                             }
-                            _ => {}
+                            None => {}
                         }
                         self.current_rect = Some(current);
                     }
@@ -229,31 +460,66 @@ impl Tool for TransformTool {
             } else {
                 self.is_dragging = false;
                 self.handle_drag = None;
+                self.anchor = None;
             }
         }
 
         None
     }
 
-    fn get_temp_layer(&self) -> Option<(&RgbaImage, u32, u32)> {
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
         None
     }
 
-    fn draw_cursor(&self, _ui: &mut Ui, painter: &Painter, _settings: &ToolSettings, _pos: Pos2) {
-        if let Some(current) = self.current_rect {
-            painter.rect_stroke(current, 0.0, egui::Stroke::new(1.0, Color32::WHITE));
-            let handle_color = Color32::WHITE;
-            painter.circle_filled(current.left_top(), 4.0, handle_color);
-            painter.circle_filled(current.right_top(), 4.0, handle_color);
-            painter.circle_filled(current.left_bottom(), 4.0, handle_color);
-            painter.circle_filled(current.right_bottom(), 4.0, handle_color);
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        _settings: &ToolSettings,
+        pos: Pos2,
+        _view: &ViewTransform,
+    ) {
+        if self.current_rect.is_some() && self.rotation != 0.0 {
+            painter.text(
+                pos + Vec2::new(14.0, 14.0),
+                egui::Align2::LEFT_TOP,
+                format!("{:.1}°", self.rotation.to_degrees()),
+                egui::FontId::proportional(13.0),
+                Color32::WHITE,
+            );
+        }
+    }
+
+    fn draw_overlay(&self, painter: &Painter, view: &ViewTransform) {
+        let Some(current) = self.current_rect else {
+            return;
+        };
+        let stroke = egui::Stroke::new(1.0, Color32::WHITE);
+        let corners = [
+            current.left_top(),
+            current.right_top(),
+            current.right_bottom(),
+            current.left_bottom(),
+        ]
+        .map(|c| view.screen_pos(self.to_image(current, c)));
+        painter.add(egui::Shape::closed_line(corners.to_vec(), stroke));
+        for corner in corners {
+            painter.circle_filled(corner, 4.0, Color32::WHITE);
         }
+
+        let top = view.screen_pos(self.to_image(current, current.center_top()));
+        let handle = view.screen_pos(self.rotate_handle(current, ROTATE_HANDLE_OFFSET / view.zoom));
+        painter.line_segment([top, handle], stroke);
+        painter.circle_stroke(handle, 5.0, egui::Stroke::new(1.5, Color32::WHITE));
     }
 
     fn configure(&mut self, ui: &mut Ui, _settings: &mut ToolSettings) {
         ui.vertical(|ui| {
+            ui.checkbox(&mut self.snap_to_pixels, "Snap to Pixels")
+                .on_hover_text("Place the result on whole pixels");
             if self.floating_buffer.is_some() {
                 ui.label("Transforming selection...");
+                self.numeric_ui(ui);
                 if ui.button("Confirm").clicked() {
                     self.committed = true;
                 }
@@ -262,4 +528,100 @@ impl Tool for TransformTool {
             }
         });
     }
+
+    fn status(&self) -> Option<String> {
+        Some(if self.floating_buffer.is_some() {
+            "Drag to move, corners to scale (Shift keeps proportions), the top handle to rotate. Confirm to apply."
+                .to_string()
+        } else {
+            "Pick up a selection first.".to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    #[test]
+    fn empty_selection_lifts_nothing() {
+        let mut image = ImageStore::new(8, 8);
+        image.selection = Some(GrayImage::new(8, 8));
+        let mut tool = TransformTool::new();
+        let input = ToolInput::pressed_at(Pos2::new(4.0, 4.0));
+
+        for _ in 0..2 {
+            let command = tool.update(&mut image, &ToolSettings::default(), &input, BLACK);
+            assert!(command.is_none());
+            assert!(tool.floating_buffer.is_none());
+            assert!(tool.current_rect.is_none());
+        }
+    }
+
+    #[test]
+    fn snapped_commit_lands_on_whole_pixels() {
+        let red = Rgba([255, 0, 0, 255]);
+        let mut image = ImageStore::with_fill(8, 8, None);
+        let mut mask = GrayImage::new(8, 8);
+        if let crate::layers::LayerData::Raster(layer) = &mut image.layers[0].data {
+            for (x, y) in [(1, 1), (2, 1), (1, 2), (2, 2)] {
+                layer.put_pixel(x, y, red);
+                mask.put_pixel(x, y, image::Luma([255]));
+            }
+        }
+        image.selection = Some(mask);
+        let mut tool = TransformTool::new();
+        let input = ToolInput::pressed_at(Pos2::ZERO);
+
+        tool.update(&mut image, &ToolSettings::default(), &input, BLACK);
+        tool.current_rect = Some(Rect::from_min_size(
+            Pos2::new(-0.6, 2.6),
+            Vec2::new(2.2, 1.8),
+        ));
+        tool.committed = true;
+        let command = tool.update(&mut image, &ToolSettings::default(), &input, BLACK);
+        assert!(command.is_some());
+
+        // The rect snaps down to (-1, 2) with a 2×2 size, so only its right
+        // column lands on the canvas, on whole pixels, and nothing is left
+        // at the old spot.
+        let out = image.render_transparent();
+        for (x, y, p) in out.enumerate_pixels() {
+            let inside = x == 0 && (2..4).contains(&y);
+            assert_eq!(
+                *p,
+                if inside { red } else { Rgba([0, 0, 0, 0]) },
+                "({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn zero_size_target_restores_the_layer() {
+        let mut image = ImageStore::new(8, 8);
+        let mut mask = GrayImage::new(8, 8);
+        mask.put_pixel(2, 2, image::Luma([255]));
+        image.selection = Some(mask);
+        let before = image.render_transparent();
+        let mut tool = TransformTool::new();
+        let input = ToolInput::pressed_at(Pos2::ZERO);
+
+        tool.update(&mut image, &ToolSettings::default(), &input, BLACK);
+        assert!(tool.floating_buffer.is_some());
+        tool.current_rect = Some(Rect::from_min_size(
+            Pos2::new(2.0, 2.0),
+            Vec2::new(0.0, 1.0),
+        ));
+        tool.committed = true;
+
+        let command = tool.update(&mut image, &ToolSettings::default(), &input, BLACK);
+        assert!(command.is_none());
+        assert!(tool.floating_buffer.is_none());
+        assert_eq!(image.render_transparent(), before);
+    }
 }
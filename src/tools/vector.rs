@@ -0,0 +1,195 @@
+use crate::commands::{AddShapeCommand, Command};
+use crate::image_store::ImageStore;
+use crate::layers::{LayerData, VectorShape};
+use crate::state::ToolSettings;
+use crate::tools::base::{drag_rect, snap_angle};
+use crate::tools::{Tool, ToolInput, ViewTransform};
+use egui::{Color32, Painter, Pos2, Rect, Ui};
+use image::{ImageBuffer, Rgba, RgbaImage};
+
+/// Which shape the vector tool adds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VectorShapeKind {
+    Line,
+    Rectangle,
+    Ellipse,
+}
+
+/// Drags out a line, rectangle or ellipse and appends it to the active
+/// vector layer as an editable shape instead of pixels.
+pub struct VectorShapeTool {
+    kind: VectorShapeKind,
+    /// Preview of the shape being dragged, cleared between frames over
+    /// `dirty_rect`.
+    layer: RgbaImage,
+    start_pos: Option<Pos2>,
+    shape: Option<VectorShape>,
+    dirty_rect: Option<Rect>,
+    /// Whether the active layer could take shapes, as of the last update.
+    on_vector_layer: bool,
+}
+
+impl VectorShapeTool {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            kind: VectorShapeKind::Line,
+            layer: ImageBuffer::new(width, height),
+            start_pos: None,
+            shape: None,
+            dirty_rect: None,
+            on_vector_layer: true,
+        }
+    }
+
+    /// The shape dragged from `start` to `end`. Shift snaps lines to 45°
+    /// and makes squares and circles; Alt draws from the center out.
+    fn build(
+        &self,
+        (start, end): (Pos2, Pos2),
+        input: &ToolInput,
+        color: Rgba<u8>,
+        settings: &ToolSettings,
+    ) -> VectorShape {
+        let (width, fill) = (settings.line_width, settings.shape_fill);
+        let (from, to) = drag_rect(start, end, input.shift, input.alt);
+        let rect = Rect::from_two_pos(from, to);
+        match self.kind {
+            VectorShapeKind::Line => VectorShape::Line {
+                start,
+                end: if input.shift {
+                    snap_angle(start, end, 45.0)
+                } else {
+                    end
+                },
+                color,
+                width,
+            },
+            VectorShapeKind::Rectangle => VectorShape::Rectangle {
+                rect,
+                color,
+                width,
+                fill,
+            },
+            VectorShapeKind::Ellipse => VectorShape::Ellipse {
+                rect,
+                color,
+                width,
+                fill,
+            },
+        }
+    }
+
+    /// Clears the last preview and draws `shape` in its place.
+    fn redraw_preview(&mut self, shape: &VectorShape) {
+        self.clear_preview();
+        shape.rasterize(&mut self.layer);
+        let canvas = Rect::from_min_size(
+            Pos2::ZERO,
+            egui::vec2(self.layer.width() as f32, self.layer.height() as f32),
+        );
+        let area = shape.bounds().intersect(canvas);
+        self.dirty_rect = Some(Rect::from_min_max(area.min.floor(), area.max.ceil()));
+    }
+
+    fn clear_preview(&mut self) {
+        if let Some(rect) = self.dirty_rect.take() {
+            let (x0, y0) = (rect.min.x as u32, rect.min.y as u32);
+            for y in y0..rect.max.y as u32 {
+                for x in x0..rect.max.x as u32 {
+                    self.layer.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                }
+            }
+        }
+    }
+}
+
+impl Tool for VectorShapeTool {
+    fn name(&self) -> &str {
+        "Vector Shape"
+    }
+
+    fn update(
+        &mut self,
+        image: &mut ImageStore,
+        settings: &ToolSettings,
+        input: &ToolInput,
+        color: Rgba<u8>,
+    ) -> Option<Box<dyn Command>> {
+        if self.layer.width() != image.width() || self.layer.height() != image.height() {
+            self.layer = ImageBuffer::new(image.width(), image.height());
+            self.dirty_rect = None;
+        }
+        self.on_vector_layer = matches!(
+            image.active_layer().map(|layer| &layer.data),
+            Some(LayerData::Vector(_))
+        );
+        if !self.on_vector_layer || image.active_layer_is_locked() {
+            return None;
+        }
+
+        if input.is_pressed {
+            if self.start_pos.is_none() {
+                self.start_pos = input.pos;
+            }
+            if let (Some(start), Some(pos)) = (self.start_pos, input.pos) {
+                let shape = self.build((start, pos), input, color, settings);
+                self.redraw_preview(&shape);
+                self.shape = Some(shape);
+            }
+        }
+
+        if input.is_released {
+            self.start_pos = None;
+            self.clear_preview();
+            let shape = self.shape.take()?;
+            let layer_index = image.active_index();
+            let command = AddShapeCommand { layer_index, shape };
+            command.redo(image);
+            image.mark_dirty();
+            return Some(Box::new(command));
+        }
+        None
+    }
+
+    fn get_temp_layer(&self) -> Option<(&RgbaImage, Rect)> {
+        self.dirty_rect.map(|rect| (&self.layer, rect))
+    }
+
+    fn draw_cursor(
+        &self,
+        _ui: &mut Ui,
+        painter: &Painter,
+        settings: &ToolSettings,
+        pos: Pos2,
+        view: &ViewTransform,
+    ) {
+        painter.circle_stroke(
+            pos,
+            settings.line_width * view.zoom,
+            egui::Stroke::new(1.0, Color32::WHITE),
+        );
+    }
+
+    fn cancel(&mut self) {
+        self.start_pos = None;
+        self.shape = None;
+        self.clear_preview();
+    }
+
+    fn status(&self) -> Option<String> {
+        (!self.on_vector_layer).then(|| "Select or add a vector layer to draw shapes".to_string())
+    }
+
+    fn configure(&mut self, ui: &mut Ui, settings: &mut ToolSettings) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.kind, VectorShapeKind::Line, "Line");
+            ui.selectable_value(&mut self.kind, VectorShapeKind::Rectangle, "Rect");
+            ui.selectable_value(&mut self.kind, VectorShapeKind::Ellipse, "Ellipse");
+            ui.label("Width:");
+            ui.add(egui::DragValue::new(&mut settings.line_width).range(1.0..=20.0));
+            if self.kind != VectorShapeKind::Line {
+                ui.checkbox(&mut settings.shape_fill, "Fill");
+            }
+        });
+    }
+}
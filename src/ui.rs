@@ -1,22 +1,172 @@
+use crate::adjustments::CubeLut;
+use crate::commands::{Command, DocumentCommand};
+use crate::config::Config;
+use crate::export::{ExportFormat, ExportSettings};
+use crate::image_store::ImageStore;
+use crate::jobs::Job;
 use crate::layers::Layer;
-use crate::state::AppState;
+use crate::mask_ops::RefineParams;
+use crate::project::SavedTool;
+use crate::state::{AppState, Preferences, SelectionStyle};
 use crate::tools::ToolInput;
 use eframe::egui::{
     self, Color32, Context, PointerButton, Pos2, Rect, Sense, TextureOptions, Ui, Vec2,
 };
 use eframe::Frame;
-use image::Rgba;
+use image::imageops::FilterType;
+use image::{GenericImageView, Rgba};
+
+/// Applied on the UI thread once a background job finishes.
+type JobCompletion = Box<dyn FnOnce(&mut ArsApp) + Send>;
+
+/// What an Open job produced: a ready document, or the frames of an animated
+/// file waiting on the import prompt.
+enum Opened {
+    Document(ImageStore),
+    Frames(Vec<image::RgbaImage>),
+}
+
+/// Image Size dialog fields while it is open.
+struct ImageSizeDialog {
+    width: u32,
+    height: u32,
+    keep_aspect: bool,
+    filter: FilterType,
+}
+
+/// New document dialog fields while it is open.
+struct NewDocumentDialog {
+    width: u32,
+    height: u32,
+    background: NewBackground,
+    /// Fill for `NewBackground::Color`.
+    color: [u8; 3],
+    dpi: u16,
+}
+
+/// What the first layer of a new document starts as.
+#[derive(Clone, Copy, PartialEq)]
+enum NewBackground {
+    Transparent,
+    White,
+    Color,
+}
+
+/// A palette change made on a swatch: dragged onto another, picked from its
+/// context menu or Ctrl+clicked.
+enum PaletteEdit {
+    /// Takes the swatch out of the first index and inserts it at the second.
+    Move(usize, usize),
+    Remove(usize),
+}
+
+/// Resampling filters offered by the Image Size dialog.
+const SCALE_FILTERS: [(FilterType, &str); 5] = [
+    (FilterType::Nearest, "Nearest (pixel art)"),
+    (FilterType::Triangle, "Bilinear"),
+    (FilterType::CatmullRom, "Bicubic"),
+    (FilterType::Gaussian, "Gaussian"),
+    (FilterType::Lanczos3, "Lanczos"),
+];
+
+/// The whole pixels `rect` touches within an image of `size`, as `(x, y,
+/// width, height)`, or `None` if there are none.
+fn pixel_region(rect: Rect, (width, height): (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+    let x0 = (rect.min.x.floor().max(0.0) as u32).min(width);
+    let y0 = (rect.min.y.floor().max(0.0) as u32).min(height);
+    let x1 = (rect.max.x.ceil().max(0.0) as u32).min(width);
+    let y1 = (rect.max.y.ceil().max(0.0) as u32).min(height);
+    (x1 > x0 && y1 > y0).then(|| (x0, y0, x1 - x0, y1 - y0))
+}
+
+/// Builds a texture from straight-alpha RGBA. egui would premultiply in
+/// linear light, but the canvas composites and the renderer blends in
+/// gamma-encoded values, so premultiply in that space to show partly
+/// transparent pixels the way they will flatten and export.
+fn canvas_texture(size: [usize; 2], rgba: &[u8]) -> egui::ColorImage {
+    let pixels = rgba
+        .chunks_exact(4)
+        .map(|p| {
+            let [r, g, b, a] = crate::color::premultiply(Rgba([p[0], p[1], p[2], p[3]])).0;
+            Color32::from_rgba_premultiplied(r, g, b, a)
+        })
+        .collect();
+    egui::ColorImage { size, pixels }
+}
+
+/// What the selection overlay was last built from, so it is rebuilt only
+/// when that changes, and the selection outline traced for marching ants.
+struct SelectionOverlay {
+    mask: image::GrayImage,
+    style: SelectionStyle,
+    color: [u8; 3],
+    refining: bool,
+    /// Outline runs in image pixel corners; empty when no ants are shown.
+    edges: Vec<[(u32, u32); 2]>,
+}
 
 pub struct ArsApp {
     state: AppState,
     base_texture: Option<egui::TextureHandle>,
     layer_texture: Option<egui::TextureHandle>,
+    // Area of `layer_texture` holding the tool's paint, as last uploaded.
+    temp_rect: Option<Rect>,
     selection_texture: Option<egui::TextureHandle>,
+    selection_overlay: Option<SelectionOverlay>,
     zoom: f32,
     pan: Vec2,
-    image_dirty: bool,
+    // Size of the canvas area as last drawn, in points, for "Fit".
+    canvas_size: Vec2,
+    // Image pixel under the pointer, for the status bar.
+    cursor_pixel: Option<(u32, u32)>,
     show_shortcuts: bool,
+    show_adjustments: bool,
+    show_preferences: bool,
+    show_guides: bool,
+    show_color_picker: bool,
+    color_picker: crate::color_picker::ColorPicker,
+    guides: crate::guides::Guides,
+    lut: Option<std::sync::Arc<CubeLut>>,
+    brightness: f32,
+    contrast: f32,
+    sharpen_radius: f32,
+    sharpen_amount: f32,
+    // Brightness/contrast or sharpen currently applied to the layer for preview only.
+    // Undone before anything else touches the layer; see `guard_adjust_preview`.
+    adjust_preview: Option<crate::commands::PatchCommand>,
+    // Where the Adjustments window was last drawn, if it is open.
+    adjust_window: Option<egui::Rect>,
     remapping: Option<String>,
+    pending_job: Option<Job<JobCompletion>>,
+    resize_preview: Option<(u32, u32)>,
+    pending_resize: Option<(u32, u32, egui::Align2)>,
+    // Canvas Size dialog fields while it is open: width, height, anchor.
+    canvas_dialog: Option<(u32, u32, egui::Align2)>,
+    image_size_dialog: Option<ImageSizeDialog>,
+    new_document_dialog: Option<NewDocumentDialog>,
+    // Frames of an animated file waiting on the "import as layers?" prompt.
+    pending_frames: Option<Vec<image::RgbaImage>>,
+    // A pasted image larger than the canvas, waiting on "expand or clip?".
+    pending_paste: Option<image::RgbaImage>,
+    // Selection as it was when Refine Edge opened. While this is set,
+    // `image.selection` holds the refined preview.
+    refine_original: Option<image::GrayImage>,
+    refine: RefineParams,
+    config: Config,
+    // Row in the layers panel whose name is being edited.
+    editing_layer_name: Option<usize>,
+    show_export: bool,
+    export_settings: ExportSettings,
+    export_view_overlays: bool,
+    // The canvas view as last drawn, relative to the canvas area, with the
+    // area's size. Both are in physical pixels.
+    viewport: Option<(crate::tools::ViewTransform, Vec2)>,
+    // Radius for the Grow and Shrink buttons in the selection panel.
+    selection_radius: u32,
+    feather_radius: f32,
+    clipboard: crate::clipboard::Clipboard,
+    // Latest stylus pressure, held between frames while the pen is down.
+    pressure: f32,
 }
 
 impl ArsApp {
@@ -28,71 +178,807 @@ impl ArsApp {
         visuals.extreme_bg_color = Color32::from_rgb(22, 22, 30);
         cc.egui_ctx.set_visuals(visuals);
 
+        let config = Config::load();
+        let export_settings = config.last_export.clone().unwrap_or_default();
+
+        let mut state = AppState::new(800, 600);
+        state.preferences = config.preferences.clone();
+        state.command_stack.set_limit(state.preferences.undo_levels);
+        state.keybindings = config.keybindings.clone();
+
         Self {
-            state: AppState::new(800, 600),
+            state,
             base_texture: None,
             layer_texture: None,
+            temp_rect: None,
             selection_texture: None,
+            selection_overlay: None,
             zoom: 1.0,
             pan: Vec2::ZERO,
-            image_dirty: true,
+            canvas_size: Vec2::ZERO,
+            cursor_pixel: None,
             show_shortcuts: false,
+            show_adjustments: false,
+            show_preferences: false,
+            show_guides: false,
+            show_color_picker: false,
+            color_picker: crate::color_picker::ColorPicker::new(),
+            guides: crate::guides::Guides::new(),
+            lut: None,
+            brightness: 0.0,
+            contrast: 0.0,
+            sharpen_radius: 1.0,
+            sharpen_amount: 0.0,
+            adjust_preview: None,
+            adjust_window: None,
             remapping: None,
+            pending_job: None,
+            resize_preview: None,
+            pending_resize: None,
+            canvas_dialog: None,
+            image_size_dialog: None,
+            new_document_dialog: None,
+            pending_frames: None,
+            pending_paste: None,
+            refine_original: None,
+            refine: RefineParams::default(),
+            config,
+            editing_layer_name: None,
+            show_export: false,
+            export_settings,
+            export_view_overlays: false,
+            viewport: None,
+            selection_radius: 1,
+            feather_radius: 4.0,
+            clipboard: Default::default(),
+            pressure: 1.0,
         }
     }
 
-    fn update_textures(&mut self, ctx: &Context) {
-        // Update base texture from composite if dirty
-        // Note: image_store.get_composite() handles dirty checking internally for the buffer
-        let composite = self.state.image.get_composite();
+    /// Centers the image and zooms it to fit the canvas area.
+    fn fit_view(&mut self) {
+        let image_size = Vec2::new(
+            self.state.image.width() as f32,
+            self.state.image.height() as f32,
+        );
+        if self.canvas_size.x > 0.0 && self.canvas_size.y > 0.0 {
+            let fit = self.canvas_size / image_size;
+            self.zoom = fit.x.min(fit.y).clamp(0.1, 50.0);
+        }
+        self.pan = Vec2::ZERO;
+    }
+
+    /// Tracks pen pressure from this frame's touch events. A mouse sends none,
+    /// so the pressure stays at 1.0.
+    fn update_pressure(&mut self, ctx: &egui::Context) {
+        ctx.input(|i| {
+            for event in &i.events {
+                if let egui::Event::Touch { phase, force, .. } = event {
+                    self.pressure = match phase {
+                        egui::TouchPhase::Start | egui::TouchPhase::Move => force.unwrap_or(1.0),
+                        egui::TouchPhase::End | egui::TouchPhase::Cancel => 1.0,
+                    };
+                }
+            }
+        });
+    }
+
+    /// Resizes the canvas, asking for confirmation first if the new buffers
+    /// would be larger than `Preferences::resize_warn_bytes`.
+    fn request_resize(&mut self, width: u32, height: u32, anchor: egui::Align2) {
+        if (width, height) == (self.state.image.width(), self.state.image.height()) {
+            return;
+        }
+        if Self::resize_bytes(&self.state, width, height) > self.state.preferences.resize_warn_bytes
+        {
+            self.pending_resize = Some((width, height, anchor));
+        } else {
+            self.apply_resize(width, height, anchor);
+        }
+    }
+
+    fn resize_bytes(state: &AppState, width: u32, height: u32) -> u64 {
+        width as u64 * height as u64 * 4 * state.image.layer_count() as u64
+    }
+
+    fn apply_resize(&mut self, width: u32, height: u32, anchor: egui::Align2) {
+        let before = self.state.image.snapshot();
+        self.state.image.resize_canvas(width, height, anchor);
+        let after = self.state.image.snapshot();
+        self.state
+            .command_stack
+            .push(Box::new(crate::commands::DocumentCommand {
+                name: "Resize Canvas".to_string(),
+                before,
+                after,
+            }));
+    }
+
+    fn apply_scale(&mut self, width: u32, height: u32, filter: FilterType) {
+        if (width, height) == (self.state.image.width(), self.state.image.height()) {
+            return;
+        }
+        self.run_edit_job("Scaling", move |doc| {
+            Some(DocumentCommand::record("Scale Image", doc, |doc| {
+                doc.scale_image(width, height, filter)
+            }))
+        });
+    }
+
+    /// Replaces the document. A project's saved tool and its settings become
+    /// the active ones.
+    fn open_document(&mut self, store: ImageStore, tool: Option<SavedTool>) {
+        let (width, height) = (store.width(), store.height());
+        // An adjustment preview holds the old document's pixels; restoring
+        // them into the new one would corrupt it, so just let it go.
+        self.adjust_preview = None;
+        // Likewise the selection a Refine Edge dialog would put back on cancel.
+        self.refine_original = None;
+        self.state.set_image(store);
+        if let Some(saved) = tool {
+            if let Some(active) = crate::tools::create_tool(&saved.name, width, height) {
+                self.state.active_tool = active;
+            }
+            self.state.tool_settings = saved.settings;
+        }
+        self.base_texture = None;
+    }
+
+    fn render_frames_prompt(&mut self, ctx: &Context) {
+        let Some(count) = self.pending_frames.as_ref().map(|f| f.len()) else {
+            return;
+        };
+        egui::Window::new("Animated Image")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "This file has {} frames. Import each frame as a layer?",
+                    count
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Import as Layers").clicked() {
+                        if let Some(frames) = self.pending_frames.take() {
+                            self.open_document(ImageStore::from_frames(frames), None);
+                        }
+                    }
+                    if ui.button("First Frame Only").clicked() {
+                        if let Some(mut frames) = self.pending_frames.take() {
+                            frames.truncate(1);
+                            self.open_document(ImageStore::from_frames(frames), None);
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.pending_frames = None;
+                    }
+                });
+            });
+    }
 
-        // We still need to upload to GPU if changed
-        // Use a simple checksum or just the image_dirty flag from AppState?
-        // AppState doesn't track dirty, ImageStore does.
-        // But ImageStore.get_composite() returns ref.
-        // We need a way to know if we need to call load_texture.
-        // Let's rely on self.image_dirty which we set when tools commit.
-
-        if self.image_dirty || self.base_texture.is_none() {
-            let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                [composite.width() as usize, composite.height() as usize],
-                composite.as_raw(),
+    /// Opens an image or `.ars` project in the background, replacing the
+    /// document once it has loaded.
+    fn open_path(&mut self, path: std::path::PathBuf) {
+        self.run_job("Opening", move || {
+            if ImageStore::is_project_path(&path) {
+                let result = ImageStore::load_project(&path);
+                return Box::new(move |app: &mut ArsApp| match result {
+                    Ok((store, tool)) => app.open_document(store, tool),
+                    Err(e) => log::error!("Failed to open project: {:#}", e),
+                });
+            }
+            let frames = match ImageStore::decode_frames(&path) {
+                Ok(frames) => frames,
+                Err(e) => {
+                    log::warn!("Failed to decode frames, opening flat: {}", e);
+                    None
+                }
+            };
+            let result = match frames {
+                Some(frames) => Ok(Opened::Frames(frames)),
+                None => ImageStore::from_file(&path).map(Opened::Document),
+            };
+            Box::new(move |app: &mut ArsApp| match result {
+                Ok(Opened::Document(store)) => app.open_document(store, None),
+                Ok(Opened::Frames(frames)) => app.pending_frames = Some(frames),
+                Err(e) => log::error!("Failed to open: {}", e),
+            })
+        });
+    }
+
+    /// Opens the first dropped file that looks like an image or project, and
+    /// highlights the window while files are dragged over it.
+    fn handle_dropped_files(&mut self, ctx: &Context) {
+        const EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "gif", "ars"];
+        let is_openable = |path: &std::path::Path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        };
+
+        let (hovering, dropped) = ctx.input(|i| {
+            let hovering = i
+                .raw
+                .hovered_files
+                .iter()
+                .any(|f| f.path.as_deref().is_none_or(is_openable));
+            let dropped = i
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|f| f.path.clone())
+                .find(|p| is_openable(p));
+            (hovering, dropped)
+        });
+
+        if hovering {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("drop_highlight"),
+            ));
+            let screen = ctx.screen_rect();
+            painter.rect_filled(screen, 0.0, Color32::from_black_alpha(140));
+            painter.rect_stroke(
+                screen.shrink(4.0),
+                8.0,
+                egui::Stroke::new(3.0, Color32::from_rgb(122, 162, 247)),
+            );
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop to open",
+                egui::FontId::proportional(28.0),
+                Color32::WHITE,
             );
+        }
+        if let Some(path) = dropped {
+            self.open_path(path);
+        }
+    }
 
-            self.base_texture =
-                Some(ctx.load_texture("base_image", color_image, TextureOptions::NEAREST));
-            self.image_dirty = false;
+    fn render_paste_prompt(&mut self, ctx: &Context) {
+        let Some((width, height)) = self.pending_paste.as_ref().map(|p| p.dimensions()) else {
+            return;
+        };
+        egui::Window::new("Paste")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The pasted image is {}×{}, larger than the {}×{} canvas.",
+                    width,
+                    height,
+                    self.state.image.width(),
+                    self.state.image.height()
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Expand Canvas").clicked() {
+                        if let Some(pixels) = self.pending_paste.take() {
+                            let w = self.state.image.width().max(width);
+                            let h = self.state.image.height().max(height);
+                            self.apply_resize(w, h, egui::Align2::LEFT_TOP);
+                            self.paste_as_layer(&pixels);
+                        }
+                    }
+                    if ui.button("Clip to Canvas").clicked() {
+                        if let Some(pixels) = self.pending_paste.take() {
+                            self.paste_as_layer(&pixels);
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.pending_paste = None;
+                    }
+                });
+            });
+    }
+
+    /// The Canvas Size dialog: a new width and height plus which edge or
+    /// corner the existing pixels stay against.
+    fn render_canvas_dialog(&mut self, ctx: &Context) {
+        let Some((mut width, mut height, mut anchor)) = self.canvas_dialog else {
+            return;
+        };
+        let mut open = true;
+        let mut apply = false;
+        let mut cancel = false;
+        egui::Window::new("Canvas Size")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Current: {}×{}",
+                    self.state.image.width(),
+                    self.state.image.height()
+                ));
+                ui.horizontal(|ui| {
+                    ui.label("Width:");
+                    ui.add(
+                        egui::DragValue::new(&mut width)
+                            .range(1..=16384)
+                            .suffix(" px"),
+                    );
+                    ui.label("Height:");
+                    ui.add(
+                        egui::DragValue::new(&mut height)
+                            .range(1..=16384)
+                            .suffix(" px"),
+                    );
+                });
+                ui.label("Anchor:");
+                egui::Grid::new("canvas_anchor").show(ui, |ui| {
+                    use egui::Align2;
+                    let rows = [
+                        [Align2::LEFT_TOP, Align2::CENTER_TOP, Align2::RIGHT_TOP],
+                        [
+                            Align2::LEFT_CENTER,
+                            Align2::CENTER_CENTER,
+                            Align2::RIGHT_CENTER,
+                        ],
+                        [
+                            Align2::LEFT_BOTTOM,
+                            Align2::CENTER_BOTTOM,
+                            Align2::RIGHT_BOTTOM,
+                        ],
+                    ];
+                    for row in rows {
+                        for cell in row {
+                            let mark = if cell == anchor { "●" } else { "○" };
+                            if ui.selectable_label(cell == anchor, mark).clicked() {
+                                anchor = cell;
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    apply = ui.button("Apply").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        self.canvas_dialog = (open && !apply && !cancel).then_some((width, height, anchor));
+        if apply {
+            self.request_resize(width, height, anchor);
         }
+    }
 
-        // Update layer texture from tool
-        if let Some((layer, _x, _y)) = self.state.active_tool.get_temp_layer() {
-            let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                [layer.width() as usize, layer.height() as usize],
-                layer.as_raw(),
+    /// The Image Size dialog: resamples the content to a new size.
+    fn render_image_size_dialog(&mut self, ctx: &Context) {
+        let Some(mut dialog) = self.image_size_dialog.take() else {
+            return;
+        };
+        let (old_width, old_height) = (self.state.image.width(), self.state.image.height());
+        let mut open = true;
+        let mut apply = false;
+        let mut cancel = false;
+        egui::Window::new("Image Size")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Current: {}×{}", old_width, old_height));
+                ui.horizontal(|ui| {
+                    ui.label("Width:");
+                    let width = ui.add(
+                        egui::DragValue::new(&mut dialog.width)
+                            .range(1..=16384)
+                            .suffix(" px"),
+                    );
+                    ui.label("Height:");
+                    let height = ui.add(
+                        egui::DragValue::new(&mut dialog.height)
+                            .range(1..=16384)
+                            .suffix(" px"),
+                    );
+                    if dialog.keep_aspect {
+                        if width.changed() {
+                            dialog.height = ((dialog.width as f32 * old_height as f32
+                                / old_width as f32)
+                                .round() as u32)
+                                .clamp(1, 16384);
+                        } else if height.changed() {
+                            dialog.width = ((dialog.height as f32 * old_width as f32
+                                / old_height as f32)
+                                .round() as u32)
+                                .clamp(1, 16384);
+                        }
+                    }
+                });
+                ui.checkbox(&mut dialog.keep_aspect, "Maintain Aspect");
+                let current = SCALE_FILTERS
+                    .iter()
+                    .find(|(filter, _)| *filter == dialog.filter)
+                    .map_or("", |(_, name)| name);
+                egui::ComboBox::from_label("Resample")
+                    .selected_text(current)
+                    .show_ui(ui, |ui| {
+                        for (filter, name) in SCALE_FILTERS {
+                            ui.selectable_value(&mut dialog.filter, filter, name);
+                        }
+                    });
+                let bytes = Self::resize_bytes(&self.state, dialog.width, dialog.height);
+                if bytes > self.state.preferences.resize_warn_bytes {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!("Will use about {} MB", bytes / (1024 * 1024)),
+                    );
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    apply = ui.button("Apply").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        if apply {
+            self.apply_scale(dialog.width, dialog.height, dialog.filter);
+        } else if open && !cancel {
+            self.image_size_dialog = Some(dialog);
+        }
+    }
+
+    fn render_new_document_dialog(&mut self, ctx: &Context) {
+        let Some(mut dialog) = self.new_document_dialog.take() else {
+            return;
+        };
+        let mut open = true;
+        let mut create = false;
+        let mut cancel = false;
+        egui::Window::new("New Document")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("new_document_grid").show(ui, |ui| {
+                    ui.label("Width:");
+                    ui.add(
+                        egui::DragValue::new(&mut dialog.width)
+                            .range(1..=16384)
+                            .suffix(" px"),
+                    );
+                    ui.end_row();
+                    ui.label("Height:");
+                    ui.add(
+                        egui::DragValue::new(&mut dialog.height)
+                            .range(1..=16384)
+                            .suffix(" px"),
+                    );
+                    ui.end_row();
+                    ui.label("Resolution:");
+                    ui.add(
+                        egui::DragValue::new(&mut dialog.dpi)
+                            .range(1..=2400)
+                            .suffix(" DPI"),
+                    )
+                    .on_hover_text("Written into exports of the new document");
+                    ui.end_row();
+                    ui.label("Background:");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut dialog.background,
+                            NewBackground::Transparent,
+                            "Transparent",
+                        );
+                        ui.selectable_value(&mut dialog.background, NewBackground::White, "White");
+                        ui.selectable_value(&mut dialog.background, NewBackground::Color, "Color");
+                        if dialog.background == NewBackground::Color {
+                            ui.color_edit_button_srgb(&mut dialog.color);
+                        }
+                    });
+                    ui.end_row();
+                });
+                let modified = self.state.command_stack.is_modified();
+                if modified {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        "The current document has unsaved changes, which will be lost.",
+                    );
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let label = if modified {
+                        "Discard Changes and Create"
+                    } else {
+                        "Create"
+                    };
+                    create = ui.button(label).clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        if create {
+            let [r, g, b] = dialog.color;
+            let fill = match dialog.background {
+                NewBackground::Transparent => None,
+                NewBackground::White => Some(Rgba([255, 255, 255, 255])),
+                NewBackground::Color => Some(Rgba([r, g, b, 255])),
+            };
+            self.open_document(
+                ImageStore::with_fill(dialog.width, dialog.height, fill),
+                None,
             );
-            self.layer_texture =
-                Some(ctx.load_texture("temp_layer", color_image, TextureOptions::NEAREST));
-        } else {
-            self.layer_texture = None;
+            self.export_settings.dpi = dialog.dpi;
+        } else if open && !cancel {
+            self.new_document_dialog = Some(dialog);
+        }
+    }
+
+    fn render_resize_confirm(&mut self, ctx: &Context) {
+        let Some((width, height, anchor)) = self.pending_resize else {
+            return;
+        };
+        let megabytes = Self::resize_bytes(&self.state, width, height) / (1024 * 1024);
+        egui::Window::new("Large Canvas")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Resizing to {}×{} will use about {} MB. Continue?",
+                    width, height, megabytes
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Resize").clicked() {
+                        self.pending_resize = None;
+                        self.apply_resize(width, height, anchor);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.pending_resize = None;
+                    }
+                });
+            });
+    }
+
+    /// Runs `work` off the UI thread behind a "Working…" overlay. Only one job
+    /// runs at a time; requests made while busy are ignored.
+    fn run_job(&mut self, label: &str, work: impl FnOnce() -> JobCompletion + Send + 'static) {
+        if self.pending_job.is_some() {
+            log::warn!("Ignoring '{}' while another operation is running", label);
+            return;
+        }
+        self.pending_job = Some(Job::spawn(label, work));
+    }
+
+    /// Runs `edit` on a copy of the document in the background, then applies
+    /// the undo step it returns to the real one. Any adjustment preview is
+    /// dropped first so it isn't baked into the copy.
+    fn run_edit_job<C: Command + Send + 'static>(
+        &mut self,
+        label: &str,
+        edit: impl FnOnce(&mut ImageStore) -> Option<C> + Send + 'static,
+    ) {
+        self.clear_adjust_preview();
+        let mut doc = self.state.image.clone();
+        self.run_job(label, move || {
+            let command = edit(&mut doc);
+            Box::new(move |app: &mut ArsApp| {
+                if let Some(command) = command {
+                    app.state
+                        .command_stack
+                        .execute(Box::new(command), &mut app.state.image);
+                }
+            })
+        });
+    }
+
+    fn poll_job(&mut self, ctx: &Context) {
+        let Some(job) = &self.pending_job else {
+            return;
+        };
+        match job.poll() {
+            Some(Ok(complete)) => {
+                self.pending_job = None;
+                complete(self);
+            }
+            Some(Err(())) => {
+                log::error!("'{}' failed unexpectedly", job.label());
+                self.pending_job = None;
+            }
+            None => ctx.request_repaint(),
+        }
+    }
+
+    fn render_busy_overlay(&self, ctx: &Context) {
+        let Some(job) = &self.pending_job else {
+            return;
+        };
+        let screen = ctx.screen_rect();
+        egui::Area::new(egui::Id::new("busy_overlay"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(screen.min)
+            .show(ctx, |ui| {
+                // Swallow pointer input so nothing underneath reacts while busy.
+                ui.allocate_rect(screen, Sense::click_and_drag());
+                ui.painter()
+                    .rect_filled(screen, 0.0, Color32::from_black_alpha(140));
+                ui.put(
+                    Rect::from_center_size(screen.center(), Vec2::new(220.0, 40.0)),
+                    |ui: &mut Ui| {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label(format!("Working… {}", job.label()));
+                        })
+                        .response
+                    },
+                );
+            });
+    }
+
+    fn update_textures(&mut self, ctx: &Context) {
+        // Recomposite whatever is stale, then upload only the part of the
+        // composite that actually changed.
+        self.state.image.composite();
+        let changed = self.state.image.take_composite_changes();
+        let composite = self.state.image.get_composite();
+        let size = [composite.width() as usize, composite.height() as usize];
+        match &mut self.base_texture {
+            Some(texture) if texture.size() == size => {
+                if let Some((x, y, w, h)) = changed {
+                    let region = composite.view(x, y, w, h).to_image();
+                    let color_image = canvas_texture([w as usize, h as usize], region.as_raw());
+                    texture.set_partial(
+                        [x as usize, y as usize],
+                        color_image,
+                        TextureOptions::NEAREST,
+                    );
+                }
+            }
+            _ => {
+                let color_image = canvas_texture(size, composite.as_raw());
+                self.base_texture =
+                    Some(ctx.load_texture("base_image", color_image, TextureOptions::NEAREST));
+            }
+        }
+
+        // The tool's stroke preview. Like the composite, only the area that
+        // holds paint now or did last frame is uploaded.
+        let temp = self.state.active_tool.get_temp_layer();
+        let stale = self.temp_rect.take();
+        match (temp, &mut self.layer_texture) {
+            (Some((layer, rect)), Some(texture))
+                if texture.size() == [layer.width() as usize, layer.height() as usize] =>
+            {
+                let area = stale.map_or(rect, |old| old.union(rect));
+                if let Some((x, y, w, h)) = pixel_region(area, layer.dimensions()) {
+                    let region = layer.view(x, y, w, h).to_image();
+                    let color_image = canvas_texture([w as usize, h as usize], region.as_raw());
+                    texture.set_partial(
+                        [x as usize, y as usize],
+                        color_image,
+                        TextureOptions::NEAREST,
+                    );
+                }
+                self.temp_rect = Some(rect);
+            }
+            (Some((layer, rect)), _) => {
+                let size = [layer.width() as usize, layer.height() as usize];
+                let color_image = canvas_texture(size, layer.as_raw());
+                self.layer_texture =
+                    Some(ctx.load_texture("temp_layer", color_image, TextureOptions::NEAREST));
+                self.temp_rect = Some(rect);
+            }
+            (None, Some(texture)) => {
+                // Keep the texture for the next stroke; just wipe what the
+                // last one left on it.
+                let size = (texture.size()[0] as u32, texture.size()[1] as u32);
+                if let Some((x, y, w, h)) = stale.and_then(|old| pixel_region(old, size)) {
+                    let clear =
+                        egui::ColorImage::new([w as usize, h as usize], Color32::TRANSPARENT);
+                    texture.set_partial([x as usize, y as usize], clear, TextureOptions::NEAREST);
+                }
+            }
+            (None, None) => {}
         }
 
         if let Some(mask) = &self.state.image.selection {
-            let mut rgba = Vec::with_capacity(mask.width() as usize * mask.height() as usize * 4);
-            for p in mask.pixels() {
-                rgba.push(0);
-                rgba.push(100);
-                rgba.push(255);
-                rgba.push(if p[0] > 0 { 50 } else { 0 });
-            }
-            let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                [mask.width() as usize, mask.height() as usize],
-                &rgba,
-            );
-            self.selection_texture =
-                Some(ctx.load_texture("selection_mask", color_image, TextureOptions::NEAREST));
+            let prefs = &self.state.preferences;
+            let refining = self.refine_original.is_some();
+            let current = self.selection_overlay.as_ref().is_some_and(|o| {
+                o.refining == refining
+                    && o.style == prefs.selection_style
+                    && o.color == prefs.selection_color
+                    && o.mask == *mask
+            });
+            if !current {
+                // Rebuilt only when the selection or how it is shown changes.
+                // Comparing the masks each frame is a plain memory compare,
+                // far cheaper than tracing and uploading them.
+                let rgba = if refining {
+                    // Red over what falls outside, fading with the feathered edge.
+                    Some(
+                        mask.pixels()
+                            .flat_map(|p| [255, 0, 0, ((255 - p[0]) as u32 * 128 / 255) as u8])
+                            .collect(),
+                    )
+                } else {
+                    Self::selection_tint(mask, prefs)
+                };
+                self.selection_texture = rgba.map(|rgba: Vec<u8>| {
+                    let size = [mask.width() as usize, mask.height() as usize];
+                    let color_image = canvas_texture(size, &rgba);
+                    ctx.load_texture("selection_mask", color_image, TextureOptions::NEAREST)
+                });
+                let ants = !refining && prefs.selection_style != SelectionStyle::Fill;
+                self.selection_overlay = Some(SelectionOverlay {
+                    mask: mask.clone(),
+                    style: prefs.selection_style,
+                    color: prefs.selection_color,
+                    refining,
+                    edges: if ants {
+                        crate::mask_ops::boundary(mask)
+                    } else {
+                        Vec::new()
+                    },
+                });
+            }
         } else {
             self.selection_texture = None;
+            self.selection_overlay = None;
+        }
+    }
+
+    /// Builds the translucent tint over the selected area in the user's
+    /// chosen style, or `None` for outline only.
+    fn selection_tint(mask: &image::GrayImage, prefs: &Preferences) -> Option<Vec<u8>> {
+        let fill_alpha = match prefs.selection_style {
+            SelectionStyle::Outline => return None,
+            SelectionStyle::Fill => 50,
+            SelectionStyle::Both => 25,
+        };
+        let [r, g, b] = prefs.selection_color;
+        Some(
+            mask.pixels()
+                .flat_map(|p| {
+                    if p[0] == 0 {
+                        [0, 0, 0, 0]
+                    } else {
+                        [r, g, b, fill_alpha]
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Draws the marching ants along the traced selection outline: the
+    /// overlay color in dashes over white, shifting with time.
+    fn draw_selection_ants(&self, ui: &Ui, painter: &egui::Painter, image_rect: Rect) {
+        let Some(overlay) = self
+            .selection_overlay
+            .as_ref()
+            .filter(|o| !o.edges.is_empty())
+        else {
+            return;
+        };
+        // Eight steps a second; keep repainting while ants are shown.
+        ui.ctx()
+            .request_repaint_after(std::time::Duration::from_millis(125));
+        let phase = (ui.input(|i| i.time) * 8.0).floor() as f32;
+        let [r, g, b] = overlay.color;
+        let white = egui::Stroke::new(1.0, Color32::WHITE);
+        let dashes = egui::Stroke::new(1.0, Color32::from_rgb(r, g, b));
+        let to_screen =
+            |(x, y): (u32, u32)| image_rect.min + Vec2::new(x as f32, y as f32) * self.zoom;
+
+        let mut shapes = Vec::with_capacity(overlay.edges.len() * 2);
+        for &[start, end] in &overlay.edges {
+            let line = [to_screen(start), to_screen(end)];
+            shapes.push(egui::Shape::line_segment(line, white));
+            // Offsetting by position lines the dashes up diagonally across
+            // separate runs, as one pattern marching along.
+            let offset = (phase - line[0].x - line[0].y).rem_euclid(8.0);
+            egui::Shape::dashed_line_many_with_offset(
+                &line,
+                dashes,
+                &[4.0],
+                &[4.0],
+                offset,
+                &mut shapes,
+            );
         }
+        painter.extend(shapes);
     }
 
     fn render_layers_panel(&mut self, ui: &mut Ui) {
@@ -100,24 +986,99 @@ impl ArsApp {
         ui.separator();
 
         if ui.button("Add Layer").clicked() {
-            let idx = self.state.image.layers.len() + 1;
+            let idx = self.state.image.layer_count() + 1;
             let layer = Layer::new_raster(
                 self.state.image.width(),
                 self.state.image.height(),
                 format!("Layer {}", idx),
             );
-            self.state.image.add_layer(layer);
-            self.image_dirty = true;
+            self.state.add_layer_above("Add Layer", layer);
+        }
+        if ui
+            .button("Add Tone")
+            .on_hover_text("A layer whose paint composites as screentone dots")
+            .clicked()
+        {
+            let idx = self.state.image.layer_count() + 1;
+            let layer = Layer::new_tone(
+                self.state.image.width(),
+                self.state.image.height(),
+                format!("Tone {}", idx),
+            );
+            self.state.add_layer_above("Add Tone Layer", layer);
+        }
+        if ui
+            .button("Add Vector")
+            .on_hover_text("A layer of editable shapes, drawn with the Vector tool")
+            .clicked()
+        {
+            let idx = self.state.image.layer_count() + 1;
+            let layer = Layer::new_vector(format!("Vector {}", idx));
+            self.state.add_layer_above("Add Vector Layer", layer);
+            self.state.active_tool = Box::new(crate::tools::VectorShapeTool::new(
+                self.state.image.width(),
+                self.state.image.height(),
+            ));
         }
+        if ui.button("Duplicate").clicked() {
+            self.state.duplicate_active_layer();
+        }
+        if ui
+            .add_enabled(
+                self.state.image.layer_count() > 1,
+                egui::Button::new("Delete Layer"),
+            )
+            .clicked()
+        {
+            // The preview patch points at a layer index that may shift.
+            self.clear_adjust_preview();
+            self.state.delete_active_layer();
+        }
+
+        ui.menu_button("Merge", |ui| {
+            let merge_visible = ui
+                .button("Merge Visible")
+                .on_hover_text("Combine the visible layers, keeping hidden ones")
+                .clicked();
+            let flatten = ui
+                .button("Flatten Image")
+                .on_hover_text("Reduce to one layer, discarding hidden layers")
+                .clicked();
+            if !merge_visible && !flatten {
+                return;
+            }
+            ui.close_menu();
+
+            let before = self.state.image.snapshot();
+            let name = if merge_visible {
+                if !self.state.image.merge_visible() {
+                    return;
+                }
+                "Merge Visible"
+            } else {
+                self.state.image.flatten();
+                "Flatten Image"
+            };
+            let after = self.state.image.snapshot();
+            self.state
+                .command_stack
+                .push(Box::new(crate::commands::DocumentCommand {
+                    name: name.to_string(),
+                    before,
+                    after,
+                }));
+        });
 
         ui.separator();
 
+        let mut reorder = None;
         egui::ScrollArea::vertical().show(ui, |ui| {
             // Iterate in reverse to show Top layer at Top of list
-            let indices: Vec<usize> = (0..self.state.image.layers.len()).rev().collect();
+            let count = self.state.image.layer_count();
+            let indices: Vec<usize> = (0..count).rev().collect();
 
             for idx in indices {
-                let is_active = idx == self.state.image.active_layer;
+                let is_active = idx == self.state.image.active_index();
 
                 ui.horizontal(|ui| {
                     // Visibility toggle
@@ -125,7 +1086,15 @@ impl ArsApp {
                     if ui.checkbox(&mut visible, "👁").changed() {
                         self.state.image.layers[idx].visible = visible;
                         self.state.image.mark_dirty();
-                        self.image_dirty = true;
+                    }
+
+                    let mut locked = self.state.image.layers[idx].locked;
+                    if ui
+                        .checkbox(&mut locked, "🔐")
+                        .on_hover_text("Lock Layer")
+                        .changed()
+                    {
+                        self.state.image.layers[idx].locked = locked;
                     }
 
                     let mut alpha_locked = self.state.image.layers[idx].alpha_locked;
@@ -137,79 +1106,906 @@ impl ArsApp {
                         self.state.image.layers[idx].alpha_locked = alpha_locked;
                     }
 
-                    let mut clipped = self.state.image.layers[idx].clipped;
+                    let mut clipped = self.state.image.layers[idx].clipped;
+                    if ui
+                        .checkbox(&mut clipped, "🖇")
+                        .on_hover_text("Clip to Layer Below")
+                        .changed()
+                    {
+                        self.state.image.layers[idx].clipped = clipped;
+                        self.state.image.mark_dirty();
+                    }
+
+                    if ui
+                        .add_enabled(idx + 1 < count, egui::Button::new("▲").small())
+                        .on_hover_text("Move Up")
+                        .clicked()
+                    {
+                        reorder = Some((idx, idx + 1));
+                    }
+                    if ui
+                        .add_enabled(idx > 0, egui::Button::new("▼").small())
+                        .on_hover_text("Move Down")
+                        .clicked()
+                    {
+                        reorder = Some((idx, idx - 1));
+                    }
+
+                    // Selection; double-click to rename in place.
+                    let name_id = ui.make_persistent_id(("layer_name", idx));
+                    if self.editing_layer_name == Some(idx) {
+                        let response = ui.add(
+                            egui::TextEdit::singleline(&mut self.state.image.layers[idx].name)
+                                .id(name_id)
+                                .desired_width(120.0),
+                        );
+                        if response.lost_focus() {
+                            self.editing_layer_name = None;
+                        }
+                    } else {
+                        let name = self.state.image.layers[idx].name.clone();
+                        let response = ui.selectable_label(is_active, &name);
+                        if response.clicked() {
+                            self.state.image.set_active(idx);
+                        }
+                        if response.double_clicked() {
+                            self.editing_layer_name = Some(idx);
+                            ui.memory_mut(|m| m.request_focus(name_id));
+                        }
+                    }
+                });
+
+                // Layer properties if active
+                if is_active {
+                    let layer = &mut self.state.image.layers[idx];
+                    let mut opacity = layer.opacity;
+                    let mut blend = layer.blend;
+                    let mut channel_lock = layer.channel_lock;
+                    let has_mask = layer.mask.is_some();
+                    let mut tone = match layer.data {
+                        crate::layers::LayerData::Tone {
+                            frequency, density, ..
+                        } => Some((frequency, density)),
+                        _ => None,
+                    };
+                    let mut mask_action = None;
+
+                    ui.indent(format!("props_{}", idx), |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Opacity");
+                            if ui.add(egui::Slider::new(&mut opacity, 0.0..=1.0)).changed() {
+                                // Will update after closure
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.label("Lock");
+                            for (c, label) in ["R", "G", "B", "A"].iter().enumerate() {
+                                if ui
+                                    .selectable_label(channel_lock[c], *label)
+                                    .on_hover_text(format!("Protect the {} channel", label))
+                                    .clicked()
+                                {
+                                    channel_lock[c] = !channel_lock[c];
+                                }
+                            }
+                        });
+
+                        egui::ComboBox::from_label("Blend")
+                            .selected_text(format!("{:?}", blend))
+                            .show_ui(ui, |ui| {
+                                for mode in crate::layers::BlendMode::ALL {
+                                    ui.selectable_value(&mut blend, mode, format!("{:?}", mode));
+                                }
+                            });
+
+                        if let Some((frequency, density)) = &mut tone {
+                            ui.horizontal(|ui| {
+                                ui.label("Frequency");
+                                ui.add(egui::Slider::new(frequency, 0.02..=0.5))
+                                    .on_hover_text("Dots per pixel");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Density");
+                                ui.add(egui::Slider::new(density, 0.0..=1.0));
+                            });
+                        }
+
+                        ui.horizontal(|ui| {
+                            if has_mask {
+                                ui.checkbox(&mut self.state.tool_settings.edit_mask, "Edit Mask")
+                                    .on_hover_text(
+                                        "Brush and eraser paint the mask: black hides, white reveals",
+                                    );
+                                if ui.button("Delete Mask").clicked() {
+                                    mask_action = Some(false);
+                                }
+                            } else if ui.button("Add Mask").clicked() {
+                                mask_action = Some(true);
+                            }
+                        });
+                    });
+
+                    match mask_action {
+                        Some(true) => self.state.add_active_layer_mask(),
+                        Some(false) => self.state.delete_active_layer_mask(),
+                        None => {}
+                    }
+
+                    // Apply changes
+                    let layer_mut = &mut self.state.image.layers[idx];
+                    layer_mut.channel_lock = channel_lock;
+                    let mut changed = layer_mut.opacity != opacity || layer_mut.blend != blend;
+                    layer_mut.opacity = opacity;
+                    layer_mut.blend = blend;
+                    if let (
+                        Some(new_tone),
+                        crate::layers::LayerData::Tone {
+                            frequency, density, ..
+                        },
+                    ) = (tone, &mut layer_mut.data)
+                    {
+                        changed |= (*frequency, *density) != new_tone;
+                        (*frequency, *density) = new_tone;
+                    }
+                    if changed {
+                        self.state.image.mark_dirty();
+                    }
+                }
+            }
+        });
+
+        if let Some((from, to)) = reorder {
+            self.clear_adjust_preview();
+            self.state.reorder_layer(from, to);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            let current = self.state.image.background;
+            let mut enabled = current.is_some();
+            let mut color = current.map_or([255, 255, 255, 255], |c| c.0);
+            let mut changed = ui
+                .checkbox(&mut enabled, "Background")
+                .on_hover_text("A solid color under all layers, left out of transparent exports")
+                .changed();
+            if enabled {
+                changed |= ui
+                    .color_edit_button_srgba_unmultiplied(&mut color)
+                    .changed();
+            }
+            if changed {
+                self.state
+                    .image
+                    .set_background(enabled.then_some(Rgba(color)));
+            }
+        });
+    }
+
+    /// Lists the undo history with the current state highlighted. Clicking a
+    /// step undoes or redoes back to just after it.
+    fn render_history_panel(&mut self, ui: &mut Ui) {
+        ui.heading("History");
+        ui.separator();
+
+        let (names, cursor) = self.state.command_stack.history();
+        let mut target = None;
+        egui::ScrollArea::vertical()
+            .id_salt("history")
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                // Step 0 is the document before any recorded edit.
+                let steps = std::iter::once("Start").chain(names.iter().copied());
+                for (i, name) in steps.enumerate() {
+                    let text = if i > cursor {
+                        egui::RichText::new(name).weak()
+                    } else {
+                        egui::RichText::new(name)
+                    };
+                    if ui.selectable_label(i == cursor, text).clicked() {
+                        target = Some(i);
+                    }
+                }
+            });
+
+        if let Some(target) = target.filter(|&t| t != cursor) {
+            self.state.command_stack.seek(target, &mut self.state.image);
+        }
+    }
+
+    fn render_selection_panel(&mut self, ui: &mut Ui) {
+        ui.heading("Selection");
+        ui.separator();
+
+        let has_selection = self.state.image.selection.is_some();
+        ui.horizontal(|ui| {
+            ui.label("Radius");
+            ui.add(
+                egui::DragValue::new(&mut self.selection_radius)
+                    .range(1..=200)
+                    .suffix(" px"),
+            );
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Grow"))
+                .clicked()
+            {
+                self.state.image.grow_selection(self.selection_radius);
+            }
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Shrink"))
+                .clicked()
+            {
+                self.state.image.shrink_selection(self.selection_radius);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Feather");
+            ui.add(
+                egui::DragValue::new(&mut self.feather_radius)
+                    .range(0.5..=100.0)
+                    .speed(0.1)
+                    .suffix(" px"),
+            );
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Feather"))
+                .on_hover_text("Soften the edge so painting fades out across it")
+                .clicked()
+            {
+                self.state.image.feather_selection(self.feather_radius);
+            }
+        });
+    }
+
+    /// Puts back the pixels a brightness/contrast or sharpen preview replaced.
+    fn clear_adjust_preview(&mut self) {
+        if let Some(preview) = self.adjust_preview.take() {
+            preview.undo(&mut self.state.image);
+            if let Some((x, y, w, h)) = preview.dirty_region() {
+                self.state.image.mark_dirty_rect(x, y, w, h);
+            }
+        }
+    }
+
+    fn preview_brightness_contrast(&mut self) {
+        self.clear_adjust_preview();
+        // Only one adjustment previews at a time.
+        self.sharpen_amount = 0.0;
+        let (brightness, contrast) = (self.brightness, self.contrast);
+        self.adjust_preview = self.state.image.adjust_active("Brightness/Contrast", |p| {
+            crate::adjustments::brightness_contrast(p, brightness, contrast)
+        });
+    }
+
+    /// Sharpens the active layer as a preview. The blur behind it is slow on
+    /// large layers, so it runs on a copy in the background. With `apply`,
+    /// the result is kept as an undo step instead.
+    fn preview_sharpen(&mut self, apply: bool) {
+        self.clear_adjust_preview();
+        self.brightness = 0.0;
+        self.contrast = 0.0;
+        let (radius, amount) = (self.sharpen_radius, self.sharpen_amount);
+        let mut doc = self.state.image.clone();
+        self.run_job("Sharpening", move || {
+            let sharpened = doc.sharpen_active(radius, amount);
+            Box::new(move |app: &mut ArsApp| {
+                let Some(sharpened) = sharpened else {
+                    return;
+                };
+                app.clear_adjust_preview();
+                if apply {
+                    app.state
+                        .command_stack
+                        .execute(Box::new(sharpened), &mut app.state.image);
+                    app.sharpen_amount = 0.0;
+                } else {
+                    sharpened.redo(&mut app.state.image);
+                    if let Some((x, y, w, h)) = sharpened.dirty_region() {
+                        app.state.image.mark_dirty_rect(x, y, w, h);
+                    }
+                    app.adjust_preview = Some(sharpened);
+                }
+            })
+        });
+    }
+
+    /// Drops the adjustment preview as soon as the user turns to anything
+    /// outside the Adjustments window: a click elsewhere or a shortcut key.
+    /// Tools, undo and redo, the history, the layer panel and saving all
+    /// start that way, so none of them sees the preview, and its undo never
+    /// writes stale pixels over their edits.
+    fn guard_adjust_preview(&mut self, ctx: &Context) {
+        if self.adjust_preview.is_none() {
+            return;
+        }
+        let typing = ctx.wants_keyboard_input();
+        let window = self.adjust_window;
+        let elsewhere = ctx.input(|i| {
+            let clicked = i.pointer.any_pressed()
+                && i.pointer
+                    .interact_pos()
+                    .is_none_or(|p| !window.is_some_and(|r| r.contains(p)));
+            let key = !typing
+                && i.events
+                    .iter()
+                    .any(|e| matches!(e, egui::Event::Key { pressed: true, .. }));
+            clicked || key
+        });
+        if elsewhere {
+            self.clear_adjust_preview();
+        }
+    }
+
+    fn render_adjustments(&mut self, ctx: &Context) {
+        let mut open = self.show_adjustments;
+        let window = egui::Window::new("Adjustments")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.heading("Brightness / Contrast");
+                ui.label("Applies within the selection, fading out over feathered edges.");
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Brightness");
+                    changed |= ui
+                        .add(egui::Slider::new(&mut self.brightness, -1.0..=1.0))
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Contrast");
+                    changed |= ui
+                        .add(egui::Slider::new(&mut self.contrast, -1.0..=1.0))
+                        .changed();
+                });
+                if changed {
+                    self.preview_brightness_contrast();
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").clicked() {
+                        if self.adjust_preview.is_none() {
+                            self.preview_brightness_contrast();
+                        }
+                        // The preview is already on the layer; keep it as the edit.
+                        if let Some(cmd) = self.adjust_preview.take() {
+                            self.state.command_stack.push(Box::new(cmd));
+                        }
+                        self.brightness = 0.0;
+                        self.contrast = 0.0;
+                    }
+                    if ui.button("Reset").clicked() {
+                        self.clear_adjust_preview();
+                        self.brightness = 0.0;
+                        self.contrast = 0.0;
+                    }
+                });
+
+                ui.separator();
+                ui.heading("Sharpen");
+                ui.label("Unsharp mask: boosts edges against a blurred copy of the layer.");
+                // Previewing runs behind the busy overlay, so wait until a
+                // slider is let go rather than following the drag.
+                let mut sliders = Vec::new();
+                ui.horizontal(|ui| {
+                    ui.label("Radius");
+                    sliders.push(ui.add(
+                        egui::Slider::new(&mut self.sharpen_radius, 0.5..=10.0).suffix(" px"),
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Amount");
+                    sliders.push(ui.add(egui::Slider::new(&mut self.sharpen_amount, 0.0..=5.0)));
+                });
+                if sliders
+                    .iter()
+                    .any(|r| r.drag_stopped() || (r.changed() && !r.dragged()))
+                {
+                    self.preview_sharpen(false);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").clicked() {
+                        if let Some(cmd) = self.adjust_preview.take() {
+                            self.state.command_stack.push(Box::new(cmd));
+                            self.sharpen_amount = 0.0;
+                        } else {
+                            self.preview_sharpen(true);
+                        }
+                    }
+                    if ui.button("Reset").clicked() {
+                        self.clear_adjust_preview();
+                        self.sharpen_amount = 0.0;
+                    }
+                });
+
+                ui.separator();
+                ui.heading("Color LUT");
+                ui.horizontal(|ui| {
+                    if ui.button("Load .cube...").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Cube LUT", &["cube"])
+                            .pick_file()
+                        {
+                            match CubeLut::load(&path) {
+                                Ok(lut) => self.lut = Some(std::sync::Arc::new(lut)),
+                                Err(e) => log::error!("Failed to load LUT: {:#}", e),
+                            }
+                        }
+                    }
+                    match &self.lut {
+                        Some(lut) => ui.label(format!(
+                            "{} ({}³)",
+                            lut.title.as_deref().unwrap_or("Untitled"),
+                            lut.size()
+                        )),
+                        None => ui.label("No LUT loaded"),
+                    };
+                });
+
+                let Some(lut) = self.lut.clone() else {
+                    return;
+                };
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Apply to Layer")
+                        .on_hover_text("Grade the active layer, within the selection if any")
+                        .clicked()
+                    {
+                        // A LUT grades the layer as it is, not the pending
+                        // preview, which the job drops.
+                        let lut = lut.clone();
+                        self.run_edit_job("Applying LUT", move |doc| {
+                            doc.adjust_active("Apply LUT", |p| lut.apply(p))
+                        });
+                    }
+                    if ui
+                        .button("Apply to Composite")
+                        .on_hover_text("Add a graded copy of the whole image as a new layer")
+                        .clicked()
+                    {
+                        let lut = lut.clone();
+                        self.run_edit_job("Applying LUT", move |doc| {
+                            Some(DocumentCommand::record(
+                                "Apply LUT to Composite",
+                                doc,
+                                |doc| {
+                                    let mut graded = doc.render_to_rgba();
+                                    for p in graded.pixels_mut() {
+                                        *p = lut.apply(*p);
+                                    }
+                                    let mut layer = Layer::new_raster(
+                                        graded.width(),
+                                        graded.height(),
+                                        "LUT".to_string(),
+                                    );
+                                    layer.data = crate::layers::LayerData::Raster(graded);
+                                    // The graded copy goes on top so it covers the
+                                    // stack it was made from.
+                                    doc.set_active(doc.layer_count() - 1);
+                                    doc.add_layer(layer);
+                                },
+                            ))
+                        });
+                    }
+                });
+            });
+        self.adjust_window = window.map(|w| w.response.rect);
+        if !open {
+            self.clear_adjust_preview();
+        }
+        self.show_adjustments = open;
+    }
+
+    /// Copies the selected part of the visible image, or all of it without
+    /// a selection, to the system clipboard.
+    fn copy_to_clipboard(&mut self) {
+        let Some(pixels) = self.state.image.copy_selection() else {
+            log::info!("Copy: selection is empty, nothing to copy");
+            return;
+        };
+        if let Err(e) = self.clipboard.copy_image(&pixels) {
+            log::error!("{:#}", e);
+        }
+    }
+
+    /// Pastes the clipboard image as a new layer above the active one. Asks
+    /// first if it is larger than the canvas.
+    fn paste_from_clipboard(&mut self) {
+        let pixels = match self.clipboard.paste_image() {
+            Ok(Some(pixels)) => pixels,
+            Ok(None) => {
+                log::info!("Paste: the clipboard holds no image");
+                return;
+            }
+            Err(e) => {
+                log::error!("{:#}", e);
+                return;
+            }
+        };
+        let (w, h) = pixels.dimensions();
+        if w > self.state.image.width() || h > self.state.image.height() {
+            self.pending_paste = Some(pixels);
+        } else {
+            self.paste_as_layer(&pixels);
+        }
+    }
+
+    /// Adds `pixels`, centered on the canvas and clipped to it, as a new layer.
+    fn paste_as_layer(&mut self, pixels: &image::RgbaImage) {
+        let (cw, ch) = (self.state.image.width(), self.state.image.height());
+        let mut layer = Layer::new_raster(cw, ch, "Pasted".to_string());
+        if let crate::layers::LayerData::Raster(buffer) = &mut layer.data {
+            let dx = (cw as i64 - pixels.width() as i64) / 2;
+            let dy = (ch as i64 - pixels.height() as i64) / 2;
+            image::imageops::replace(buffer, pixels, dx, dy);
+        }
+        self.state.add_layer_above("Paste", layer);
+    }
+
+    fn open_refine_edge(&mut self) {
+        if self.refine_original.is_none() {
+            self.refine_original = self.state.image.selection.clone();
+            self.refine = RefineParams::default();
+        }
+    }
+
+    fn render_refine_edge(&mut self, ctx: &Context) {
+        let Some(original) = &self.refine_original else {
+            return;
+        };
+        let mut open = true;
+        let mut confirmed = false;
+        let mut cancelled = false;
+        let mut changed = false;
+        egui::Window::new("Refine Edge")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("Red marks what falls outside the selection.");
+                egui::Grid::new("refine_edge_grid").show(ui, |ui| {
+                    ui.label("Feather");
+                    changed |= ui
+                        .add(egui::Slider::new(&mut self.refine.feather, 0.0..=50.0).suffix(" px"))
+                        .changed();
+                    ui.end_row();
+                    ui.label("Contract / Expand");
+                    changed |= ui
+                        .add(egui::Slider::new(&mut self.refine.shift, -50..=50).suffix(" px"))
+                        .changed();
+                    ui.end_row();
+                    ui.label("Smooth");
+                    changed |= ui
+                        .add(egui::Slider::new(&mut self.refine.smooth, 0..=20).suffix(" px"))
+                        .changed();
+                    ui.end_row();
+                });
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("OK").clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+
+        if changed {
+            self.state.image.selection = Some(crate::mask_ops::refine(original, self.refine));
+        }
+        if cancelled || !open {
+            self.state.image.selection = self.refine_original.take();
+        } else if confirmed {
+            self.refine_original = None;
+        }
+    }
+
+    /// Writes the composite in the background and, once it succeeds,
+    /// remembers the settings and path for "Export with Last Settings".
+    fn start_export(&mut self, path: std::path::PathBuf, settings: ExportSettings) {
+        // The document background only shows up when the export is flattened.
+        let pixels = if settings.flattens() {
+            self.state.image.render_to_rgba()
+        } else {
+            self.state.image.render_transparent()
+        };
+        self.export_pixels(pixels, path, settings, true);
+    }
+
+    fn export_pixels(
+        &mut self,
+        pixels: image::RgbaImage,
+        path: std::path::PathBuf,
+        settings: ExportSettings,
+        remember: bool,
+    ) {
+        self.run_job("Exporting", move || {
+            let result = crate::export::export(&pixels, &path, &settings);
+            Box::new(move |app: &mut ArsApp| match result {
+                Ok(()) if !remember => {}
+                Ok(()) => {
+                    app.config.last_export = Some(settings);
+                    app.config.last_export_path = Some(path);
+                    if let Err(e) = app.config.save() {
+                        log::error!("Failed to save config: {:#}", e);
+                    }
+                }
+                Err(e) => log::error!("Failed to export: {:#}", e),
+            })
+        });
+    }
+
+    fn export_with_last_settings(&mut self) {
+        let Some(settings) = self.config.last_export.clone() else {
+            return;
+        };
+        let path = match &self.config.last_export_path {
+            Some(path) => Some(path.clone()),
+            None => rfd::FileDialog::new()
+                .add_filter(settings.format.label(), &[settings.format.extension()])
+                .save_file(),
+        };
+        if let Some(path) = path {
+            self.start_export(path, settings);
+        }
+    }
+
+    /// Exports what the canvas area currently shows rather than the raw image.
+    fn export_view(&mut self, path: std::path::PathBuf) {
+        let Some((view, size)) = self.viewport else {
+            return;
+        };
+        let (width, height) = (size.x.round() as u32, size.y.round() as u32);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let selection = self.state.image.selection.clone();
+        let composite = self.state.image.render_to_rgba();
+        let pixels = crate::export::render_view(
+            &composite,
+            selection.as_ref(),
+            &view,
+            width,
+            height,
+            self.export_view_overlays,
+        );
+        // A view export is a one-off; "Export with Last Settings" keeps
+        // exporting the image itself.
+        self.export_pixels(pixels, path, self.export_settings.clone(), false);
+    }
+
+    fn render_export(&mut self, ctx: &Context) {
+        let mut open = self.show_export;
+        let mut export = false;
+        let mut export_view = false;
+        egui::Window::new("Export")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let settings = &mut self.export_settings;
+                egui::Grid::new("export_grid").show(ui, |ui| {
+                    ui.label("Format");
+                    egui::ComboBox::from_id_salt("export_format")
+                        .selected_text(settings.format.label())
+                        .show_ui(ui, |ui| {
+                            for format in ExportFormat::ALL {
+                                ui.selectable_value(&mut settings.format, format, format.label());
+                            }
+                        });
+                    ui.end_row();
+
+                    if settings.format == ExportFormat::Jpeg {
+                        ui.label("Quality");
+                        ui.add(egui::Slider::new(&mut settings.quality, 1..=100));
+                        ui.end_row();
+                    }
+                    if settings.format != ExportFormat::Bmp {
+                        ui.label("DPI");
+                        ui.add(egui::DragValue::new(&mut settings.dpi).range(1..=2400));
+                        ui.end_row();
+                    }
+
+                    ui.label("Flatten");
+                    ui.horizontal(|ui| {
+                        ui.add_enabled(
+                            settings.format.has_alpha(),
+                            egui::Checkbox::without_text(&mut settings.flatten),
+                        )
+                        .on_disabled_hover_text("JPEG has no transparency");
+                        if settings.flattens() {
+                            ui.label("Matte");
+                            ui.color_edit_button_srgb(&mut settings.matte);
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Premultiply");
+                    ui.add_enabled(
+                        !settings.flattens(),
+                        egui::Checkbox::without_text(&mut settings.premultiply),
+                    )
+                    .on_hover_text("Store color multiplied by alpha");
+                    ui.end_row();
+                });
+                ui.separator();
+                export = ui.button("Export...").clicked();
+
+                ui.separator();
+                ui.label("Export the canvas area as shown on screen, at the current zoom.");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.export_view_overlays, "Include Overlays")
+                        .on_hover_text("Selection tint and canvas border");
+                    export_view = ui.button("Export View...").clicked();
+                });
+            });
+
+        if export || export_view {
+            let format = self.export_settings.format;
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter(format.label(), &[format.extension()])
+                .save_file()
+            {
+                if export_view {
+                    self.export_view(path);
+                } else {
+                    self.start_export(path, self.export_settings.clone());
+                }
+                open = false;
+            }
+        }
+        self.show_export = open;
+    }
+
+    fn render_preferences(&mut self, ctx: &Context) {
+        let mut open = self.show_preferences;
+        let mut changed = false;
+        egui::Window::new("Preferences")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let prefs = &mut self.state.preferences;
+                egui::Grid::new("preferences_grid").show(ui, |ui| {
+                    ui.label("Undo Levels");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut prefs.undo_levels).range(1..=1000))
+                        .on_hover_text("Older steps are dropped to save memory")
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Selection");
+                    ui.horizontal(|ui| {
+                        for (style, label) in [
+                            (SelectionStyle::Outline, "Outline"),
+                            (SelectionStyle::Fill, "Fill"),
+                            (SelectionStyle::Both, "Both"),
+                        ] {
+                            changed |= ui
+                                .selectable_value(&mut prefs.selection_style, style, label)
+                                .changed();
+                        }
+                        changed |= ui
+                            .color_edit_button_srgb(&mut prefs.selection_color)
+                            .changed();
+                    });
+                    ui.end_row();
+
+                    ui.label("Max Canvas Size");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut prefs.max_canvas_size)
+                                .range(1..=65535)
+                                .suffix(" px"),
+                        )
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Warn on Resize Above");
+                    let mut megabytes = prefs.resize_warn_bytes / (1024 * 1024);
                     if ui
-                        .checkbox(&mut clipped, "🖇")
-                        .on_hover_text("Clip to Layer Below")
+                        .add(
+                            egui::DragValue::new(&mut megabytes)
+                                .range(1..=65536)
+                                .suffix(" MB"),
+                        )
                         .changed()
                     {
-                        self.state.image.layers[idx].clipped = clipped;
-                        self.state.image.mark_dirty();
-                    }
-
-                    // Selection
-                    let name = self.state.image.layers[idx].name.clone();
-                    let response = ui.selectable_label(is_active, &name);
-                    if response.clicked() {
-                        self.state.image.active_layer = idx;
+                        prefs.resize_warn_bytes = megabytes * 1024 * 1024;
+                        changed = true;
                     }
+                    ui.end_row();
                 });
+            });
+        self.show_preferences = open;
+
+        if changed {
+            self.state
+                .command_stack
+                .set_limit(self.state.preferences.undo_levels);
+            self.config.preferences = self.state.preferences.clone();
+            if let Err(e) = self.config.save() {
+                log::error!("Failed to save config: {:#}", e);
+            }
+        }
+    }
 
-                // Layer properties if active
-                if is_active {
-                    let layer = &mut self.state.image.layers[idx];
-                    let mut opacity = layer.opacity;
-                    let mut blend = layer.blend;
-
-                    ui.indent(format!("props_{}", idx), |ui| {
-                        ui.horizontal(|ui| {
-                            ui.label("Opacity");
-                            if ui.add(egui::Slider::new(&mut opacity, 0.0..=1.0)).changed() {
-                                // Will update after closure
-                            }
-                        });
+    fn render_guides(&mut self, ctx: &Context) {
+        use crate::guides::GuideKind;
 
-                        egui::ComboBox::from_label("Blend")
-                            .selected_text(format!("{:?}", blend))
-                            .show_ui(ui, |ui| {
-                                ui.selectable_value(
-                                    &mut blend,
-                                    crate::layers::BlendMode::Normal,
-                                    "Normal",
-                                );
-                                ui.selectable_value(
-                                    &mut blend,
-                                    crate::layers::BlendMode::Multiply,
-                                    "Multiply",
-                                );
-                                ui.selectable_value(
-                                    &mut blend,
-                                    crate::layers::BlendMode::Add,
-                                    "Add",
-                                );
-                                ui.selectable_value(
-                                    &mut blend,
-                                    crate::layers::BlendMode::Screen,
-                                    "Screen",
-                                );
-                            });
+        let mut open = self.show_guides;
+        let (width, height) = (
+            self.state.image.width() as f32,
+            self.state.image.height() as f32,
+        );
+        egui::Window::new("Guides")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let guides = &mut self.guides;
+                ui.checkbox(&mut guides.visible, "Show Guides");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut guides.kind, GuideKind::Perspective, "Perspective");
+                    ui.selectable_value(&mut guides.kind, GuideKind::Concentric, "Concentric");
+                });
+                egui::Grid::new("guides_grid").show(ui, |ui| {
+                    ui.label("Center");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut guides.center.x).prefix("x "));
+                        ui.add(egui::DragValue::new(&mut guides.center.y).prefix("y "));
+                        if ui
+                            .selectable_label(guides.placing, "Place")
+                            .on_hover_text("Click or drag on the canvas to move the center")
+                            .clicked()
+                        {
+                            guides.placing = !guides.placing;
+                            guides.visible = true;
+                        }
+                        if ui.button("Middle").clicked() {
+                            guides.center = Pos2::new(width / 2.0, height / 2.0);
+                        }
                     });
+                    ui.end_row();
 
-                    // Apply changes
-                    let layer_mut = &mut self.state.image.layers[idx];
-                    if layer_mut.opacity != opacity || layer_mut.blend != blend {
-                        layer_mut.opacity = opacity;
-                        layer_mut.blend = blend;
-                        self.state.image.mark_dirty();
-                        self.image_dirty = true;
+                    match guides.kind {
+                        GuideKind::Perspective => {
+                            ui.label("Lines");
+                            ui.add(egui::DragValue::new(&mut guides.count).range(2..=180));
+                        }
+                        GuideKind::Concentric => {
+                            ui.label("Rings");
+                            ui.add(egui::DragValue::new(&mut guides.count).range(1..=100));
+                            ui.end_row();
+                            ui.label("Spacing");
+                            ui.add(
+                                egui::DragValue::new(&mut guides.spacing)
+                                    .range(1.0..=1000.0)
+                                    .suffix(" px"),
+                            );
+                        }
                     }
+                    ui.end_row();
+
+                    ui.label("Color");
+                    ui.color_edit_button_srgba(&mut guides.color);
+                    ui.end_row();
+                });
+                if guides.kind == GuideKind::Perspective {
+                    ui.checkbox(&mut guides.snap_lines, "Snap Line Tool")
+                        .on_hover_text("Lines aim at the vanishing point (hold Shift for angles)");
                 }
-            }
-        });
+            });
+        if !open {
+            self.guides.placing = false;
+        }
+        self.show_guides = open;
+    }
+
+    fn render_color_picker(&mut self, ctx: &Context) {
+        let mut open = self.show_color_picker;
+        egui::Window::new("Color")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Edits the primary color.");
+                self.color_picker.show(ui, &mut self.state.primary_color);
+            });
+        self.show_color_picker = open;
     }
 
     fn render_shortcuts_popup(&mut self, ctx: &Context) {
         let mut open = self.show_shortcuts;
+        let mut changed = false;
         egui::Window::new("Key Mappings")
             .open(&mut open)
             .show(ctx, |ui| {
@@ -235,21 +2031,9 @@ impl ArsApp {
                         });
                     }
 
-                    shortcut_row(ui, "Undo", &mut bindings.undo, &mut self.remapping);
-                    shortcut_row(ui, "Redo", &mut bindings.redo, &mut self.remapping);
-                    shortcut_row(ui, "Brush", &mut bindings.brush, &mut self.remapping);
-                    shortcut_row(ui, "Eraser", &mut bindings.eraser, &mut self.remapping);
-                    shortcut_row(ui, "Line", &mut bindings.line, &mut self.remapping);
-                    shortcut_row(ui, "Rectangle", &mut bindings.rect, &mut self.remapping);
-                    shortcut_row(ui, "Ellipse", &mut bindings.ellipse, &mut self.remapping);
-                    shortcut_row(ui, "Select", &mut bindings.select, &mut self.remapping);
-                    shortcut_row(ui, "Deselect", &mut bindings.deselect, &mut self.remapping);
-                    shortcut_row(
-                        ui,
-                        "Transform",
-                        &mut bindings.transform,
-                        &mut self.remapping,
-                    );
+                    for (label, shortcut) in bindings.entries_mut() {
+                        shortcut_row(ui, label, shortcut, &mut self.remapping);
+                    }
 
                     ui.horizontal(|ui| {
                         ui.label("Pan (Modifier):");
@@ -262,86 +2046,48 @@ impl ArsApp {
                             self.remapping = Some("Pan".to_string());
                         }
                     });
+
+                    ui.separator();
+                    if ui.button("Reset to Defaults").clicked() {
+                        *bindings = crate::state::Keybindings::default();
+                        self.remapping = None;
+                        changed = true;
+                    }
                 });
 
                 if let Some(label) = &self.remapping {
                     let input = ui.input(|i| i.clone());
                     if let Some(key) = input.keys_down.iter().next() {
                         let bindings = &mut self.state.keybindings;
-                        match label.as_str() {
-                            "Undo" => {
-                                bindings.undo = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Redo" => {
-                                bindings.redo = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Brush" => {
-                                bindings.brush = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Eraser" => {
-                                bindings.eraser = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Line" => {
-                                bindings.line = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Rectangle" => {
-                                bindings.rect = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Ellipse" => {
-                                bindings.ellipse = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Select" => {
-                                bindings.select = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Deselect" => {
-                                bindings.deselect = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Transform" => {
-                                bindings.transform = crate::state::Shortcut::new(*key)
-                                    .ctrl(input.modifiers.ctrl)
-                                    .shift(input.modifiers.shift)
-                                    .alt(input.modifiers.alt)
-                            }
-                            "Pan" => bindings.pan = *key,
-                            _ => {}
+                        if label == "Pan" {
+                            bindings.pan = *key;
+                        } else if let Some((_, shortcut)) =
+                            bindings.entries_mut().into_iter().find(|(l, _)| l == label)
+                        {
+                            *shortcut = crate::state::Shortcut::new(*key)
+                                .ctrl(input.modifiers.ctrl)
+                                .shift(input.modifiers.shift)
+                                .alt(input.modifiers.alt);
                         }
                         self.remapping = None;
+                        changed = true;
                     }
                 }
             });
         self.show_shortcuts = open;
+
+        if changed {
+            self.config.keybindings = self.state.keybindings.clone();
+            if let Err(e) = self.config.save() {
+                log::error!("Failed to save config: {:#}", e);
+            }
+        }
     }
 
     fn render_canvas(&mut self, ui: &mut Ui) {
         let canvas_size = ui.available_size();
         let (response, painter) = ui.allocate_painter(canvas_size, Sense::drag());
+        self.canvas_size = response.rect.size();
 
         let image_size = Vec2::new(
             self.state.image.width() as f32,
@@ -351,8 +2097,25 @@ impl ArsApp {
         let screen_center = response.rect.center();
         let image_rect = Rect::from_center_size(screen_center + self.pan, image_size);
 
+        self.cursor_pixel = ui
+            .input(|i| i.pointer.hover_pos())
+            .filter(|pos| response.rect.contains(*pos) && image_rect.contains(*pos))
+            .map(|pos| {
+                let pixel = (pos - image_rect.min) / self.zoom;
+                (pixel.x as u32, pixel.y as u32)
+            });
+
+        let ppp = ui.ctx().pixels_per_point();
+        self.viewport = Some((
+            crate::tools::ViewTransform {
+                origin: ((image_rect.min - response.rect.min) * ppp).to_pos2(),
+                zoom: self.zoom * ppp,
+            },
+            response.rect.size() * ppp,
+        ));
+
         let checker_size = 16.0 * self.zoom;
-        let mut checker_painter = painter.with_clip_rect(image_rect);
+        let checker_painter = painter.with_clip_rect(image_rect);
         checker_painter.rect_filled(image_rect, 0.0, Color32::from_gray(200));
 
         let rows = (image_rect.height() / checker_size).ceil() as i32;
@@ -386,7 +2149,11 @@ impl ArsApp {
         }
 
         // Draw Temp Tool Layer (e.g. brush stroke in progress)
-        if let Some(texture) = &self.layer_texture {
+        if let Some(texture) = self
+            .layer_texture
+            .as_ref()
+            .filter(|_| self.temp_rect.is_some())
+        {
             painter.image(
                 texture.id(),
                 image_rect,
@@ -403,6 +2170,7 @@ impl ArsApp {
                 Color32::WHITE,
             );
         }
+        self.draw_selection_ants(ui, &painter, image_rect);
 
         // Canvas Border
         painter.rect_stroke(
@@ -419,7 +2187,7 @@ impl ArsApp {
         let corner_handle =
             Rect::from_center_size(image_rect.right_bottom(), Vec2::splat(handle_size));
 
-        let mut draw_handle = |rect: Rect, id_str: &str, cursor: egui::CursorIcon| {
+        let draw_handle = |rect: Rect, id_str: &str, cursor: egui::CursorIcon| {
             let id = ui.make_persistent_id(id_str);
             let response = ui.interact(rect, id, Sense::drag());
             let color = if response.hovered() || response.dragged() {
@@ -439,31 +2207,50 @@ impl ArsApp {
         let h_bottom = draw_handle(bottom_handle, "h_bottom", egui::CursorIcon::ResizeVertical);
         let h_corner = draw_handle(corner_handle, "h_corner", egui::CursorIcon::ResizeNwSe);
 
-        if h_right.dragged() {
-            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
-                let new_w = ((mouse_pos.x - image_rect.left()) / self.zoom).max(1.0) as u32;
-                self.state.image.resize(new_w, self.state.image.height());
-                self.image_dirty = true;
+        // Handles only preview the new size while dragging; the resize itself
+        // happens once on release so it is a single undo step.
+        let max_dim = self.state.preferences.max_canvas_size.max(1) as f32;
+        let (cur_w, cur_h) = (self.state.image.width(), self.state.image.height());
+        let handles = [
+            (&h_right, true, false),
+            (&h_bottom, false, true),
+            (&h_corner, true, true),
+        ];
+        for (handle, horizontal, vertical) in handles {
+            if handle.dragged() {
+                if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
+                    let new_w = if horizontal {
+                        ((mouse_pos.x - image_rect.left()) / self.zoom).clamp(1.0, max_dim) as u32
+                    } else {
+                        cur_w
+                    };
+                    let new_h = if vertical {
+                        ((mouse_pos.y - image_rect.top()) / self.zoom).clamp(1.0, max_dim) as u32
+                    } else {
+                        cur_h
+                    };
+                    self.resize_preview = Some((new_w, new_h));
+                }
             }
-        }
-        if h_bottom.dragged() {
-            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
-                let new_h = ((mouse_pos.y - image_rect.top()) / self.zoom).max(1.0) as u32;
-                self.state.image.resize(self.state.image.width(), new_h);
-                self.image_dirty = true;
+            if handle.drag_stopped() {
+                if let Some((new_w, new_h)) = self.resize_preview.take() {
+                    self.request_resize(new_w, new_h, egui::Align2::LEFT_TOP);
+                }
             }
         }
-        if h_corner.dragged() {
-            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
-                let new_w = ((mouse_pos.x - image_rect.left()) / self.zoom).max(1.0) as u32;
-                let new_h = ((mouse_pos.y - image_rect.top()) / self.zoom).max(1.0) as u32;
-                self.state.image.resize(new_w, new_h);
-                self.image_dirty = true;
-            }
+
+        if let Some((new_w, new_h)) = self.resize_preview {
+            let preview = Rect::from_min_size(
+                image_rect.min,
+                Vec2::new(new_w as f32, new_h as f32) * self.zoom,
+            );
+            painter.rect_stroke(preview, 0.0, egui::Stroke::new(1.0, Color32::WHITE));
         }
 
         // Input Handling
         let bindings = &self.state.keybindings;
+        // Plain-key shortcuts give way to a focused text field.
+        let typing = ui.ctx().wants_keyboard_input();
         let ctrl = ui.input(|i| i.modifiers.ctrl);
 
         if ctrl {
@@ -476,68 +2263,144 @@ impl ArsApp {
             }
         } else {
             if response.dragged_by(PointerButton::Middle)
-                || (ui.input(|i| i.key_down(bindings.pan)) && response.dragged())
+                || (ui.input(|i| !typing && i.key_down(bindings.pan)) && response.dragged())
             {
                 self.pan += response.drag_delta();
             }
         }
 
-        let is_panning =
-            response.dragged_by(PointerButton::Middle) || ui.input(|i| i.key_down(bindings.pan));
+        let is_panning = response.dragged_by(PointerButton::Middle)
+            || ui.input(|i| !typing && i.key_down(bindings.pan));
 
         if !is_panning {
             ui.input(|i| {
-                if bindings.undo.matches(i) {
+                if bindings.undo.triggered(i, typing) {
                     self.state.command_stack.undo(&mut self.state.image);
-                    self.image_dirty = true;
                 }
-                if bindings.redo.matches(i) {
+                if bindings.redo.triggered(i, typing) {
                     self.state.command_stack.redo(&mut self.state.image);
-                    self.image_dirty = true;
                 }
-                if bindings.brush.matches(i) {
+                if bindings.brush.triggered(i, typing) {
                     self.state.active_tool = Box::new(crate::tools::BrushTool::new(
                         self.state.image.width(),
                         self.state.image.height(),
                     ));
                 }
-                if bindings.eraser.matches(i) {
+                if bindings.eraser.triggered(i, typing) {
                     self.state.active_tool = Box::new(crate::tools::EraserTool::new(
                         self.state.image.width(),
                         self.state.image.height(),
                     ));
                 }
-                if bindings.line.matches(i) {
+                if bindings.line.triggered(i, typing) {
                     self.state.active_tool = Box::new(crate::tools::LineTool::new(
                         self.state.image.width(),
                         self.state.image.height(),
                     ));
                 }
-                if bindings.rect.matches(i) {
+                if bindings.rect.triggered(i, typing) {
                     self.state.active_tool = Box::new(crate::tools::RectangleTool::new(
                         self.state.image.width(),
                         self.state.image.height(),
                     ));
                 }
-                if bindings.ellipse.matches(i) {
+                if bindings.ellipse.triggered(i, typing) {
                     self.state.active_tool = Box::new(crate::tools::EllipseTool::new(
                         self.state.image.width(),
                         self.state.image.height(),
                     ));
                 }
-                if bindings.select.matches(i) {
+                if bindings.select.triggered(i, typing) {
                     self.state.active_tool =
                         Box::new(crate::tools::selection::RectSelectionTool::new());
                 }
-                if bindings.deselect.matches(i) {
+                if bindings.ellipse_select.triggered(i, typing) {
+                    self.state.active_tool = Box::new(crate::tools::EllipseSelectionTool::new());
+                }
+                if bindings.magic_wand.triggered(i, typing) {
+                    self.state.active_tool = Box::new(crate::tools::MagicWandTool::new());
+                }
+                if bindings.deselect.triggered(i, typing) {
                     self.state.image.selection = None;
                 }
-                if bindings.transform.matches(i) {
+                if bindings.invert_selection.triggered(i, typing) {
+                    self.state.image.invert_selection();
+                }
+                if bindings.select_all.triggered(i, typing) {
+                    self.state.image.select_all();
+                }
+                if bindings.transform.triggered(i, typing) {
                     self.state.active_tool = Box::new(crate::tools::TransformTool::new());
                 }
+                if bindings.eyedropper.triggered(i, typing) {
+                    self.state.active_tool = Box::new(crate::tools::EyedropperTool::new());
+                }
+                if i.key_pressed(egui::Key::Escape) {
+                    self.state.active_tool.cancel();
+                }
+            });
+
+            let (toggle_visibility, layer_up, layer_down, copy, paste, fit) = ui.input(|i| {
+                (
+                    bindings.toggle_visibility.triggered(i, typing),
+                    bindings.layer_up.triggered(i, typing),
+                    bindings.layer_down.triggered(i, typing),
+                    bindings.copy.triggered(i, typing),
+                    bindings.paste.triggered(i, typing),
+                    bindings.fit_view.triggered(i, typing),
+                )
             });
+            let (swap_colors, reset_colors, size_down, size_up) = ui.input(|i| {
+                (
+                    bindings.swap_colors.triggered(i, typing),
+                    bindings.reset_colors.triggered(i, typing),
+                    bindings.size_down.triggered(i, typing),
+                    bindings.size_up.triggered(i, typing),
+                )
+            });
+            if size_down || size_up {
+                self.state.step_tool_size(size_up);
+            }
+            if swap_colors {
+                self.state.swap_colors();
+            }
+            if reset_colors {
+                self.state.reset_colors();
+            }
+            if fit {
+                self.fit_view();
+            }
+            if copy {
+                self.copy_to_clipboard();
+            }
+            if paste {
+                self.paste_from_clipboard();
+            }
+            if toggle_visibility {
+                self.state.toggle_active_layer_visibility();
+            }
+            if layer_up {
+                self.state.move_active_layer(1);
+            }
+            if layer_down {
+                self.state.move_active_layer(-1);
+            }
 
             let pointer_pos = response.interact_pointer_pos();
+
+            // While placing a guide center, clicks move it instead of painting.
+            let placing = self.guides.placing;
+            if placing {
+                if let Some(pos) = pointer_pos.filter(|_| response.clicked() || response.dragged())
+                {
+                    self.guides.center = ((pos - image_rect.min) / self.zoom).to_pos2();
+                }
+                if response.clicked() || response.drag_stopped() {
+                    self.guides.placing = false;
+                }
+            }
+            let pointer_pos = pointer_pos.filter(|_| !placing);
+
             let hover_pos_in_image = pointer_pos.map(|pos| {
                 let relative = pos - image_rect.min;
                 let x = (relative.x / self.zoom) as i32;
@@ -545,6 +2408,8 @@ impl ArsApp {
                 Pos2::new(x as f32, y as f32)
             });
 
+            self.update_pressure(ui.ctx());
+
             let is_right_click = response.dragged_by(PointerButton::Secondary)
                 || response.drag_started_by(PointerButton::Secondary);
 
@@ -555,6 +2420,16 @@ impl ArsApp {
                     || is_right_click,
                 is_released: response.drag_stopped_by(PointerButton::Primary)
                     || response.drag_stopped_by(PointerButton::Secondary),
+                shift: ui.input(|i| i.modifiers.shift),
+                alt: ui.input(|i| i.modifiers.alt),
+                pressure: self.pressure,
+                zoom: self.zoom,
+                vanishing_point: self.guides.snap_target(),
+                other_color: if is_right_click {
+                    self.state.primary_color
+                } else {
+                    self.state.secondary_color
+                },
             };
 
             let draw_color = if is_right_click {
@@ -563,6 +2438,7 @@ impl ArsApp {
                 self.state.primary_color
             };
 
+            let size_before = (self.state.image.width(), self.state.image.height());
             let command = self.state.active_tool.update(
                 &mut self.state.image,
                 &self.state.tool_settings,
@@ -572,9 +2448,27 @@ impl ArsApp {
 
             if let Some(cmd) = command {
                 self.state.command_stack.push(cmd);
-                self.image_dirty = true;
+                // A crop moves the kept region; bring it back to the middle.
+                if size_before != (self.state.image.width(), self.state.image.height()) {
+                    self.pan = Vec2::ZERO;
+                }
+            }
+
+            if let Some(picked) = self.state.active_tool.take_picked_color() {
+                if is_right_click {
+                    self.state.secondary_color = picked;
+                } else {
+                    self.state.primary_color = picked;
+                }
             }
 
+            let view = crate::tools::ViewTransform {
+                origin: image_rect.min,
+                zoom: self.zoom,
+            };
+            self.guides.draw(&painter, &view, image_rect);
+            self.state.active_tool.draw_overlay(&painter, &view);
+
             if let Some(pos) = pointer_pos {
                 if image_rect.contains(pos) {
                     self.state.active_tool.draw_cursor(
@@ -582,6 +2476,7 @@ impl ArsApp {
                         &painter,
                         &self.state.tool_settings,
                         pos,
+                        &view,
                     );
                 }
             }
@@ -591,13 +2486,32 @@ impl ArsApp {
 
 impl eframe::App for ArsApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        self.poll_job(ctx);
+        self.guard_adjust_preview(ctx);
+        self.handle_dropped_files(ctx);
         self.update_textures(ctx);
         self.render_shortcuts_popup(ctx);
+        self.render_adjustments(ctx);
+        self.render_canvas_dialog(ctx);
+        self.render_image_size_dialog(ctx);
+        self.render_new_document_dialog(ctx);
+        self.render_resize_confirm(ctx);
+        self.render_frames_prompt(ctx);
+        self.render_paste_prompt(ctx);
+        self.render_refine_edge(ctx);
+        self.render_export(ctx);
+        self.render_preferences(ctx);
+        self.render_guides(ctx);
+        self.render_color_picker(ctx);
 
         egui::SidePanel::right("right_panel")
             .resizable(true)
             .show(ctx, |ui| {
                 self.render_layers_panel(ui);
+                ui.add_space(8.0);
+                self.render_selection_panel(ui);
+                ui.add_space(8.0);
+                self.render_history_panel(ui);
             });
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
@@ -608,45 +2522,219 @@ impl eframe::App for ArsApp {
                 if ui.button("Shortcuts").clicked() {
                     self.show_shortcuts = true;
                 }
+                if ui.button("Preferences").clicked() {
+                    self.show_preferences = true;
+                }
+                if ui.button("Adjustments").clicked() {
+                    self.show_adjustments = true;
+                }
+                if ui.button("Guides").clicked() {
+                    self.show_guides = true;
+                }
+                ui.menu_button("Select", |ui| {
+                    if ui.button("All").clicked() {
+                        self.state.image.select_all();
+                        ui.close_menu();
+                    }
+                    if ui.button("Invert").clicked() {
+                        self.state.image.invert_selection();
+                        ui.close_menu();
+                    }
+                    let has_selection = self.state.image.selection.is_some();
+                    if ui
+                        .add_enabled(has_selection, egui::Button::new("Crop to Selection"))
+                        .clicked()
+                    {
+                        if self.state.crop_to_selection() {
+                            self.pan = Vec2::ZERO;
+                        }
+                        ui.close_menu();
+                    }
+                });
+                if ui.button("Canvas Size...").clicked() {
+                    self.canvas_dialog = Some((
+                        self.state.image.width(),
+                        self.state.image.height(),
+                        egui::Align2::CENTER_CENTER,
+                    ));
+                }
+                if ui.button("Image Size...").clicked() {
+                    self.image_size_dialog = Some(ImageSizeDialog {
+                        width: self.state.image.width(),
+                        height: self.state.image.height(),
+                        keep_aspect: true,
+                        filter: FilterType::Lanczos3,
+                    });
+                }
+                ui.menu_button("Rotate", |ui| {
+                    for (label, quarter_turns) in [("90° CW", 1), ("90° CCW", 3), ("180°", 2)] {
+                        if ui.button(label).clicked() {
+                            let name = format!("Rotate {}", label);
+                            self.run_edit_job("Rotating", move |doc| {
+                                Some(DocumentCommand::record(&name, doc, |doc| {
+                                    doc.rotate(quarter_turns)
+                                }))
+                            });
+                            self.pan = Vec2::ZERO;
+                            ui.close_menu();
+                        }
+                    }
+                });
+                ui.menu_button("Flip", |ui| {
+                    use crate::layers::FlipAxis;
+                    use crate::state::FlipScope;
+
+                    let has_selection = self.state.image.selection.is_some();
+                    let entries = [
+                        ("Image", FlipScope::Image, true),
+                        ("Layer", FlipScope::Layer, true),
+                        ("Selection", FlipScope::Selection, has_selection),
+                    ];
+                    for (label, scope, enabled) in entries {
+                        for (axis, direction) in [
+                            (FlipAxis::Horizontal, "Horizontal"),
+                            (FlipAxis::Vertical, "Vertical"),
+                        ] {
+                            if ui
+                                .add_enabled(
+                                    enabled,
+                                    egui::Button::new(format!("{} {}", label, direction)),
+                                )
+                                .clicked()
+                            {
+                                self.state.flip(axis, scope);
+                                ui.close_menu();
+                            }
+                        }
+                        if scope != FlipScope::Selection {
+                            ui.separator();
+                        }
+                    }
+                });
+                if ui
+                    .add_enabled(
+                        self.state.image.selection.is_some(),
+                        egui::Button::new("Refine Edge..."),
+                    )
+                    .clicked()
+                {
+                    self.open_refine_edge();
+                }
 
                 ui.separator();
 
+                if ui.button("New...").clicked() {
+                    self.new_document_dialog = Some(NewDocumentDialog {
+                        width: self.state.image.width(),
+                        height: self.state.image.height(),
+                        background: NewBackground::White,
+                        color: [255, 255, 255],
+                        dpi: self.export_settings.dpi,
+                    });
+                }
                 if ui.button("Open").clicked() {
                     if let Some(path) = rfd::FileDialog::new()
-                        .add_filter("Image", &["png", "jpg", "bmp"])
+                        .add_filter("Image or Project", &["png", "jpg", "bmp", "gif", "ars"])
+                        .add_filter("ArsPaint Project", &["ars"])
+                        .add_filter("Image", &["png", "jpg", "bmp", "gif"])
                         .pick_file()
                     {
-                        match crate::image_store::ImageStore::from_file(&path) {
-                            Ok(store) => {
-                                self.state.image = store;
-                                self.state.command_stack = crate::commands::CommandStack::new();
-                                self.base_texture = None;
-                                self.image_dirty = true;
-                            }
-                            Err(e) => log::error!("Failed to open: {}", e),
-                        }
+                        self.open_path(path);
                     }
                 }
                 if ui.button("Save").clicked() {
                     if let Some(path) = rfd::FileDialog::new()
-                        .add_filter("Image", &["png", "jpg", "bmp"])
+                        .add_filter("ArsPaint Project", &["ars"])
+                        .add_filter("Flat Image", &["png", "jpg", "bmp"])
                         .save_file()
                     {
-                        if let Err(e) = self.state.image.save(&path) {
-                            log::error!("Failed to save: {}", e);
-                        }
+                        let image = self.state.image.clone();
+                        let tool = SavedTool {
+                            name: self.state.active_tool.name().to_string(),
+                            settings: self.state.tool_settings.clone(),
+                        };
+                        let state = self.state.command_stack.state();
+                        self.run_job("Saving", move || {
+                            let result = if ImageStore::is_project_path(&path) {
+                                image.save_project(&tool, &path)
+                            } else {
+                                image.save(&path)
+                            };
+                            Box::new(move |app: &mut ArsApp| match result {
+                                Ok(()) => app.state.command_stack.mark_saved(state),
+                                Err(e) => log::error!("Failed to save: {:#}", e),
+                            })
+                        });
                     }
                 }
+                if ui.button("Export...").clicked() {
+                    self.show_export = true;
+                }
+                let last_export = self.config.last_export_path.as_ref().map(|p| p.display());
+                if ui
+                    .add_enabled(
+                        self.config.last_export.is_some(),
+                        egui::Button::new("Export with Last Settings"),
+                    )
+                    .on_hover_text(match last_export {
+                        Some(path) => format!("Export again to {}", path),
+                        None => "Export once to remember the settings".to_string(),
+                    })
+                    .clicked()
+                {
+                    self.export_with_last_settings();
+                }
 
                 ui.separator();
 
                 if ui.button("Undo").clicked() {
                     self.state.command_stack.undo(&mut self.state.image);
-                    self.image_dirty = true;
                 }
                 if ui.button("Redo").clicked() {
                     self.state.command_stack.redo(&mut self.state.image);
-                    self.image_dirty = true;
+                }
+                if ui
+                    .button("Copy")
+                    .on_hover_text("Copy the selection, or the whole image, to the clipboard")
+                    .clicked()
+                {
+                    self.copy_to_clipboard();
+                }
+                if ui
+                    .button("Paste")
+                    .on_hover_text("Paste the clipboard image as a new layer")
+                    .clicked()
+                {
+                    self.paste_from_clipboard();
+                }
+
+                ui.separator();
+                if ui
+                    .button("Fit")
+                    .on_hover_text("Zoom so the whole image fits the window")
+                    .clicked()
+                {
+                    self.fit_view();
+                }
+                if ui.button("100%").clicked() {
+                    self.zoom = 1.0;
+                    self.pan = Vec2::ZERO;
+                }
+                let mut percent = self.zoom * 100.0;
+                let zoom_field = ui
+                    .add(
+                        egui::DragValue::new(&mut percent)
+                            .range(10.0..=5000.0)
+                            .max_decimals(0)
+                            .suffix("%"),
+                    )
+                    .on_hover_text("Zoom; drag or type a value");
+                if zoom_field.changed() {
+                    let zoom = (percent / 100.0).clamp(0.1, 50.0);
+                    // Scale the pan too, so the point at the middle of the
+                    // canvas stays put.
+                    self.pan *= zoom / self.zoom;
+                    self.zoom = zoom;
                 }
 
                 ui.separator();
@@ -682,15 +2770,39 @@ impl eframe::App for ArsApp {
                         self.state.image.height(),
                     ));
                 }
+                if ui.button("Vector").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::VectorShapeTool::new(
+                        self.state.image.width(),
+                        self.state.image.height(),
+                    ));
+                }
                 if ui.button("Select").clicked() {
                     self.state.active_tool = Box::new(crate::tools::RectSelectionTool::new());
                 }
+                if ui.button("Ellipse Select").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::EllipseSelectionTool::new());
+                }
                 if ui.button("Lasso").clicked() {
                     self.state.active_tool = Box::new(crate::tools::LassoSelectionTool::new());
                 }
+                if ui.button("Wand").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::MagicWandTool::new());
+                }
                 if ui.button("Transform").clicked() {
                     self.state.active_tool = Box::new(crate::tools::TransformTool::new());
                 }
+                if ui.button("Crop").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::CropTool::new());
+                }
+                if ui.button("Eyedropper").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::EyedropperTool::new());
+                }
+                if ui.button("Fill").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::FillTool::new());
+                }
+                if ui.button("Gradient").clicked() {
+                    self.state.active_tool = Box::new(crate::tools::GradientTool::new());
+                }
 
                 ui.label(format!("Active: {}", self.state.active_tool.name()));
 
@@ -698,37 +2810,65 @@ impl eframe::App for ArsApp {
                     .active_tool
                     .configure(ui, &mut self.state.tool_settings);
 
+                if self.state.active_tool.name() == "Brush" {
+                    if let Some((_, min_y, _, max_y)) = self.state.image.selection_bounds() {
+                        if ui
+                            .button("Fit to Selection")
+                            .on_hover_text("Set brush diameter to the selection height")
+                            .clicked()
+                        {
+                            let diameter = (max_y - min_y + 1) as f32;
+                            self.state.tool_settings.brush_size =
+                                (diameter / 2.0).clamp(1.0, 500.0);
+                        }
+                    }
+                }
+
                 ui.separator();
                 ui.label("Color:");
 
                 ui.vertical(|ui| {
                     ui.horizontal(|ui| {
-                        let mut p_color = [
-                            self.state.primary_color[0],
-                            self.state.primary_color[1],
-                            self.state.primary_color[2],
-                        ];
-                        if ui.color_edit_button_srgb(&mut p_color).changed() {
-                            self.state.primary_color =
-                                Rgba([p_color[0], p_color[1], p_color[2], 255]);
+                        // Alpha is kept so translucent colors can glaze.
+                        let mut p_color = self.state.primary_color.0;
+                        if ui
+                            .color_edit_button_srgba_unmultiplied(&mut p_color)
+                            .changed()
+                        {
+                            self.state.primary_color = Rgba(p_color);
                         }
                         ui.label("Primary");
+                        if ui
+                            .selectable_label(self.show_color_picker, "Picker")
+                            .on_hover_text("HSV sliders, a saturation/value square and hex entry")
+                            .clicked()
+                        {
+                            self.show_color_picker = !self.show_color_picker;
+                        }
 
-                        ui.separator();
+                        let swap = ui.small_button("↔").on_hover_text(format!(
+                            "Swap primary and secondary ({}); {} resets to black and white",
+                            self.state.keybindings.swap_colors.format(),
+                            self.state.keybindings.reset_colors.format(),
+                        ));
+                        if swap.clicked() {
+                            self.state.swap_colors();
+                        }
 
-                        let mut s_color = [
-                            self.state.secondary_color[0],
-                            self.state.secondary_color[1],
-                            self.state.secondary_color[2],
-                        ];
-                        if ui.color_edit_button_srgb(&mut s_color).changed() {
-                            self.state.secondary_color =
-                                Rgba([s_color[0], s_color[1], s_color[2], 255]);
+                        let mut s_color = self.state.secondary_color.0;
+                        if ui
+                            .color_edit_button_srgba_unmultiplied(&mut s_color)
+                            .changed()
+                        {
+                            self.state.secondary_color = Rgba(s_color);
                         }
                         ui.label("Secondary");
                     });
 
                     ui.horizontal_wrapped(|ui| {
+                        // Removing or moving a swatch would shift the ones
+                        // still to draw, so it waits for the loop to end.
+                        let mut edit: Option<PaletteEdit> = None;
                         for i in 0..self.state.palette.len() {
                             let p_color = self.state.palette[i];
                             let c32 = Color32::from_rgba_unmultiplied(
@@ -736,10 +2876,37 @@ impl eframe::App for ArsApp {
                             );
 
                             let (rect, response) =
-                                ui.allocate_at_least(Vec2::splat(18.0), Sense::click());
+                                ui.allocate_at_least(Vec2::splat(18.0), Sense::click_and_drag());
+                            if p_color[3] < 255 {
+                                // Backdrop so translucent swatches read as such.
+                                ui.painter().rect_filled(rect, 2.0, Color32::from_gray(200));
+                                ui.painter().rect_filled(
+                                    Rect::from_min_max(rect.min, rect.center()),
+                                    0.0,
+                                    Color32::from_gray(120),
+                                );
+                            }
                             ui.painter().rect_filled(rect, 2.0, c32);
+
+                            // Drag a swatch onto another to move it there.
+                            response.dnd_set_drag_payload(i);
+                            if response.dragged() {
+                                ui.ctx().set_cursor_icon(egui::CursorIcon::Grabbing);
+                            }
+                            if response.dnd_hover_payload::<usize>().is_some() {
+                                let stroke = ui.visuals().selection.stroke;
+                                ui.painter().rect_stroke(rect.expand(1.0), 2.0, stroke);
+                            }
+                            if let Some(from) = response.dnd_release_payload::<usize>() {
+                                edit = Some(PaletteEdit::Move(*from, i));
+                            }
+
                             if response.clicked() {
-                                self.state.primary_color = p_color;
+                                if ui.input(|i| i.modifiers.ctrl) {
+                                    edit = Some(PaletteEdit::Remove(i));
+                                } else {
+                                    self.state.primary_color = p_color;
+                                }
                             }
                             if response.secondary_clicked() {
                                 self.state.secondary_color = p_color;
@@ -747,6 +2914,48 @@ impl eframe::App for ArsApp {
                             if response.middle_clicked() {
                                 self.state.palette[i] = self.state.primary_color;
                             }
+                            response.context_menu(|ui| {
+                                if ui.button("Replace in Layer with Primary").clicked() {
+                                    if let Some(cmd) = self
+                                        .state
+                                        .image
+                                        .replace_color(p_color, self.state.primary_color)
+                                    {
+                                        self.state.command_stack.push(Box::new(cmd));
+                                    }
+                                    ui.close_menu();
+                                }
+                                ui.separator();
+                                let last = self.state.palette.len() - 1;
+                                if ui
+                                    .add_enabled(i > 0, egui::Button::new("Move Left"))
+                                    .clicked()
+                                {
+                                    edit = Some(PaletteEdit::Move(i, i - 1));
+                                    ui.close_menu();
+                                }
+                                if ui
+                                    .add_enabled(i < last, egui::Button::new("Move Right"))
+                                    .clicked()
+                                {
+                                    edit = Some(PaletteEdit::Move(i, i + 1));
+                                    ui.close_menu();
+                                }
+                                if ui.button("Remove (Ctrl+Click)").clicked() {
+                                    edit = Some(PaletteEdit::Remove(i));
+                                    ui.close_menu();
+                                }
+                            });
+                        }
+                        match edit {
+                            Some(PaletteEdit::Move(from, to)) => {
+                                let color = self.state.palette.remove(from);
+                                self.state.palette.insert(to, color);
+                            }
+                            Some(PaletteEdit::Remove(i)) => {
+                                self.state.palette.remove(i);
+                            }
+                            None => {}
                         }
                         if ui
                             .button("+")
@@ -755,13 +2964,135 @@ impl eframe::App for ArsApp {
                         {
                             self.state.palette.push(self.state.primary_color);
                         }
+                        if ui
+                            .button("Save...")
+                            .on_hover_text("Save the palette as JSON (keeps alpha) or GIMP .gpl")
+                            .clicked()
+                        {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("ArsPaint Palette", &["json"])
+                                .add_filter("GIMP Palette", &["gpl"])
+                                .save_file()
+                            {
+                                if let Err(e) = crate::palette::save(&path, &self.state.palette) {
+                                    log::error!("Failed to save palette: {:#}", e);
+                                }
+                            }
+                        }
+                        if ui
+                            .button("Load...")
+                            .on_hover_text(
+                                "Replace the palette from a JSON, GIMP .gpl or Adobe .ase file",
+                            )
+                            .clicked()
+                        {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("Palette", &["json", "gpl", "ase"])
+                                .pick_file()
+                            {
+                                match crate::palette::load(&path) {
+                                    Ok(colors) => self.state.palette = colors,
+                                    Err(e) => log::error!("Failed to load palette: {:#}", e),
+                                }
+                            }
+                        }
                     });
                 });
             });
         });
 
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(self.state.active_tool.name());
+                if let Some(status) = self.state.active_tool.status() {
+                    ui.separator();
+                    ui.label(status);
+                }
+                if self.state.image.active_layer_is_locked() {
+                    ui.separator();
+                    ui.colored_label(ui.visuals().warn_fg_color, "Layer is locked");
+                }
+                ui.separator();
+                let pixel = self.cursor_pixel.and_then(|(x, y)| {
+                    let color = *self.state.image.get_composite().get_pixel_checked(x, y)?;
+                    Some((x, y, color))
+                });
+                match pixel {
+                    Some((x, y, image::Rgba([r, g, b, a]))) => {
+                        ui.label(format!("{}, {}", x, y));
+                        ui.separator();
+                        ui.label(format!("RGBA {} {} {} {}", r, g, b, a));
+                    }
+                    None => {
+                        ui.label("—");
+                        ui.separator();
+                        ui.label("RGBA —");
+                    }
+                }
+                if let Some((min_x, min_y, max_x, max_y)) = self.state.image.selection_bounds() {
+                    ui.separator();
+                    ui.label(format!(
+                        "Selection {}×{}",
+                        max_x - min_x + 1,
+                        max_y - min_y + 1
+                    ));
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.label(format!("{:.0}%", self.zoom * 100.0));
+                    ui.separator();
+                    ui.label(format!(
+                        "Undo {}/{}",
+                        self.state.command_stack.undo_count(),
+                        self.state.preferences.undo_levels
+                    ))
+                    .on_hover_text(format!(
+                        "History holds about {} MB",
+                        self.state.command_stack.memory_estimate() / (1024 * 1024)
+                    ));
+                    ui.separator();
+                    ui.label(format!(
+                        "{}×{}",
+                        self.state.image.width(),
+                        self.state.image.height()
+                    ));
+                });
+            });
+        });
+
         egui::CentralPanel::default().show(ctx, |ui| {
             self.render_canvas(ui);
         });
+
+        self.render_busy_overlay(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_region_rounds_out_and_clamps() {
+        let rect = Rect::from_min_max(Pos2::new(-2.5, 1.5), Pos2::new(3.2, 9.0));
+        assert_eq!(pixel_region(rect, (8, 8)), Some((0, 1, 4, 7)));
+        let off = Rect::from_min_max(Pos2::new(9.0, 0.0), Pos2::new(12.0, 4.0));
+        assert_eq!(pixel_region(off, (8, 8)), None);
+    }
+
+    #[test]
+    fn half_red_over_transparent_uploads_premultiplied_in_gamma() {
+        let mut layer = Layer::new_raster(1, 1, String::new());
+        layer.data = crate::layers::LayerData::Raster(image::RgbaImage::from_pixel(
+            1,
+            1,
+            Rgba([255, 0, 0, 128]),
+        ));
+        let mut store = ImageStore::from_layers(1, 1, vec![layer], 0);
+
+        let composite = store.get_composite();
+        assert_eq!(*composite.get_pixel(0, 0), Rgba([255, 0, 0, 128]));
+        let texture = canvas_texture([1, 1], composite.as_raw());
+        // The texel egui uploads is premultiplied: #80000080.
+        assert_eq!(texture.pixels[0].to_array(), [128, 0, 0, 128]);
     }
 }